@@ -1,29 +1,237 @@
 #[macro_use]
 extern crate lazy_static;
 
-#[derive(Debug)]
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/*
+** 错误类别
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Code {
-    NotImplement(Option<String>),
-    SerdeError(Option<String>),
-    DeserdeError(Option<String>),
-    OpenFileError(Option<String>),
-    FileMetadataError(Option<String>),
-    FileSeekError(Option<String>),
-    FileWriteError(Option<String>),
-    FileReadError(Option<String>),
-    CreateDirError(Option<String>),
-    LimitError(Option<String>),
-    NewError(Option<String>),
-    PathToStrError(Option<String>),
-    FileTryCloneError(Option<String>)
+    NotImplement,
+    SerdeError,
+    DeserdeError,
+    OpenFileError,
+    FileMetadataError,
+    FileSeekError,
+    FileWriteError,
+    FileReadError,
+    CreateDirError,
+    LimitError,
+    NewError,
+    PathToStrError,
+    FileTryCloneError,
+    NotFoundError,
+    MismatchError,
+    ExistsError,
+    LockError,
+    DoubleFreeError,
+    ForeignPathError,
+    TaskError,
+    VersionError
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Code::NotImplement => "not implemented",
+            Code::SerdeError => "serialize error",
+            Code::DeserdeError => "deserialize error",
+            Code::OpenFileError => "open file error",
+            Code::FileMetadataError => "file metadata error",
+            Code::FileSeekError => "file seek error",
+            Code::FileWriteError => "file write error",
+            Code::FileReadError => "file read error",
+            Code::CreateDirError => "create dir error",
+            Code::LimitError => "limit exceeded",
+            Code::NewError => "new error",
+            Code::PathToStrError => "path to str error",
+            Code::FileTryCloneError => "file try clone error",
+            Code::NotFoundError => "not found",
+            Code::MismatchError => "mismatch",
+            Code::ExistsError => "already exists",
+            Code::LockError => "lock error",
+            Code::DoubleFreeError => "double free",
+            Code::ForeignPathError => "position belongs to another file",
+            Code::TaskError => "background task error",
+            Code::VersionError => "unsupported format version"
+        };
+        f.write_str(s)
+    }
 }
 
+/*
+** 错误
+**  code: 错误类别
+**  message: 附加说明
+**  path / offset: 出错的文件以及文件中的位置
+**  source: 底层错误 (io / bincode), 通过 std::error::Error::source 取得
+*/
 #[derive(Debug)]
 pub struct Error {
-    pub code: Option<Code>
+    code: Code,
+    message: Option<String>,
+    path: Option<PathBuf>,
+    offset: Option<u64>,
+    source: Option<Box<dyn error::Error + Send + Sync + 'static>>
+}
+
+impl Error {
+    pub fn new(code: Code) -> Error {
+        Error{
+            code,
+            message: None,
+            path: None,
+            offset: None,
+            source: None
+        }
+    }
+
+    /*
+    ** 以 io 错误作为 source 创建
+    */
+    pub fn io(code: Code, err: io::Error) -> Error {
+        Error::new(code).with_source(err)
+    }
+
+    /*
+    ** 以 bincode 错误作为 source 创建
+    */
+    pub fn bincode(code: Code, err: bincode::Error) -> Error {
+        Error::new(code).with_source(err)
+    }
+
+    pub fn with_message<S: Into<String>>(mut self, message: S) -> Error {
+        self.message = Some(message.into());
+        self
+    }
+
+    pub fn with_path<P: AsRef<Path>>(mut self, path: P) -> Error {
+        self.path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn with_offset(mut self, offset: u64) -> Error {
+        self.offset = Some(offset);
+        self
+    }
+
+    pub fn with_source<E: Into<Box<dyn error::Error + Send + Sync + 'static>>>(mut self, source: E) -> Error {
+        self.source = Some(source.into());
+        self
+    }
+}
+
+impl Error {
+    pub fn code(&self) -> Code {
+        self.code
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn offset(&self) -> Option<u64> {
+        self.offset
+    }
+
+    /*
+    ** 底层为 io 错误时, 返回其 ErrorKind
+    */
+    pub fn io_kind(&self) -> Option<io::ErrorKind> {
+        match &self.source {
+            Some(source) => source.downcast_ref::<io::Error>().map(|err| err.kind()),
+            None => None
+        }
+    }
+
+    /*
+    ** 文件 / 目录 / 块不存在
+    */
+    pub fn is_not_found(&self) -> bool {
+        self.code == Code::NotFoundError || self.io_kind() == Some(io::ErrorKind::NotFound)
+    }
+
+    /*
+    ** 磁盘上的内容无法解析 (文件损坏或格式不匹配)
+    */
+    pub fn is_corruption(&self) -> bool {
+        self.code == Code::DeserdeError
+    }
+
+    pub fn is_limit(&self) -> bool {
+        self.code == Code::LimitError
+    }
+
+    /*
+    ** 释放已经空闲的块, 或者释放其他文件中的位置
+    */
+    pub fn is_invalid_free(&self) -> bool {
+        self.code == Code::DoubleFreeError || self.code == Code::ForeignPathError
+    }
 }
 
-type Result<T> = std::result::Result<T, Error>;
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.code)?;
+        if let Some(message) = &self.message {
+            write!(f, ": {}", message)?;
+        }
+        match (&self.path, self.offset) {
+            (Some(path), Some(offset)) => write!(f, " (path: {}, offset: {})", path.display(), offset),
+            (Some(path), None) => write!(f, " (path: {})", path.display()),
+            (None, Some(offset)) => write!(f, " (offset: {})", offset),
+            (None, None) => Ok(())
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match &self.source {
+            Some(source) => Some(source.as_ref()),
+            None => None
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
 
 pub mod multifile;
 pub mod singlefile;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::error::Error as StdError;
+
+    #[test]
+    fn error_context_test() {
+        let err = Error::io(Code::FileSeekError, io::Error::new(io::ErrorKind::NotFound, "gone"))
+            .with_path("run_test/user_index")
+            .with_offset(128);
+        assert_eq!(err.code(), Code::FileSeekError);
+        assert!(err.is_not_found());
+        assert!(!err.is_corruption());
+        assert_eq!(err.offset(), Some(128));
+        assert_eq!(err.to_string(), "file seek error (path: run_test/user_index, offset: 128)");
+        assert_eq!(err.source().unwrap().to_string(), "gone");
+    }
+
+    #[test]
+    fn error_corruption_test() {
+        let bincode_err = bincode::deserialize::<u64>(&[1, 2]).unwrap_err();
+        let err = Error::bincode(Code::DeserdeError, bincode_err).with_message("block header");
+        assert!(err.is_corruption());
+        assert!(!err.is_not_found());
+        assert_eq!(err.io_kind(), None);
+        assert!(err.source().is_some());
+    }
+}
@@ -0,0 +1,224 @@
+/*
+** Fixed / Block 的异步接口 (feature = "async")
+**  文件读写在 tokio 的阻塞线程池中执行, 不阻塞异步执行器
+**  数据文件使用按位置读写 (pread / pwrite), 多个任务共享同一个文件时不需要 seek
+**  AsyncFixed 与 MultiFile::open_fixed 返回的 FixedHandle 共享同一个 Fixed
+*/
+use crate::{Result, Error, Code};
+use super::fixed::{Fixed, Block, BlockId};
+use super::handle::FixedHandle;
+
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/*
+** 在阻塞线程池中执行 f
+*/
+async fn blocking<T, F>(f: F) -> Result<T>
+    where T: Send + 'static, F: FnOnce() -> Result<T> + Send + 'static {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(err) => Err(Error::new(Code::TaskError).with_source(err))
+    }
+}
+
+#[derive(Clone)]
+pub struct AsyncFixed {
+    handle: FixedHandle
+}
+
+impl AsyncFixed {
+    pub async fn new<P: AsRef<Path>>(name: &str, fixed_size: usize, path: P) -> Result<AsyncFixed> {
+        let name = name.to_string();
+        let path = path.as_ref().to_path_buf();
+        let fixed = blocking(move || Fixed::new(&name, fixed_size, path)).await?;
+        Ok(AsyncFixed::from_handle(FixedHandle::new(fixed)))
+    }
+
+    pub async fn open<P: AsRef<Path>>(name: &str, path: P) -> Result<AsyncFixed> {
+        let name = name.to_string();
+        let path = path.as_ref().to_path_buf();
+        let fixed = blocking(move || Fixed::open(&name, path)).await?;
+        Ok(AsyncFixed::from_handle(FixedHandle::new(fixed)))
+    }
+
+    pub fn from_handle(handle: FixedHandle) -> AsyncFixed {
+        AsyncFixed{
+            handle
+        }
+    }
+
+    pub fn handle(&self) -> &FixedHandle {
+        &self.handle
+    }
+
+    pub async fn new_block(&self) -> Result<AsyncBlock> {
+        let handle = self.handle.clone();
+        let block = blocking(move || handle.lock()?.new_block()).await?;
+        Ok(AsyncBlock::new(block))
+    }
+
+    pub async fn new_blocks(&self, n: usize) -> Result<Vec<AsyncBlock>> {
+        let handle = self.handle.clone();
+        let blocks = blocking(move || handle.lock()?.new_blocks(n)).await?;
+        Ok(blocks.into_iter().map(AsyncBlock::new).collect())
+    }
+
+    pub async fn block(&self, id: BlockId) -> Result<AsyncBlock> {
+        let handle = self.handle.clone();
+        let block = blocking(move || handle.lock()?.block(id)).await?;
+        Ok(AsyncBlock::new(block))
+    }
+
+    pub async fn free_block(&self, id: BlockId) -> Result<()> {
+        let handle = self.handle.clone();
+        blocking(move || handle.lock()?.free_block(id)).await
+    }
+
+    pub async fn write_payload<T: serde::Serialize + Send + 'static>(&self, id: BlockId, value: T) -> Result<()> {
+        let handle = self.handle.clone();
+        blocking(move || handle.lock()?.write_payload(id, &value)).await
+    }
+
+    pub async fn read_payload<T: serde::de::DeserializeOwned + Send + 'static>(&self, id: BlockId) -> Result<T> {
+        let handle = self.handle.clone();
+        blocking(move || handle.lock()?.read_payload(id)).await
+    }
+
+    pub async fn block_count(&self) -> Result<u64> {
+        let handle = self.handle.clone();
+        blocking(move || handle.lock()?.block_count()).await
+    }
+
+    pub async fn sync(&self) -> Result<()> {
+        let handle = self.handle.clone();
+        blocking(move || handle.lock()?.sync()).await
+    }
+}
+
+/*
+** 块的异步接口, clone 之后指向同一个块
+*/
+#[derive(Clone)]
+pub struct AsyncBlock {
+    id: BlockId,
+    path: String,
+    inner: Arc<Mutex<Block>>
+}
+
+impl AsyncBlock {
+    fn new(block: Block) -> AsyncBlock {
+        AsyncBlock{
+            id: block.id(),
+            path: block.path().to_string(),
+            inner: Arc::new(Mutex::new(block))
+        }
+    }
+
+    pub fn id(&self) -> BlockId {
+        self.id
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub async fn update_header<Header: serde::Serialize + Send + 'static>(&self, header: Header) -> Result<()> {
+        let inner = self.inner.clone();
+        blocking(move || lock(&inner)?.update_header(header)).await
+    }
+
+    pub async fn header<Header: serde::de::DeserializeOwned + Send + 'static>(&self) -> Result<Header> {
+        let inner = self.inner.clone();
+        blocking(move || lock(&inner)?.header()).await
+    }
+
+    /*
+    ** 将 value 序列化到块的内容区域 (业务header之后)
+    */
+    pub async fn write_payload<T: serde::Serialize + Send + 'static>(&self, value: T) -> Result<()> {
+        let inner = self.inner.clone();
+        let path = self.path.clone();
+        blocking(move || {
+            let content = bincode::serialize(&value).map_err(|err| Error::bincode(Code::SerdeError, err))?;
+            let mut cursor = lock(&inner)?.cursor()?;
+            if content.len() as u64 > cursor.capacity() {
+                return Err(Error::new(Code::LimitError)
+                    .with_message(format!("payload size {} exceeds block capacity {}", content.len(), cursor.capacity()))
+                    .with_path(&path));
+            }
+            cursor.write_all(&content).map_err(|err| Error::io(Code::FileWriteError, err).with_path(&path))
+        }).await
+    }
+
+    /*
+    ** 从块的内容区域反序列化 write_payload 写入的内容
+    */
+    pub async fn read_payload<T: serde::de::DeserializeOwned + Send + 'static>(&self) -> Result<T> {
+        let inner = self.inner.clone();
+        let path = self.path.clone();
+        blocking(move || {
+            let mut cursor = lock(&inner)?.cursor()?;
+            bincode::deserialize_from(&mut cursor).map_err(|err| Error::bincode(Code::DeserdeError, err).with_path(&path))
+        }).await
+    }
+}
+
+fn lock(inner: &Mutex<Block>) -> Result<MutexGuard<'_, Block>> {
+    inner.lock().map_err(|_| {
+        Error::new(Code::LockError).with_message("block is poisoned")
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::multifile::run_test::TestDir;
+
+    #[derive(serde_derive::Serialize, serde_derive::Deserialize, Debug, PartialEq)]
+    struct User {
+        id: u64,
+        name: String
+    }
+
+    #[test]
+    fn async_fixed_test() {
+        let dir = TestDir::new("async_fixed_test");
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        runtime.block_on(async {
+            let fixed = AsyncFixed::new("user", 64, &dir).await.unwrap();
+            let block = fixed.new_block().await.unwrap();
+            block.update_header(7u32).await.unwrap();
+            block.write_payload(User{ id: 1, name: "a".to_string() }).await.unwrap();
+            assert_eq!(block.header::<u32>().await.unwrap(), 7);
+            /*
+            ** 多个任务并发创建块
+            */
+            let tasks: Vec<_> = (0..8).map(|i| {
+                let fixed = fixed.clone();
+                tokio::spawn(async move {
+                    let block = fixed.new_block().await.unwrap();
+                    fixed.write_payload(block.id(), i as u64).await.unwrap();
+                    block.id()
+                })
+            }).collect();
+            let mut ids: Vec<BlockId> = Vec::new();
+            for task in tasks {
+                ids.push(task.await.unwrap());
+            }
+            ids.sort();
+            assert_eq!(ids, (1..9).collect::<Vec<BlockId>>());
+            fixed.sync().await.unwrap();
+        });
+        runtime.block_on(async {
+            let fixed = AsyncFixed::open("user", &dir).await.unwrap();
+            assert_eq!(fixed.block_count().await.unwrap(), 9);
+            let block = fixed.block(0).await.unwrap();
+            assert_eq!(block.read_payload::<User>().await.unwrap(), User{ id: 1, name: "a".to_string() });
+            fixed.free_block(0).await.unwrap();
+            assert!(fixed.free_block(0).await.err().unwrap().is_invalid_free());
+            assert!(fixed.block(9).await.err().unwrap().is_not_found());
+        });
+    }
+}
@@ -0,0 +1,663 @@
+/*
+** 保存在 Fixed 块中的 B+ 树
+**  每个节点占用一个块 (节点大小 = fixed_size), 节点内容通过 bincode 写入块内容区域
+**  第 0 个块保存树的元信息 (根节点), 叶子节点之间通过 prev / next 串联, 用于范围扫描
+**  删除时不做节点合并, 节点为空时释放到 stack::Delete, 根节点只有一个子节点时降低树高
+*/
+use crate::{Result, Error, Code};
+use super::fixed::{Fixed, BlockId};
+use super::handle::FixedHandle;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_derive::{Serialize, Deserialize};
+
+use std::collections::VecDeque;
+use std::io::Write;
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+
+const META_ID: BlockId = 0;
+const NO_LEAF: BlockId = BlockId::MAX;
+
+#[derive(Serialize, Deserialize)]
+struct Meta {
+    root: BlockId
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(bound(serialize = "K: Serialize", deserialize = "K: DeserializeOwned"))]
+enum Node<K> {
+    Leaf {
+        keys: Vec<K>,
+        values: Vec<BlockId>,
+        prev: BlockId,
+        next: BlockId
+    },
+    Internal {
+        keys: Vec<K>,
+        children: Vec<BlockId>
+    }
+}
+
+impl<K> Node<K> {
+    fn empty_leaf() -> Node<K> {
+        Node::Leaf{
+            keys: Vec::new(),
+            values: Vec::new(),
+            prev: NO_LEAF,
+            next: NO_LEAF
+        }
+    }
+}
+
+/*
+** 节点读写
+*/
+fn encode<K: Serialize>(fixed: &Fixed, id: BlockId, node: &Node<K>) -> Result<Vec<u8>> {
+    bincode::serialize(node).map_err(|err| {
+        Error::bincode(Code::SerdeError, err).with_message(format!("btree node {} in {}", id, fixed.name()))
+    })
+}
+
+fn fits<K: Serialize>(fixed: &Fixed, node: &Node<K>) -> Result<bool> {
+    Ok(encode(fixed, 0, node)?.len() <= fixed.fixed_size())
+}
+
+fn read_node<K: DeserializeOwned>(fixed: &Fixed, id: BlockId) -> Result<Node<K>> {
+    let mut cursor = fixed.block(id)?.cursor()?;
+    bincode::deserialize_from(&mut cursor).map_err(|err| {
+        Error::bincode(Code::DeserdeError, err).with_message(format!("btree node {} in {}", id, fixed.name()))
+    })
+}
+
+fn write_node<K: Serialize>(fixed: &Fixed, id: BlockId, node: &Node<K>) -> Result<()> {
+    let content = encode(fixed, id, node)?;
+    let mut cursor = fixed.block(id)?.cursor()?;
+    cursor.write_all(&content).map_err(|err| {
+        Error::io(Code::FileWriteError, err).with_message(format!("btree node {} in {}", id, fixed.name()))
+    })
+}
+
+/*
+** 分裂时每个键占用的字节数 (键 + 值 / 子节点)
+*/
+fn entry_sizes<K: Serialize>(keys: &[K]) -> Result<Vec<u64>> {
+    keys.iter().map(|key| match bincode::serialized_size(key) {
+        Ok(size) => Ok(size + std::mem::size_of::<BlockId>() as u64),
+        Err(err) => Err(Error::bincode(Code::SerdeError, err))
+    }).collect()
+}
+
+/*
+** 在 [lo, hi] 中选择分裂位置 i, 使 sizes[..i] 与 sizes[i..] 的字节数最接近
+*/
+fn byte_midpoint(sizes: &[u64], lo: usize, hi: usize) -> usize {
+    let total: u64 = sizes.iter().sum();
+    let mut best = lo;
+    let mut best_diff = u64::MAX;
+    let mut left: u64 = sizes[..lo].iter().sum();
+    for (i, size) in sizes.iter().enumerate().take(hi + 1).skip(lo) {
+        let diff = left.abs_diff(total - left);
+        if diff < best_diff {
+            best = i;
+            best_diff = diff;
+        }
+        left += size;
+    }
+    best
+}
+
+/*
+** 分裂出的两个节点都必须能放入一个块, 在写入任何节点之前检查
+*/
+fn check_split<K: Serialize>(fixed: &Fixed, id: BlockId, left: &Node<K>, right: &Node<K>) -> Result<()> {
+    if fits(fixed, left)? && fits(fixed, right)? {
+        return Ok(());
+    }
+    Err(Error::new(Code::LimitError)
+        .with_message(format!("btree node {} in {} can not be split into two nodes of {} bytes", id, fixed.name(), fixed.fixed_size())))
+}
+
+fn alloc_node<K: Serialize>(fixed: &mut Fixed, node: &Node<K>) -> Result<BlockId> {
+    let id = fixed.new_block()?.id();
+    write_node(fixed, id, node)?;
+    Ok(id)
+}
+
+/*
+** 修改相邻叶子节点的 prev / next
+*/
+fn relink<K: Serialize + DeserializeOwned>(fixed: &Fixed, id: BlockId, prev: Option<BlockId>, next: Option<BlockId>) -> Result<()> {
+    if id == NO_LEAF {
+        return Ok(());
+    }
+    let mut node: Node<K> = read_node(fixed, id)?;
+    if let Node::Leaf{ prev: p, next: n, .. } = &mut node {
+        if let Some(prev) = prev {
+            *p = prev;
+        }
+        if let Some(next) = next {
+            *n = next;
+        }
+    }
+    write_node(fixed, id, &node)
+}
+
+fn root(fixed: &Fixed) -> Result<BlockId> {
+    let meta: Meta = fixed.block(META_ID)?.header()?;
+    Ok(meta.root)
+}
+
+/*
+** 分裂之后父节点更新失败 => 释放新的右节点, 右节点为叶子时把它从链表中摘除
+*/
+fn discard_split<K: Serialize + DeserializeOwned>(fixed: &mut Fixed, left: BlockId, right: BlockId) -> Result<()> {
+    if let Node::Leaf{ next, .. } = read_node::<K>(fixed, right)? {
+        relink::<K>(fixed, left, None, Some(next))?;
+        relink::<K>(fixed, next, Some(left), None)?;
+    }
+    fixed.free_block(right)
+}
+
+pub struct BTree<K> {
+    fixed: FixedHandle,
+    marker: PhantomData<fn() -> K>
+}
+
+impl<K: Serialize + DeserializeOwned + Ord + Clone> BTree<K> {
+    /*
+    ** 打开树, Fixed 为空时创建元信息块以及空的根节点
+    */
+    pub fn open(fixed: FixedHandle) -> Result<BTree<K>> {
+        {
+            let mut f = fixed.lock()?;
+            if f.block_count()? == 0 {
+                let mut meta = f.new_block()?;
+                let root = alloc_node(&mut f, &Node::<K>::empty_leaf())?;
+                meta.update_header(Meta{ root })?;
+            }
+        }
+        Ok(BTree{
+            fixed,
+            marker: PhantomData
+        })
+    }
+
+    /*
+    ** 插入 / 覆盖, 返回原来的值
+    */
+    pub fn insert(&self, key: K, value: BlockId) -> Result<Option<BlockId>> {
+        let mut fixed = self.fixed.lock()?;
+        /*
+        ** 键过大时直接拒绝; 分裂时按字节数对半分, 写入之前仍然检查两半都能放下
+        */
+        let probe: Node<K> = Node::Internal{
+            keys: vec![key.clone(), key.clone(), key.clone()],
+            children: vec![0; 4]
+        };
+        if !fits(&fixed, &probe)? {
+            return Err(Error::new(Code::LimitError)
+                .with_message(format!("key is too large for btree node of {} bytes", fixed.fixed_size())));
+        }
+        let root = self.root(&fixed)?;
+        let mut old = None;
+        if let Some((sep, right)) = self.insert_at(&mut fixed, root, key, value, &mut old)? {
+            let grown = alloc_node(&mut fixed, &Node::Internal{
+                keys: vec![sep],
+                children: vec![root, right]
+            }).and_then(|new_root| self.set_root(&fixed, new_root));
+            if let Err(err) = grown {
+                let _ = discard_split::<K>(&mut fixed, root, right);
+                return Err(err);
+            }
+        }
+        Ok(old)
+    }
+
+    pub fn get(&self, key: &K) -> Result<Option<BlockId>> {
+        let fixed = self.fixed.lock()?;
+        let mut id = self.root(&fixed)?;
+        loop {
+            match read_node::<K>(&fixed, id)? {
+                Node::Leaf{ keys, values, .. } => {
+                    return Ok(keys.binary_search(key).ok().map(|i| values[i]));
+                },
+                Node::Internal{ keys, children } => {
+                    id = children[keys.partition_point(|k| k <= key)];
+                }
+            }
+        }
+    }
+
+    /*
+    ** 删除, 返回被删除的值
+    */
+    pub fn remove(&self, key: &K) -> Result<Option<BlockId>> {
+        let mut fixed = self.fixed.lock()?;
+        let root = self.root(&fixed)?;
+        let (old, _) = self.remove_at(&mut fixed, root, key, true)?;
+        /*
+        ** 根节点只剩一个子节点 => 子节点成为新的根节点
+        */
+        loop {
+            let root = self.root(&fixed)?;
+            match read_node::<K>(&fixed, root)? {
+                Node::Internal{ children, .. } if children.len() == 1 => {
+                    self.set_root(&fixed, children[0])?;
+                    fixed.free_block(root)?;
+                },
+                Node::Internal{ children, .. } if children.is_empty() => {
+                    write_node(&fixed, root, &Node::<K>::empty_leaf())?;
+                },
+                _ => break
+            }
+        }
+        Ok(old)
+    }
+
+    /*
+    ** 按键的顺序扫描范围内的所有键值
+    **  每次在锁中从根节点重新查找上一次返回的键之后的叶子节点, 扫描期间的插入 / 删除 / 分裂不会让迭代器读到被释放的节点
+    */
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Result<Range<K>> {
+        Ok(Range{
+            fixed: self.fixed.clone(),
+            buffer: VecDeque::new(),
+            start: clone_bound(range.start_bound()),
+            end: clone_bound(range.end_bound()),
+            done: false
+        })
+    }
+}
+
+impl<K: Serialize + DeserializeOwned + Ord + Clone> BTree<K> {
+    fn root(&self, fixed: &Fixed) -> Result<BlockId> {
+        root(fixed)
+    }
+
+    fn set_root(&self, fixed: &Fixed, root: BlockId) -> Result<()> {
+        fixed.block(META_ID)?.update_header(Meta{ root })
+    }
+
+    /*
+    ** 插入到以 id 为根的子树, 节点分裂时返回 (分隔键, 新的右节点)
+    */
+    fn insert_at(&self, fixed: &mut Fixed, id: BlockId, key: K, value: BlockId, old: &mut Option<BlockId>) -> Result<Option<(K, BlockId)>> {
+        match read_node::<K>(fixed, id)? {
+            Node::Leaf{ mut keys, mut values, prev, next } => {
+                match keys.binary_search(&key) {
+                    Ok(i) => {
+                        *old = Some(values[i]);
+                        values[i] = value;
+                    },
+                    Err(i) => {
+                        keys.insert(i, key);
+                        values.insert(i, value);
+                    }
+                }
+                let node = Node::Leaf{ keys, values, prev, next };
+                if fits(fixed, &node)? {
+                    write_node(fixed, id, &node)?;
+                    return Ok(None);
+                }
+                /*
+                ** 叶子节点分裂, 右半部分放到新的节点
+                */
+                let (mut keys, mut values) = match node {
+                    Node::Leaf{ keys, values, .. } => (keys, values),
+                    Node::Internal{ .. } => unreachable!()
+                };
+                if keys.len() < 2 {
+                    return Err(Error::new(Code::LimitError)
+                        .with_message(format!("btree node {} in {} can not hold a single key", id, fixed.name())));
+                }
+                let mid = byte_midpoint(&entry_sizes(&keys)?, 1, keys.len() - 1);
+                let right_keys = keys.split_off(mid);
+                let right_values = values.split_off(mid);
+                let sep = right_keys[0].clone();
+                let right_node = Node::Leaf{
+                    keys: right_keys,
+                    values: right_values,
+                    prev: id,
+                    next
+                };
+                /*
+                ** next 的大小固定, 用 NO_LEAF 代替新节点的序号检查
+                */
+                let mut left_node = Node::Leaf{ keys, values, prev, next: NO_LEAF };
+                check_split(fixed, id, &left_node, &right_node)?;
+                let right = alloc_node(fixed, &right_node)?;
+                if let Node::Leaf{ next, .. } = &mut left_node {
+                    *next = right;
+                }
+                let linked = relink::<K>(fixed, next, Some(right), None).and_then(|_| write_node(fixed, id, &left_node));
+                if let Err(err) = linked {
+                    let _ = relink::<K>(fixed, next, Some(id), None);
+                    let _ = fixed.free_block(right);
+                    return Err(err);
+                }
+                Ok(Some((sep, right)))
+            },
+            Node::Internal{ mut keys, mut children } => {
+                let i = keys.partition_point(|k| k <= &key);
+                let split = self.insert_at(fixed, children[i], key, value, old)?;
+                let (sep, right) = match split {
+                    Some(s) => s,
+                    None => {
+                        return Ok(None);
+                    }
+                };
+                let child = children[i];
+                keys.insert(i, sep);
+                children.insert(i + 1, right);
+                let split = self.update_internal(fixed, id, keys, children);
+                if split.is_err() {
+                    let _ = discard_split::<K>(fixed, child, right);
+                }
+                split
+            }
+        }
+    }
+
+    /*
+    ** 写入插入了子节点的内部节点, 放不下时分裂, 中间的键上移到父节点
+    */
+    fn update_internal(&self, fixed: &mut Fixed, id: BlockId, keys: Vec<K>, children: Vec<BlockId>) -> Result<Option<(K, BlockId)>> {
+        let node = Node::Internal{ keys, children };
+        if fits(fixed, &node)? {
+            write_node(fixed, id, &node)?;
+            return Ok(None);
+        }
+        let (mut keys, mut children) = match node {
+            Node::Internal{ keys, children } => (keys, children),
+            Node::Leaf{ .. } => unreachable!()
+        };
+        let lo = if keys.len() >= 3 { 1 } else { 0 };
+        let mid = byte_midpoint(&entry_sizes(&keys)?, lo, keys.len() - 1 - lo);
+        let right_keys = keys.split_off(mid + 1);
+        let sep = match keys.pop() {
+            Some(k) => k,
+            None => unreachable!()
+        };
+        let right_children = children.split_off(mid + 1);
+        let right_node = Node::Internal{
+            keys: right_keys,
+            children: right_children
+        };
+        let left_node = Node::Internal{ keys, children };
+        check_split(fixed, id, &left_node, &right_node)?;
+        let right = alloc_node(fixed, &right_node)?;
+        if let Err(err) = write_node(fixed, id, &left_node) {
+            let _ = fixed.free_block(right);
+            return Err(err);
+        }
+        Ok(Some((sep, right)))
+    }
+
+    /*
+    ** 从以 id 为根的子树中删除, 返回 (被删除的值, 节点是否已经被释放)
+    */
+    fn remove_at(&self, fixed: &mut Fixed, id: BlockId, key: &K, is_root: bool) -> Result<(Option<BlockId>, bool)> {
+        match read_node::<K>(fixed, id)? {
+            Node::Leaf{ mut keys, mut values, prev, next } => {
+                let i = match keys.binary_search(key) {
+                    Ok(i) => i,
+                    Err(_) => {
+                        return Ok((None, false));
+                    }
+                };
+                keys.remove(i);
+                let old = values.remove(i);
+                if keys.is_empty() && !is_root {
+                    /*
+                    ** 叶子节点为空 => 从链表中摘除并释放
+                    */
+                    relink::<K>(fixed, prev, None, Some(next))?;
+                    relink::<K>(fixed, next, Some(prev), None)?;
+                    fixed.free_block(id)?;
+                    return Ok((Some(old), true));
+                }
+                write_node(fixed, id, &Node::Leaf{ keys, values, prev, next })?;
+                Ok((Some(old), false))
+            },
+            Node::Internal{ mut keys, mut children } => {
+                let i = keys.partition_point(|k| k <= key);
+                let (old, freed) = self.remove_at(fixed, children[i], key, false)?;
+                if !freed {
+                    return Ok((old, false));
+                }
+                children.remove(i);
+                if !keys.is_empty() {
+                    keys.remove(i.saturating_sub(1));
+                }
+                if children.is_empty() && !is_root {
+                    fixed.free_block(id)?;
+                    return Ok((old, true));
+                }
+                write_node(fixed, id, &Node::Internal{ keys, children })?;
+                Ok((old, false))
+            }
+        }
+    }
+}
+
+fn clone_bound<K: Clone>(bound: Bound<&K>) -> Bound<K> {
+    match bound {
+        Bound::Included(k) => Bound::Included(k.clone()),
+        Bound::Excluded(k) => Bound::Excluded(k.clone()),
+        Bound::Unbounded => Bound::Unbounded
+    }
+}
+
+/*
+** 范围扫描的迭代器, 每次读取一个叶子节点
+**  start 在每次读取之后前移到已经读取的最后一个键
+*/
+pub struct Range<K> {
+    fixed: FixedHandle,
+    buffer: VecDeque<(K, BlockId)>,
+    start: Bound<K>,
+    end: Bound<K>,
+    done: bool
+}
+
+impl<K: Serialize + DeserializeOwned + Ord + Clone> Range<K> {
+    /*
+    ** 在锁中从根节点找到 start 所在的叶子节点, 读取 start 之后的键
+    **  叶子节点中没有 start 之后的键时沿着 next 继续, 返回 false 表示已经没有更多的键
+    */
+    fn load_leaf(&mut self) -> Result<bool> {
+        let fixed = self.fixed.lock()?;
+        let mut id = root(&fixed)?;
+        loop {
+            match read_node::<K>(&fixed, id)? {
+                Node::Leaf{ .. } => break,
+                Node::Internal{ keys, children } => {
+                    let i = match &self.start {
+                        Bound::Included(k) | Bound::Excluded(k) => keys.partition_point(|x| x <= k),
+                        Bound::Unbounded => 0
+                    };
+                    id = children[i];
+                }
+            }
+        }
+        while id != NO_LEAF {
+            match read_node::<K>(&fixed, id)? {
+                Node::Leaf{ keys, values, next, .. } => {
+                    for (key, value) in keys.into_iter().zip(values) {
+                        let after_start = match &self.start {
+                            Bound::Included(s) => &key >= s,
+                            Bound::Excluded(s) => &key > s,
+                            Bound::Unbounded => true
+                        };
+                        if after_start {
+                            self.buffer.push_back((key, value));
+                        }
+                    }
+                    if let Some((key, _)) = self.buffer.back() {
+                        self.start = Bound::Excluded(key.clone());
+                        return Ok(true);
+                    }
+                    id = next;
+                },
+                Node::Internal{ .. } => {
+                    return Err(Error::new(Code::DeserdeError)
+                        .with_message(format!("btree node {} in {} is not a leaf", id, fixed.name())));
+                }
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl<K: Serialize + DeserializeOwned + Ord + Clone> Iterator for Range<K> {
+    type Item = Result<(K, BlockId)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buffer.is_empty() {
+            if self.done {
+                return None;
+            }
+            match self.load_leaf() {
+                Ok(true) => {},
+                Ok(false) => {
+                    self.done = true;
+                    return None;
+                },
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+        let (key, value) = self.buffer.pop_front()?;
+        let before_end = match &self.end {
+            Bound::Included(e) => &key <= e,
+            Bound::Excluded(e) => &key < e,
+            Bound::Unbounded => true
+        };
+        if !before_end {
+            self.done = true;
+            self.buffer.clear();
+            return None;
+        }
+        Some(Ok((key, value)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::multifile::run_test::TestDir;
+
+    fn open(name: &str) -> (BTree<u32>, FixedHandle, TestDir) {
+        let dir = TestDir::new(name);
+        let handle = FixedHandle::new(Fixed::new("index", 64, &dir).unwrap());
+        (BTree::open(handle.clone()).unwrap(), handle, dir)
+    }
+
+    #[test]
+    fn btree_insert_get_range_test() {
+        let (tree, handle, _dir) = open("btree_insert_get_range_test");
+        for i in 0..500u32 {
+            let key = (i * 7919) % 500;
+            assert_eq!(tree.insert(key, key as u64 + 1000).unwrap(), None);
+        }
+        assert_eq!(tree.insert(42, 1).unwrap(), Some(1042));
+        assert_eq!(tree.get(&42).unwrap(), Some(1));
+        assert_eq!(tree.get(&499).unwrap(), Some(1499));
+        assert_eq!(tree.get(&500).unwrap(), None);
+        let keys: Vec<u32> = tree.range(100..110).unwrap().map(|r| r.unwrap().0).collect();
+        assert_eq!(keys, (100..110).collect::<Vec<u32>>());
+        assert_eq!(tree.range(..).unwrap().count(), 500);
+        assert_eq!(tree.range((Bound::Excluded(497), Bound::Unbounded)).unwrap().count(), 2);
+        /*
+        ** 重新打开之后数据仍然存在
+        */
+        drop(tree);
+        let tree: BTree<u32> = BTree::open(handle).unwrap();
+        assert_eq!(tree.get(&250).unwrap(), Some(1250));
+    }
+
+    #[test]
+    fn btree_remove_test() {
+        let (tree, handle, _dir) = open("btree_remove_test");
+        for i in 0..300u32 {
+            tree.insert(i, i as u64).unwrap();
+        }
+        let block_count = handle.lock().unwrap().block_count().unwrap();
+        for i in 0..300u32 {
+            if i % 3 != 0 {
+                assert_eq!(tree.remove(&i).unwrap(), Some(i as u64));
+            }
+        }
+        assert_eq!(tree.remove(&1).unwrap(), None);
+        let keys: Vec<u32> = tree.range(..).unwrap().map(|r| r.unwrap().0).collect();
+        assert_eq!(keys, (0..300).filter(|i| i % 3 == 0).collect::<Vec<u32>>());
+        for i in 0..300u32 {
+            tree.remove(&i).unwrap();
+        }
+        assert_eq!(tree.range(..).unwrap().count(), 0);
+        /*
+        ** 释放的节点被重新使用
+        */
+        for i in 0..300u32 {
+            tree.insert(i, i as u64).unwrap();
+        }
+        assert_eq!(handle.lock().unwrap().block_count().unwrap(), block_count);
+        assert_eq!(tree.range(290..).unwrap().count(), 10);
+        /*
+        ** 扫描期间删除的叶子节点被释放并重新用于分裂, 迭代器不会读到重新使用的节点
+        */
+        let mut range = tree.range(..).unwrap();
+        assert_eq!(range.next().unwrap().unwrap().0, 0);
+        for i in 1..200u32 {
+            tree.remove(&i).unwrap();
+        }
+        for i in 1000..1200u32 {
+            tree.insert(i, i as u64).unwrap();
+        }
+        let keys: Vec<u32> = range.map(|r| r.unwrap().0).collect();
+        /*
+        ** 第一个叶子节点中已经读取的键仍然返回
+        */
+        assert!(keys.iter().filter(|k| **k < 200).count() < 20);
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+        assert!(keys.ends_with(&(200..300).chain(1000..1200).collect::<Vec<u32>>()));
+    }
+
+    #[test]
+    fn btree_variable_key_test() {
+        let dir = TestDir::new("btree_variable_key_test");
+        let handle = FixedHandle::new(Fixed::new("index", 160, &dir).unwrap());
+        let tree: BTree<String> = BTree::open(handle.clone()).unwrap();
+        /*
+        ** 长短不一的键: 按键数量对半分时一半可能放不下
+        */
+        let keys: Vec<String> = (0..400u32).map(|i| {
+            let len = if i % 5 == 0 { 20 } else { 1 };
+            format!("{:03}{}", (i * 7) % 400, "x".repeat(len))
+        }).collect();
+        for (i, key) in keys.iter().enumerate() {
+            tree.insert(key.clone(), i as u64).unwrap();
+        }
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(tree.get(key).unwrap(), Some(i as u64));
+        }
+        let mut sorted = keys.clone();
+        sorted.sort();
+        let scanned: Vec<String> = tree.range(..).unwrap().map(|r| r.unwrap().0).collect();
+        assert_eq!(scanned, sorted);
+        assert_eq!(byte_midpoint(&[40, 10, 10, 10, 10], 1, 4), 1);
+        assert_eq!(byte_midpoint(&[10, 10, 10, 10, 40], 1, 4), 4);
+    }
+
+    #[test]
+    fn btree_key_too_large_test() {
+        let dir = TestDir::new("btree_key_too_large_test");
+        let handle = FixedHandle::new(Fixed::new("index", 64, &dir).unwrap());
+        let tree: BTree<String> = BTree::open(handle).unwrap();
+        assert!(tree.insert("x".repeat(40), 1).err().unwrap().is_limit());
+    }
+}
@@ -0,0 +1,106 @@
+/*
+** 块内容的 Read / Write / Seek 适配
+**  读写范围限制在块的内容区域 (业务header之后, 到块结束为止), 不会越界写入下一个块
+*/
+use crate::{Error, Code};
+use super::pager::Pager;
+
+use std::io;
+
+pub struct BlockCursor {
+    pager: Pager,
+    path: String,
+    /*
+    ** 内容区域在文件中的起始位置, 长度
+    */
+    start: u64,
+    length: u64,
+    /*
+    ** 相对内容区域起始位置的偏移
+    */
+    pos: u64
+}
+
+fn to_io_error(err: Error) -> io::Error {
+    io::Error::other(err)
+}
+
+impl BlockCursor {
+    pub(crate) fn new(pager: Pager, path: String, start: u64, length: u64) -> BlockCursor {
+        BlockCursor{
+            pager,
+            path,
+            start,
+            length,
+            pos: 0
+        }
+    }
+
+    /*
+    ** 内容区域的长度
+    */
+    pub fn capacity(&self) -> u64 {
+        self.length
+    }
+
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /*
+    ** 剩余可读写的字节数
+    */
+    pub fn remaining(&self) -> u64 {
+        self.length - self.pos
+    }
+}
+
+impl io::Read for BlockCursor {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = (buf.len() as u64).min(self.remaining()) as usize;
+        if n == 0 {
+            return Ok(0);
+        }
+        let content = self.pager.read_at(self.start + self.pos, n).map_err(to_io_error)?;
+        buf[..content.len()].copy_from_slice(&content);
+        self.pos += content.len() as u64;
+        Ok(content.len())
+    }
+}
+
+impl io::Write for BlockCursor {
+    /*
+    ** 超出内容区域时整体拒绝, 不写入部分内容
+    */
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() as u64 > self.remaining() {
+            let err = Error::new(Code::LimitError)
+                .with_message(format!("write of {} bytes exceeds block payload, {} bytes remaining", buf.len(), self.remaining()))
+                .with_path(&self.path)
+                .with_offset(self.start + self.pos);
+            return Err(to_io_error(err));
+        }
+        self.pager.write_at(self.start + self.pos, buf).map_err(to_io_error)?;
+        self.pos += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.pager.flush().map_err(to_io_error)
+    }
+}
+
+impl io::Seek for BlockCursor {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            io::SeekFrom::Start(p) => p as i128,
+            io::SeekFrom::End(p) => self.length as i128 + p as i128,
+            io::SeekFrom::Current(p) => self.pos as i128 + p as i128
+        };
+        if target < 0 || target > self.length as i128 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("seek to {} is outside block payload of {} bytes", target, self.length)));
+        }
+        self.pos = target as u64;
+        Ok(self.pos)
+    }
+}
@@ -0,0 +1,281 @@
+/*
+** 使用位图, 保存删除信息
+**  每个块一位, 1 表示空闲; 文件头记录位图覆盖的块数量以及空闲块数量
+**  位图整体保存在内存中, 修改时先写入变化的字节, 再写入文件头
+**  两次写入之间崩溃时文件头中的 free_count 不可信, 打开时按位图重新统计; slots 落后时多出的空闲块不会被使用
+**  stage_* 立即修改内存中的位图, 返回需要的写入, 由调用方与其它写入一起提交
+*/
+use crate::multifile::{Result, Error, Code};
+use crate::multifile::fixed::BlockId;
+use super::Staged;
+
+use serde_derive::{Deserialize, Serialize};
+
+use std::fs;
+use std::io::SeekFrom;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+lazy_static!{
+    static ref FILE_HEADER_LENGTH: usize = bincode::serialize(&FileHeader::default()).unwrap().len();
+}
+
+#[derive(Default, Deserialize, Serialize)]
+struct FileHeader {
+    slots: u64,
+    free_count: u64
+}
+
+pub struct Bitmap {
+    file: fs::File,
+    path: PathBuf,
+    bits: Vec<u8>,
+    slots: u64,
+    free_count: u64,
+    /*
+    ** 该字节之前没有空闲块
+    */
+    hint: usize
+}
+
+impl Bitmap {
+    pub fn is_free(&self, id: BlockId) -> bool {
+        id < self.slots && self.bits[(id / 8) as usize] & (1 << (id % 8)) != 0
+    }
+
+    pub fn free_count(&self) -> u64 {
+        self.free_count
+    }
+
+    /*
+    ** 标记块为空闲, 块已经是空闲时返回 false
+    */
+    pub fn set_free(&mut self, ids: &[BlockId]) -> Result<Vec<bool>> {
+        let (changed, writes) = self.stage_set_free(ids)?;
+        self.write(writes)?;
+        Ok(changed)
+    }
+
+    /*
+    ** 按地址从低到高取出最多 n 个空闲块
+    */
+    pub fn take_lowest(&mut self, n: usize) -> Result<Vec<BlockId>> {
+        let (ids, writes) = self.stage_take_lowest(n)?;
+        self.write(writes)?;
+        Ok(ids)
+    }
+
+    pub(crate) fn stage_set_free(&mut self, ids: &[BlockId]) -> Result<(Vec<bool>, Staged)> {
+        let mut changed: Vec<bool> = Vec::with_capacity(ids.len());
+        let mut range: Option<(usize, usize)> = None;
+        for id in ids.iter() {
+            if *id >= self.slots {
+                self.slots = *id + 1;
+                self.bits.resize(self.slots.div_ceil(8) as usize, 0);
+            }
+            if self.is_free(*id) {
+                changed.push(false);
+                continue;
+            }
+            let byte = (*id / 8) as usize;
+            self.bits[byte] |= 1 << (*id % 8);
+            self.free_count += 1;
+            self.hint = self.hint.min(byte);
+            range = Some(extend(range, byte));
+            changed.push(true);
+        }
+        Ok((changed, self.stage(range)?))
+    }
+
+    pub(crate) fn stage_take_lowest(&mut self, n: usize) -> Result<(Vec<BlockId>, Staged)> {
+        let mut ids: Vec<BlockId> = Vec::new();
+        let mut range: Option<(usize, usize)> = None;
+        let mut byte = self.hint;
+        while ids.len() < n && byte < self.bits.len() {
+            if self.bits[byte] == 0 {
+                byte += 1;
+                continue;
+            }
+            let bit = self.bits[byte].trailing_zeros();
+            self.bits[byte] &= !(1 << bit);
+            ids.push(byte as BlockId * 8 + bit as BlockId);
+            range = Some(extend(range, byte));
+        }
+        self.hint = byte;
+        self.free_count -= ids.len() as u64;
+        Ok((ids, self.stage(range)?))
+    }
+
+    /*
+    ** 按地址从低到高查找 n 个连续的空闲块, 找到时取出并返回第一个块
+    */
+    pub fn take_run(&mut self, n: u64) -> Result<Option<BlockId>> {
+        if n == 0 || n > self.free_count {
+            return Ok(None);
+        }
+        let mut start = 0;
+        let mut length = 0;
+        for id in self.hint as u64 * 8..self.slots {
+            if self.is_free(id) {
+                if length == 0 {
+                    start = id;
+                }
+                length += 1;
+                if length == n {
+                    for i in start..start + n {
+                        self.bits[(i / 8) as usize] &= !(1 << (i % 8));
+                    }
+                    self.free_count -= n;
+                    let writes = self.stage(Some(((start / 8) as usize, ((start + n - 1) / 8) as usize)))?;
+                    self.write(writes)?;
+                    return Ok(Some(start));
+                }
+            } else {
+                length = 0;
+            }
+        }
+        Ok(None)
+    }
+
+    pub(crate) fn file(&self) -> (&fs::File, &Path) {
+        (&self.file, &self.path)
+    }
+
+    pub fn sync(&self) -> Result<()> {
+        self.file.sync_all().map_err(|err| {
+            Error::io(Code::FileWriteError, err).with_path(&self.path)
+        })
+    }
+}
+
+fn extend(range: Option<(usize, usize)>, byte: usize) -> (usize, usize) {
+    match range {
+        Some((first, last)) => (first.min(byte), last.max(byte)),
+        None => (byte, byte)
+    }
+}
+
+impl Bitmap {
+    /*
+    ** [first, last] 范围内的字节以及文件头, 按顺序写入
+    */
+    fn stage(&self, range: Option<(usize, usize)>) -> Result<Staged> {
+        let (first, last) = match range {
+            Some(r) => r,
+            None => return Ok(Vec::new())
+        };
+        let offset = (*FILE_HEADER_LENGTH + first) as u64;
+        let header = FileHeader{
+            slots: self.slots,
+            free_count: self.free_count
+        };
+        let header_vec = bincode::serialize(&header).map_err(|err| Error::bincode(Code::SerdeError, err))?;
+        Ok(vec![(offset, self.bits[first..=last].to_vec()), (0, header_vec)])
+    }
+
+    fn write(&mut self, writes: Staged) -> Result<()> {
+        for (offset, content) in writes.iter() {
+            write_at(&mut self.file, &self.path, *offset, content)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_at(file: &mut fs::File, path: &Path, offset: u64, content: &[u8]) -> Result<()> {
+    if let Err(err) = file.seek(SeekFrom::Start(offset)) {
+        return Err(Error::io(Code::FileSeekError, err)
+            .with_path(path)
+            .with_offset(offset));
+    };
+    if let Err(err) = file.write_all(content) {
+        return Err(Error::io(Code::FileWriteError, err)
+            .with_path(path)
+            .with_offset(offset));
+    };
+    Ok(())
+}
+
+impl Bitmap {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Bitmap> {
+        let mut f = match fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(path.as_ref()) {
+            Ok(f) => f,
+            Err(err) => {
+                return Err(Error::io(Code::OpenFileError, err).with_path(path));
+            }
+        };
+        let mut content: Vec<u8> = Vec::new();
+        if let Err(err) = f.read_to_end(&mut content) {
+            return Err(Error::io(Code::FileReadError, err).with_path(path));
+        }
+        let mut bitmap = Bitmap{
+            file: f,
+            path: path.as_ref().to_path_buf(),
+            bits: Vec::new(),
+            slots: 0,
+            free_count: 0,
+            hint: 0
+        };
+        if content.is_empty() {
+            let header_vec = bincode::serialize(&FileHeader::default()).map_err(|err| Error::bincode(Code::SerdeError, err))?;
+            write_at(&mut bitmap.file, &bitmap.path, 0, &header_vec)?;
+            return Ok(bitmap);
+        }
+        let header: FileHeader = bincode::deserialize(&content).map_err(|err| {
+            Error::bincode(Code::DeserdeError, err).with_path(path.as_ref()).with_offset(0)
+        })?;
+        let length = header.slots.div_ceil(8) as usize;
+        if content.len() < *FILE_HEADER_LENGTH + length {
+            return Err(Error::new(Code::DeserdeError)
+                .with_message(format!("bitmap of {} slots is truncated", header.slots))
+                .with_path(path.as_ref()));
+        }
+        bitmap.bits = content[*FILE_HEADER_LENGTH..*FILE_HEADER_LENGTH + length].to_vec();
+        /*
+        ** 最后一个字节中超出 slots 的位不属于位图
+        */
+        if !header.slots.is_multiple_of(8) {
+            if let Some(last) = bitmap.bits.last_mut() {
+                *last &= (1u8 << (header.slots % 8)) - 1;
+            }
+        }
+        bitmap.slots = header.slots;
+        /*
+        ** 文件头中的 free_count 可能与位图不一致 (写入中途崩溃), 按位图重新统计
+        */
+        bitmap.free_count = bitmap.bits.iter().map(|b| b.count_ones() as u64).sum();
+        Ok(bitmap)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::multifile::run_test::TestDir;
+
+    #[test]
+    fn bitmap_reopen_test() {
+        let dir = TestDir::new("bitmap_reopen_test");
+        let path = dir.join("bitmap.rd");
+        let mut bitmap = Bitmap::new(&path).unwrap();
+        bitmap.set_free(&[1, 3, 10]).unwrap();
+        drop(bitmap);
+        /*
+        ** 只写入了位图, 文件头中的 free_count 没有更新
+        */
+        let header_vec = bincode::serialize(&FileHeader{
+            slots: 11,
+            free_count: 0
+        }).unwrap();
+        let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        write_at(&mut file, &path, 0, &header_vec).unwrap();
+        let mut bitmap = Bitmap::new(&path).unwrap();
+        assert_eq!(bitmap.free_count(), 3);
+        assert_eq!(bitmap.take_run(1).unwrap(), Some(1));
+        assert_eq!(bitmap.take_lowest(5).unwrap(), vec![3, 10]);
+    }
+}
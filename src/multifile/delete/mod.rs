@@ -1 +1,7 @@
+pub mod bitmap;
 pub mod stack;
+
+/*
+** 还没有写入的修改: (位置, 内容), 需要按顺序写入
+*/
+pub(crate) type Staged = Vec<(u64, Vec<u8>)>;
@@ -1,7 +1,9 @@
 /*
-** 使用栈, 保存删除信息
+** 持久化的栈, 每条记录为 bincode 内容 + Tail (内容长度), 文件头记录栈顶位置
+**  Delete 使用 Pos 作为记录, 保存删除信息
 */
 use crate::multifile::{Result, Error, Code};
+use super::Staged;
 
 // use serde::{Deserialize, Serialize};
 use serde_derive::{Deserialize, Serialize};
@@ -9,27 +11,31 @@ use serde_derive::{Deserialize, Serialize};
 use std::fs;
 use std::io::SeekFrom;
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::marker::PhantomData;
 
 lazy_static!{
-    static ref TAIL_LENGTH: usize = Tail::new(0).to_vec().unwrap().len();
+    pub(crate) static ref TAIL_LENGTH: usize = Tail::new(0).to_vec().unwrap().len();
     static ref FILE_HEADER_LENGTH: usize = FileHeader::new(0).to_vec().unwrap().len();
 }
 
+pub struct PersistentStack<T> {
+    file: fs::File,
+    path: PathBuf,
+    marker: PhantomData<fn() -> T>
+}
+
+/*
+** 删除信息栈
+**  owner: 数据文件路径, 设置之后拒绝放入其他文件中的位置
+*/
 pub struct Delete {
-    file: fs::File
+    stack: PersistentStack<Pos>,
+    owner: Option<String>
 }
 
 fn to_vec<T: serde::Serialize>(t: &T) -> Result<Vec<u8>> {
-    let s = match bincode::serialize(t) {
-        Ok(c) => c,
-        Err(err) => {
-            return Err(Error{
-                code: Some(Code::SerdeError(Some(err.to_string())))
-            });
-        }
-    };
-    Ok(s)
+    bincode::serialize(t).map_err(|err| Error::bincode(Code::SerdeError, err))
 }
 
 #[derive(Default, Deserialize, Serialize)]
@@ -45,62 +51,54 @@ impl Pos {
     }
 
     pub fn new(path: String, start_pos: usize, length: usize) -> Pos {
-        let pos = Pos{
-            path: path,
-            start_pos: start_pos,
-            length: length
-        };
-        pos
+        Pos{
+            path,
+            start_pos,
+            length
+        }
     }
 }
 
+/*
+** 记录内容的长度, 位于记录内容之后, 用于从后向前读取
+*/
 #[derive(Default, Deserialize, Serialize)]
-struct Tail {
-    length: usize
+pub(crate) struct Tail {
+    pub(crate) length: usize
 }
 
 impl Tail {
-    fn to_vec(&self) -> Result<Vec<u8>> {
+    pub(crate) fn to_vec(&self) -> Result<Vec<u8>> {
         to_vec(self)
     }
 
-    fn new(length: usize) -> Tail {
-        let tail = Tail{
-            length: length
-        };
-        tail
+    pub(crate) fn new(length: usize) -> Tail {
+        Tail{
+            length
+        }
     }
 }
 
-#[derive(Default, Deserialize, Serialize)]
-struct Body {
-    pos: Pos
+/*
+** 记录: bincode 内容 + Tail
+*/
+pub(crate) struct Body<'a, T> {
+    value: &'a T
 }
 
-impl Body {
-    fn to_vec(&self) -> Result<Vec<u8>> {
-        let mut pos_vec = match self.pos.to_vec() {
-            Ok(v) => v,
-            Err(err) => {
-                return Err(err);
-            }
-        };
-        let tail = Tail::new(pos_vec.len());
-        let mut tail_vec = match tail.to_vec() {
-            Ok(v) => v,
-            Err(err) => {
-                return Err(err);
-            }
-        };
-        pos_vec.append(&mut tail_vec);
-        Ok(pos_vec)
+impl<'a, T: serde::Serialize> Body<'a, T> {
+    pub(crate) fn to_vec(&self) -> Result<Vec<u8>> {
+        let mut value_vec = to_vec(self.value)?;
+        let tail = Tail::new(value_vec.len());
+        let mut tail_vec = tail.to_vec()?;
+        value_vec.append(&mut tail_vec);
+        Ok(value_vec)
     }
 
-    fn new(pos: Pos) -> Body {
-        let body = Body{
-            pos: pos
-        };
-        body
+    pub(crate) fn new(value: &'a T) -> Body<'a, T> {
+        Body{
+            value
+        }
     }
 }
 
@@ -115,69 +113,139 @@ impl FileHeader {
     }
 
     fn new(stack_top_pos: usize) -> FileHeader {
-        let file_header = FileHeader{
-            stack_top_pos: stack_top_pos
-        };
-        file_header
+        FileHeader{
+            stack_top_pos
+        }
     }
 }
 
-impl Delete {
+impl<T: serde::Serialize + serde::de::DeserializeOwned> PersistentStack<T> {
     /*
-    ** 将传入的位置放到栈顶
+    ** 将记录放到栈顶
     */
-    pub fn push(&mut self, pos: Pos) -> Result<()> {
-        let body = Body::new(pos);
-        let body_vec = match body.to_vec() {
-            Ok(v) => v,
-            Err(err) => {
-                return Err(err);
-            }
-        };
+    pub fn push(&mut self, value: &T) -> Result<()> {
+        let body = Body::new(value);
+        let body_vec = body.to_vec()?;
         /*
         ** 获取文件头
         */
-        let file_header = match Delete::get_file_header(&mut self.file) {
-            Ok(h) => h,
-            Err(err) => {
-                return Err(err);
-            }
-        };
+        let file_header = self.get_file_header()?;
         /*
         ** 将文件指针指向文件头指定的位置
         */
-        if let Err(err) = self.file.seek(SeekFrom::Start(file_header.stack_top_pos as u64)) {
-            return Err(Error{
-                code: Some(Code::FileSeekError(Some(err.to_string())))
-            });
-        };
-        if let Err(err) = self.file.write(body_vec.as_slice()) {
-            return Err(Error{
-                code: Some(Code::FileWriteError(Some(err.to_string())))
-            });
+        let offset = file_header.stack_top_pos as u64;
+        self.seek(offset)?;
+        if let Err(err) = self.file.write_all(body_vec.as_slice()) {
+            return Err(Error::io(Code::FileWriteError, err)
+                .with_path(&self.path)
+                .with_offset(offset));
         };
         /*
         ** 更新文件头
         */
-        if let Err(err) = self.update_file_header(FileHeader::new(file_header.stack_top_pos + body_vec.len())) {
-            return Err(err);
-        };
+        self.update_file_header(FileHeader::new(file_header.stack_top_pos + body_vec.len()))
+    }
+
+    /*
+    ** 按顺序将多条记录放到栈顶, 只写入一次, 文件头只更新一次
+    */
+    pub fn push_all(&mut self, values: &[T]) -> Result<()> {
+        for (offset, content) in self.stage_push_all(values)? {
+            self.write_at(offset, &content)?;
+        }
         Ok(())
     }
 
     /*
-    ** 将栈顶的位置移除
+    ** 从栈顶移除最多 n 条记录 (栈顶在前), 文件头只更新一次
     */
-    pub fn pop(&mut self) -> Result<Option<Pos>> {
+    pub fn pop_many(&mut self, n: usize) -> Result<Vec<T>> {
+        let (values, writes) = self.stage_pop_many(n)?;
+        for (offset, content) in writes {
+            self.write_at(offset, &content)?;
+        }
+        Ok(values)
+    }
+
+    /*
+    ** push_all 需要的写入 (记录内容, 文件头), 不写入文件
+    **  由调用方按顺序写入之后, 记录才放入栈中
+    */
+    pub(crate) fn stage_push_all(&mut self, values: &[T]) -> Result<Staged> {
+        if values.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut body_vec: Vec<u8> = Vec::new();
+        for value in values.iter() {
+            body_vec.append(&mut Body::new(value).to_vec()?);
+        }
+        let file_header = self.get_file_header()?;
+        let top = file_header.stack_top_pos + body_vec.len();
+        Ok(vec![(file_header.stack_top_pos as u64, body_vec), (0, FileHeader::new(top).to_vec()?)])
+    }
+
+    /*
+    ** 读取栈顶最多 n 条记录 (栈顶在前) 以及移除它们需要的写入 (文件头), 不写入文件
+    */
+    pub(crate) fn stage_pop_many(&mut self, n: usize) -> Result<(Vec<T>, Staged)> {
+        let file_header = self.get_file_header()?;
+        let mut top = file_header.stack_top_pos;
+        let mut values: Vec<T> = Vec::new();
+        while values.len() < n && top > *FILE_HEADER_LENGTH {
+            self.seek((top - *TAIL_LENGTH) as u64)?;
+            let tail: Tail = self.deserde(*TAIL_LENGTH)?;
+            top -= *TAIL_LENGTH + tail.length;
+            self.seek(top as u64)?;
+            values.push(self.deserde(tail.length)?);
+        }
+        if top == file_header.stack_top_pos {
+            return Ok((values, Vec::new()));
+        }
+        Ok((values, vec![(0, FileHeader::new(top).to_vec()?)]))
+    }
+
+    /*
+    ** 将栈顶的记录移除
+    */
+    pub fn pop(&mut self) -> Result<Option<T>> {
+        match self.top()? {
+            Some((value, pos_start)) => {
+                /*
+                ** 更新文件头
+                */
+                self.update_file_header(FileHeader::new(pos_start))?;
+                Ok(Some(value))
+            }
+            None => Ok(None)
+        }
+    }
+
+    /*
+    ** 读取栈顶的记录, 不移除
+    */
+    pub fn peek(&mut self) -> Result<Option<T>> {
+        Ok(self.top()?.map(|(value, _)| value))
+    }
+
+    /*
+    ** 栈顶位置 (文件头中记录的位置)
+    */
+    pub(crate) fn top_pos(&mut self) -> Result<usize> {
+        Ok(self.get_file_header()?.stack_top_pos)
+    }
+
+    pub fn is_empty(&mut self) -> Result<bool> {
+        Ok(self.get_file_header()?.stack_top_pos == *FILE_HEADER_LENGTH)
+    }
+
+    /*
+    ** 栈顶的记录以及记录的起始位置
+    */
+    fn top(&mut self) -> Result<Option<(T, usize)>> {
         /*
         ** 获取文件头
         */
-        let file_header = match Delete::get_file_header(&mut self.file) {
-            Ok(h) => h,
-            Err(err) => {
-                return Err(err);
-            }
-        };
+        let file_header = self.get_file_header()?;
         /*
         ** 判断栈是否为空
         */
@@ -187,136 +255,107 @@ impl Delete {
         /*
         ** 获取栈顶Tail
         */
-        if let Err(err) = self.file.seek(SeekFrom::Start((file_header.stack_top_pos - *TAIL_LENGTH) as u64)) {
-            return Err(Error{
-                code: Some(Code::FileSeekError(Some(err.to_string())))
-            });
-        };
-        let tail = match Delete::deserde_tail(&mut self.file) {
-            Ok(t) => t,
-            Err(err) => {
-                return Err(err);
-            }
-        };
+        self.seek((file_header.stack_top_pos - *TAIL_LENGTH) as u64)?;
+        let tail: Tail = self.deserde(*TAIL_LENGTH)?;
         /*
-        ** 获取栈顶Pos
+        ** 获取栈顶记录
         */
-        if let Err(err) = self.file.seek(SeekFrom::Start((file_header.stack_top_pos - *TAIL_LENGTH - tail.length) as u64)) {
-            return Err(Error{
-                code: Some(Code::FileSeekError(Some(err.to_string())))
-            });
-        };
-        let pos = match Delete::deserde_pos(&mut self.file, tail.length) {
-            Ok(p) => p,
-            Err(err) => {
-                return Err(err);
-            }
-        };
-        /*
-        ** 更新文件头
-        */
-        if let Err(err) = self.update_file_header(FileHeader::new(file_header.stack_top_pos - *TAIL_LENGTH - tail.length)) {
-            return Err(err);
-        };
-        Ok(Some(pos))
+        let pos_start = file_header.stack_top_pos - *TAIL_LENGTH - tail.length;
+        self.seek(pos_start as u64)?;
+        let value: T = self.deserde(tail.length)?;
+        Ok(Some((value, pos_start)))
     }
 }
 
-impl Delete {
-    fn deserde_pos(file: &mut fs::File, length: usize) -> Result<Pos> {
-        let mut content: Vec<u8> = Vec::new();
-        if let Err(err) = file.take(length as u64).read_to_end(&mut content) {
-            return Err(Error{
-                code: Some(Code::FileReadError(Some(err.to_string())))
-            });
+impl<T> PersistentStack<T> {
+    /*
+    ** 栈的文件, 用于和其它写入一起提交 stage_* 返回的内容
+    */
+    pub(crate) fn file(&self) -> (&fs::File, &Path) {
+        (&self.file, &self.path)
+    }
+
+    /*
+    ** 将文件内容刷到磁盘
+    */
+    pub fn sync(&self) -> Result<()> {
+        self.file.sync_all().map_err(|err| {
+            Error::io(Code::FileWriteError, err).with_path(&self.path)
+        })
+    }
+}
+
+impl<T> PersistentStack<T> {
+    fn seek(&mut self, offset: u64) -> Result<()> {
+        if let Err(err) = self.file.seek(SeekFrom::Start(offset)) {
+            return Err(Error::io(Code::FileSeekError, err)
+                .with_path(&self.path)
+                .with_offset(offset));
         };
-        let pos = match bincode::deserialize(&content) {
+        Ok(())
+    }
+
+    /*
+    ** 从当前位置读取 length 字节并反序列化
+    */
+    fn deserde<D: serde::de::DeserializeOwned>(&mut self, length: usize) -> Result<D> {
+        let offset = match self.file.stream_position() {
             Ok(p) => p,
             Err(err) => {
-                return Err(Error{
-                    code: Some(Code::DeserdeError(Some(err.to_string())))
-                });
+                return Err(Error::io(Code::FileSeekError, err).with_path(&self.path));
             }
         };
-        Ok(pos)
+        let mut content: Vec<u8> = Vec::new();
+        if let Err(err) = (&mut self.file).take(length as u64).read_to_end(&mut content) {
+            return Err(Error::io(Code::FileReadError, err)
+                .with_path(&self.path)
+                .with_offset(offset));
+        };
+        bincode::deserialize(&content).map_err(|err| {
+            Error::bincode(Code::DeserdeError, err)
+                .with_path(&self.path)
+                .with_offset(offset)
+        })
     }
 
-    fn deserde_tail(file: &mut fs::File) -> Result<Tail> {
-        let mut content: Vec<u8> = Vec::new();
-        if let Err(err) = file.take(*TAIL_LENGTH as u64).read_to_end(&mut content) {
-            return Err(Error{
-                code: Some(Code::FileReadError(Some(err.to_string())))
-            });
-        };
-        let tail = match bincode::deserialize(&content) {
-            Ok(t) => t,
-            Err(err) => {
-                return Err(Error{
-                    code: Some(Code::DeserdeError(Some(err.to_string())))
-                });
-            }
+    fn write_at(&mut self, offset: u64, content: &[u8]) -> Result<()> {
+        self.seek(offset)?;
+        if let Err(err) = self.file.write_all(content) {
+            return Err(Error::io(Code::FileWriteError, err)
+                .with_path(&self.path)
+                .with_offset(offset));
         };
-        Ok(tail)
+        Ok(())
     }
 
-    fn get_file_header(file: &mut fs::File) -> Result<FileHeader> {
-        if let Err(err) = file.seek(SeekFrom::Start(0)) {
-            return Err(Error{
-                code: Some(Code::FileSeekError(Some(err.to_string())))
-            });
-        };
-        let mut content: Vec<u8> = Vec::new();
-        if let Err(err) = file.take(*FILE_HEADER_LENGTH as u64).read_to_end(&mut content) {
-            return Err(Error{
-                code: Some(Code::FileReadError(Some(err.to_string())))
-            });
-        };
-        let header = match bincode::deserialize(&content) {
-            Ok(h) => h,
-            Err(err) => {
-                return Err(Error{
-                    code: Some(Code::DeserdeError(Some(err.to_string())))
-                });
-            }
-        };
-        Ok(header)
+    fn get_file_header(&mut self) -> Result<FileHeader> {
+        self.seek(0)?;
+        self.deserde(*FILE_HEADER_LENGTH)
     }
 
     fn update_file_header(&mut self, file_hedaer: FileHeader) -> Result<()> {
-        if let Err(err) = self.file.seek(SeekFrom::Start(0)) {
-            return Err(Error{
-                code: Some(Code::FileSeekError(Some(err.to_string())))
-            });
-        };
-        let file_header_vec = match file_hedaer.to_vec() {
-            Ok(v) => v,
-            Err(err) => {
-                return Err(err);
-            }
-        };
-        if let Err(err) = self.file.write(file_header_vec.as_slice()) {
-            return Err(Error{
-                code: Some(Code::FileWriteError(Some(err.to_string())))
-            });
+        self.seek(0)?;
+        let file_header_vec = file_hedaer.to_vec()?;
+        if let Err(err) = self.file.write_all(file_header_vec.as_slice()) {
+            return Err(Error::io(Code::FileWriteError, err)
+                .with_path(&self.path)
+                .with_offset(0));
         };
         Ok(())
     }
 
     fn get_file_size(&self) -> Result<usize> {
-        let metadata = match self.file.metadata() {
-            Ok(l) => l,
+        match self.file.metadata() {
+            Ok(metadata) => Ok(metadata.len() as usize),
             Err(err) => {
-                return Err(Error{
-                    code: Some(Code::FileMetadataError(Some(err.to_string())))
-                });
+                Err(Error::io(Code::FileMetadataError, err).with_path(&self.path))
             }
-        };
-        Ok(metadata.len() as usize)
+        }
     }
 }
 
-impl Delete {
-    pub fn new<P: AsRef<Path>>(path: P) -> Result<Delete> {
+impl<T> PersistentStack<T> {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<PersistentStack<T>> {
         /*
         ** 打开文件
         **  1. 如果文件不存在, 写入尾指针到文件头
@@ -324,43 +363,110 @@ impl Delete {
         */
         let f = match fs::OpenOptions::new()
             .create(true)
+            .truncate(false)
             .read(true)
             .write(true)
-            .open(path) {
+            .open(path.as_ref()) {
             Ok(f) => f,
             Err(err) => {
-                return Err(Error{
-                    code: Some(Code::OpenFileError(Some(err.to_string())))
-                })
+                return Err(Error::io(Code::OpenFileError, err).with_path(path));
             }
         };
-        let mut delete = Delete{
-            file: f
+        let mut stack = PersistentStack{
+            file: f,
+            path: path.as_ref().to_path_buf(),
+            marker: PhantomData
         };
-        match delete.get_file_size() {
-            Ok(size) => {
-                if size == 0 {
-                    /*
-                    ** 文件内容为空, 需要添加文件头
-                    */
-                    let file_header_vec = match FileHeader::new(*FILE_HEADER_LENGTH).to_vec() {
-                        Ok(v) => v,
-                        Err(err) => {
-                            return Err(err);
-                        }
-                    };
-                    if let Err(err) = delete.file.write(file_header_vec.as_slice()) {
-                        return Err(Error{
-                            code: Some(Code::FileWriteError(Some(err.to_string())))
-                        });
-                    };
-                }
-            },
-            Err(err) => {
-                return Err(err);
+        if stack.get_file_size()? == 0 {
+            /*
+            ** 文件内容为空, 需要添加文件头
+            */
+            stack.update_file_header(FileHeader::new(*FILE_HEADER_LENGTH))?;
+        }
+        Ok(stack)
+    }
+}
+
+impl Delete {
+    /*
+    ** 将传入的位置放到栈顶
+    */
+    pub fn push(&mut self, pos: Pos) -> Result<()> {
+        self.check_owner(&pos)?;
+        self.stack.push(&pos)
+    }
+
+    /*
+    ** 将栈顶的位置移除
+    */
+    pub fn pop(&mut self) -> Result<Option<Pos>> {
+        self.stack.pop()
+    }
+
+    /*
+    ** 批量放入 / 取出位置
+    */
+    pub fn push_many(&mut self, positions: Vec<Pos>) -> Result<()> {
+        for pos in positions.iter() {
+            self.check_owner(pos)?;
+        }
+        self.stack.push_all(&positions)
+    }
+
+    pub fn pop_many(&mut self, n: usize) -> Result<Vec<Pos>> {
+        self.stack.pop_many(n)
+    }
+
+    /*
+    ** push_many / pop_many 需要的写入, 不写入文件, 见 PersistentStack::stage_push_all
+    */
+    pub(crate) fn stage_push_many(&mut self, positions: &[Pos]) -> Result<Staged> {
+        for pos in positions.iter() {
+            self.check_owner(pos)?;
+        }
+        self.stack.stage_push_all(positions)
+    }
+
+    pub(crate) fn stage_pop_many(&mut self, n: usize) -> Result<(Vec<Pos>, Staged)> {
+        self.stack.stage_pop_many(n)
+    }
+
+    pub(crate) fn file(&self) -> (&fs::File, &Path) {
+        self.stack.file()
+    }
+
+    pub(crate) fn top_pos(&mut self) -> Result<usize> {
+        self.stack.top_pos()
+    }
+
+    pub fn sync(&self) -> Result<()> {
+        self.stack.sync()
+    }
+
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Delete> {
+        Ok(Delete{
+            stack: PersistentStack::new(path)?,
+            owner: None
+        })
+    }
+
+    pub fn with_owner<P: AsRef<Path>>(path: P, owner: &str) -> Result<Delete> {
+        Ok(Delete{
+            stack: PersistentStack::new(path)?,
+            owner: Some(owner.to_string())
+        })
+    }
+
+    fn check_owner(&self, pos: &Pos) -> Result<()> {
+        match &self.owner {
+            Some(owner) if owner != &pos.path => {
+                Err(Error::new(Code::ForeignPathError)
+                    .with_message(format!("position of {} can not be freed into {}", pos.path, owner))
+                    .with_path(&self.stack.path)
+                    .with_offset(pos.start_pos as u64))
             }
+            _ => Ok(())
         }
-        Ok(delete)
     }
 }
 
@@ -369,6 +475,7 @@ mod test {
     use super::*;
     #[test]
     #[ignore]
+    #[allow(unused_must_use)]
     fn fixed_push_test() {
         let mut delete = match Delete::new("delete_record") {
             Ok(f) => f,
@@ -398,13 +505,11 @@ mod test {
                     },
                     None => {
                         println!("stack is empty");
-                        return;
                     }
                 }
             },
             Err(err) => {
                 println!("{:?}", err);
-                return;
             }
         }
     }
@@ -0,0 +1,119 @@
+/*
+** 直接 I/O (O_DIRECT) 的对齐读写
+**  读写的偏移, 长度以及内存地址都必须按 alignment 对齐
+**  不对齐的读写扩展到覆盖它的对齐区域: 读取整个区域, 写入时先读取再修改整个区域
+*/
+use crate::{Result, Error, Code};
+
+use std::alloc::{self, Layout};
+use std::fs;
+use std::os::unix::fs::FileExt;
+
+/*
+** 按 alignment 对齐的内存, 初始内容为 0
+*/
+struct AlignedBuf {
+    ptr: *mut u8,
+    layout: Layout
+}
+
+impl AlignedBuf {
+    fn new(length: usize, alignment: usize) -> Result<AlignedBuf> {
+        let layout = match Layout::from_size_align(length.max(alignment), alignment) {
+            Ok(layout) => layout,
+            Err(_) => {
+                return Err(Error::new(Code::LimitError)
+                    .with_message(format!("alignment {} is not a power of two", alignment)));
+            }
+        };
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        Ok(AlignedBuf{
+            ptr,
+            layout
+        })
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.layout.size()) }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.layout.size()) }
+    }
+}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.ptr, self.layout) }
+    }
+}
+
+/*
+** 覆盖 [offset, offset + length) 的对齐区域 (起始位置, 长度)
+*/
+fn aligned_range(offset: u64, length: usize, alignment: u64) -> (u64, usize) {
+    let start = offset / alignment * alignment;
+    let end = (offset + length as u64).div_ceil(alignment) * alignment;
+    (start, (end - start) as usize)
+}
+
+/*
+** 读取对齐区域, 返回读取到的字节数, 文件结尾之后的部分为 0
+*/
+fn read_region(file: &fs::File, path: &str, buf: &mut AlignedBuf, start: u64) -> Result<usize> {
+    let mut read = 0;
+    let length = buf.layout.size();
+    while read < length {
+        match file.read_at(&mut buf.as_mut_slice()[read..], start + read as u64) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => {
+                return Err(Error::io(Code::FileReadError, err)
+                    .with_path(path)
+                    .with_offset(start + read as u64));
+            }
+        }
+    }
+    Ok(read)
+}
+
+pub(crate) fn read_aligned(file: &fs::File, path: &str, alignment: u64, offset: u64, length: usize) -> Result<Vec<u8>> {
+    let (start, region) = aligned_range(offset, length, alignment);
+    let mut buf = AlignedBuf::new(region, alignment as usize)?;
+    let read = read_region(file, path, &mut buf, start)?;
+    let first = (offset - start) as usize;
+    let last = (first + length).min(read.max(first));
+    Ok(buf.as_slice()[first..last].to_vec())
+}
+
+pub(crate) fn write_aligned(file: &fs::File, path: &str, alignment: u64, offset: u64, content: &[u8]) -> Result<()> {
+    let (start, region) = aligned_range(offset, content.len(), alignment);
+    let mut buf = AlignedBuf::new(region, alignment as usize)?;
+    let first = (offset - start) as usize;
+    if first != 0 || content.len() != region {
+        read_region(file, path, &mut buf, start)?;
+    }
+    buf.as_mut_slice()[first..first + content.len()].copy_from_slice(content);
+    file.write_all_at(&buf.as_slice()[..region], start).map_err(|err| {
+        Error::io(Code::FileWriteError, err)
+            .with_path(path)
+            .with_offset(offset)
+    })
+}
+
+/*
+** 以 O_DIRECT 重新打开文件
+*/
+pub(crate) fn open_direct(path: &str, direct: bool) -> Result<fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    let mut options = fs::OpenOptions::new();
+    options.read(true).write(true);
+    if direct {
+        options.custom_flags(libc::O_DIRECT);
+    }
+    options.open(path).map_err(|err| Error::io(Code::OpenFileError, err).with_path(path))
+}
@@ -1,167 +1,396 @@
 use crate::{Result, Error, Code};
-use super::delete::stack;
+use super::delete::{stack, bitmap, Staged};
+use super::pager::{Pager, WriteOp, CacheConfig, CacheStats, IoBackend};
+use super::cursor::BlockCursor;
+use super::index::Indexes;
+use super::limit::{self, Limits, Usage, DirQuota};
 
 use serde_derive::{Serialize, Deserialize};
 
+use std::collections::HashSet;
 use std::path::Path;
 use std::fs;
 use std::io::SeekFrom;
 use std::io::prelude::*;
 
 fn to_vec<T: serde::Serialize>(t: &T) -> Result<Vec<u8>> {
-    let s = match bincode::serialize(t) {
-        Ok(c) => c,
-        Err(err) => {
-            return Err(Error{
-                code: Some(Code::SerdeError(Some(err.to_string())))
-            });
-        }
-    };
-    Ok(s)
+    bincode::serialize(t).map_err(|err| Error::bincode(Code::SerdeError, err))
 }
 
-fn new_u8_vec_with_size(size: usize) -> Vec<u8> {
-    let mut v: Vec<u8> = Vec::with_capacity(size);
-    for _i in 0..size {
-        v.push(0);
-    }
-    v
+/*
+** 使用 name 拼接 delete record name
+*/
+pub(crate) fn delete_record_name(name: &str) -> String {
+    let mut delete_record_name = String::new();
+    delete_record_name.push_str(name);
+    delete_record_name.push_str("_delete.rd");
+    delete_record_name
+}
+
+/*
+** 使用 name 拼接 bitmap record name
+*/
+pub(crate) fn bitmap_record_name(name: &str) -> String {
+    let mut bitmap_record_name = String::new();
+    bitmap_record_name.push_str(name);
+    bitmap_record_name.push_str("_bitmap.rd");
+    bitmap_record_name
 }
 
+/*
+** 块在 Fixed 文件中的序号
+*/
+pub type BlockId = u64;
+
 /*
 ** 块
 */
 pub struct Block {
     path: String,
+    id: BlockId,
     start_pos: usize,
     length: usize,
-    file: fs::File
+    pager: Pager,
+    indexes: Indexes
 }
 
 /*
-** 为 usize 新增方法
+** 续块不存在
 */
-trait ToVec {
-    fn to_vec(&self) -> Result<Vec<u8>>;
+const NO_NEXT: BlockId = BlockId::MAX;
+
+#[derive(Serialize, Deserialize)]
+struct BlockHeader {
+    /*
+    ** 业务的header长度
+    */
+    header_size: usize,
+    /*
+    ** 记录的下一个续块, 没有续块时为 NO_NEXT
+    */
+    next: BlockId,
+    /*
+    ** 记录在该块中占用的字节数
+    */
+    used: usize,
+    /*
+    ** 块是 write_record 写入的记录的第一个块
+    */
+    head: bool,
+    /*
+    ** 块已经被释放, 用于检测重复释放
+    */
+    freed: bool
+}
+
+impl Default for BlockHeader {
+    fn default() -> Self {
+        BlockHeader::new(0)
+    }
 }
 
-impl ToVec for usize {
+impl BlockHeader {
     fn to_vec(&self) -> Result<Vec<u8>> {
         to_vec(self)
     }
+
+    fn new(header_size: usize) -> Self {
+        Self {
+            header_size,
+            next: NO_NEXT,
+            used: 0,
+            head: false,
+            freed: false
+        }
+    }
+
+    /*
+    ** 块保存着 write_record 写入的内容 (第一个块或者续块)
+    **  续块的 used 总是大于 0, 只有空记录的第一个块 used 为 0
+    */
+    fn is_record(&self) -> bool {
+        self.head || self.used > 0
+    }
+
+    fn is_continuation(&self) -> bool {
+        !self.head && self.used > 0
+    }
 }
 
+/*
+** 文件头的标识以及格式版本, 文件头 / 块头的布局变化时增加版本
+**  格式版本 0 (没有文件头, 8 字节块头) 的文件不能读取, 见 CHANGELOG.md
+*/
+const FIXED_MAGIC: u32 = 0x4658_4450;
+const FORMAT_VERSION: u32 = 1;
+
+/*
+** Fixed 文件头, 记录块大小, 使得不知道 fixed_size 也可以打开文件
+*/
 #[derive(Default, Serialize, Deserialize)]
-struct BlockHeader {
+struct FileHeader {
+    magic: u32,
+    version: u32,
+    fixed_size: usize,
     /*
-    ** 业务的header长度
+    ** 下一个未使用的块序号, 预分配的块不计入块数量
+    */
+    high_water: u64,
+    allocator: Allocator,
+    /*
+    ** 块以及第一个块的起始位置的对齐大小, 0 表示不对齐
     */
-    header_size: usize
+    alignment: u64
 }
 
-impl BlockHeader {
+impl FileHeader {
     fn to_vec(&self) -> Result<Vec<u8>> {
         to_vec(self)
     }
 
-    fn new(header_size: usize) -> Self {
+    fn new(fixed_size: usize, high_water: u64, options: FixedOptions) -> Self {
         Self {
-            header_size: header_size
+            magic: FIXED_MAGIC,
+            version: FORMAT_VERSION,
+            fixed_size,
+            high_water,
+            allocator: options.allocator,
+            alignment: options.alignment
+        }
+    }
+
+    fn options(&self) -> FixedOptions {
+        FixedOptions{
+            allocator: self.allocator,
+            alignment: self.alignment
         }
     }
 }
 
+/*
+** 创建文件时的选项, 记录在文件头中, 打开已经存在的文件时必须一致
+**  allocator: 空闲块的管理方式
+**  alignment: 块的对齐大小 (例如 4096), 块头 + fixed_size 向上补齐, 开启直接 I/O 时需要不小于 512; 0 表示不对齐
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FixedOptions {
+    pub allocator: Allocator,
+    pub alignment: u64
+}
+
+impl FixedOptions {
+    pub fn new() -> FixedOptions {
+        FixedOptions::default()
+    }
+
+    pub fn with_allocator(mut self, allocator: Allocator) -> FixedOptions {
+        self.allocator = allocator;
+        self
+    }
+
+    pub fn with_alignment(mut self, alignment: u64) -> FixedOptions {
+        self.alignment = alignment;
+        self
+    }
+}
+
+/*
+** 直接 I/O 要求的最小对齐 (设备的逻辑块大小至少为 512)
+*/
+const DIRECT_IO_ALIGNMENT: u64 = 512;
+
+/*
+** 按 alignment 向上补齐
+*/
+fn align_up(value: usize, alignment: u64) -> usize {
+    if alignment == 0 {
+        return value;
+    }
+    value.div_ceil(alignment as usize) * alignment as usize
+}
+
+/*
+** 空闲块的管理方式, 创建文件时选择, 记录在文件头中
+**  Stack: stack::Delete, 后释放的块先被使用
+**  Bitmap: bitmap::Bitmap, 地址低的块先被使用, 支持查询块是否空闲以及分配连续的块
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Allocator {
+    #[default]
+    Stack,
+    Bitmap
+}
+
+enum FreeSpace {
+    Stack(stack::Delete),
+    Bitmap(bitmap::Bitmap),
+    /*
+    ** 没有自己的删除记录, 释放的位置由外部 (条带的共享删除记录) 管理
+    */
+    External
+}
+
+/*
+** 已经分配但还没有提交的块, 由 Fixed::commit 写入
+**  ids 的前 popped 个来自删除记录, 其余来自高水位
+*/
+struct Allocation {
+    ids: Vec<BlockId>,
+    popped: usize,
+    /*
+    ** 取出之前删除栈的栈顶, 提交失败时用于判断取出是否已经写入
+    */
+    free_top: Option<usize>,
+    /*
+    ** 删除记录需要按顺序写入的内容
+    */
+    free_writes: Staged,
+    high_water: u64
+}
+
+/*
+** 数据文件的扩展方式
+**  Slots(n): 每次扩展 n 个块
+**  Percent(p): 每次扩展当前块数量的 p%, 至少一个块
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Growth {
+    Slots(u64),
+    Percent(u32)
+}
+
+impl Default for Growth {
+    fn default() -> Self {
+        Growth::Slots(1)
+    }
+}
+
+impl Growth {
+    fn extra_slots(&self, slots: u64) -> u64 {
+        let extra = match *self {
+            Growth::Slots(n) => n,
+            Growth::Percent(p) => slots * p as u64 / 100
+        };
+        extra.max(1)
+    }
+}
+
 lazy_static!{
     static ref BLOCK_HEADER_LENGTH: usize = BlockHeader::new(0).to_vec().unwrap().len();
+    static ref FILE_HEADER_LENGTH: usize = FileHeader::default().to_vec().unwrap().len();
 }
 
 impl Block {
     /*
     ** 更新header (业务header)
+    **  write_record 写入的块中, 记录内容占用了业务header的位置, 不能再写入业务header
     */
     pub fn update_header<Header: serde::Serialize>(&mut self, header: Header) -> Result<()> {
-        let block_header = match Block::get_block_header(self.start_pos, &mut self.file) {
-            Ok(h) => h,
-            Err(err) => {
-                return Err(err);
-            }
-        };
-        let header_vec = match to_vec(&header) {
-            Ok(v) => v,
-            Err(err) => {
-                return Err(err);
-            }
-        };
+        let mut block_header = self.get_block_header()?;
+        if block_header.is_record() {
+            return Err(Error::new(Code::MismatchError)
+                .with_message(format!("block {} holds a record, it has no business header", self.id))
+                .with_path(&self.path)
+                .with_offset(self.start_pos as u64));
+        }
+        let header_vec = to_vec(&header)?;
+        if header_vec.len() > self.length {
+            return Err(Error::new(Code::LimitError)
+                .with_message(format!("header size {} exceeds fixed size {}", header_vec.len(), self.length))
+                .with_path(&self.path)
+                .with_offset(self.start_pos as u64));
+        }
         /*
-        ** 移动到业务头的位置 (块起始位置 + 块头长度)
+        ** 覆盖业务头信息 (块起始位置 + 块头长度)
         */
-        if let Err(err) = self.file.seek(SeekFrom::Start((self.start_pos + *BLOCK_HEADER_LENGTH) as u64)) {
-            return Err(Error{
-                code: Some(Code::FileSeekError(Some(err.to_string())))
-            });
-        };
+        let offset = (self.start_pos + *BLOCK_HEADER_LENGTH) as u64;
+        self.pager.write_at(offset, header_vec.as_slice())?;
         /*
-        ** 覆盖业务头信息
+        ** 记录业务头长度, 读取时使用
         */
-        if let Err(err) = self.file.write(header_vec.as_slice()) {
-            return Err(Error{
-                code: Some(Code::FileWriteError(Some(err.to_string())))
-            });
-        };
-        Ok(())
+        block_header.header_size = header_vec.len();
+        self.update_block_header(&block_header)?;
+        /*
+        ** 维护二级索引
+        */
+        self.indexes.update(self.id, &header_vec)
+    }
+
+    /*
+    ** 读取header (业务header)
+    */
+    pub fn header<Header: serde::de::DeserializeOwned>(&mut self) -> Result<Header> {
+        let block_header = self.get_block_header()?;
+        if block_header.header_size == 0 {
+            return Err(Error::new(Code::NotFoundError)
+                .with_message("block header has not been written")
+                .with_path(&self.path)
+                .with_offset(self.start_pos as u64));
+        }
+        let offset = (self.start_pos + *BLOCK_HEADER_LENGTH) as u64;
+        let content = self.pager.read_at(offset, block_header.header_size)?;
+        bincode::deserialize(&content).map_err(|err| {
+            Error::bincode(Code::DeserdeError, err)
+                .with_path(&self.path)
+                .with_offset(offset)
+        })
+    }
+
+    /*
+    ** 块内容的读写游标, 范围为业务header之后到块结束
+    */
+    pub fn cursor(&mut self) -> Result<BlockCursor> {
+        let block_header = self.get_block_header()?;
+        let start = self.start_pos + *BLOCK_HEADER_LENGTH + block_header.header_size;
+        let length = self.length - block_header.header_size;
+        Ok(BlockCursor::new(self.pager.clone(), self.path.clone(), start as u64, length as u64))
+    }
+
+    pub fn id(&self) -> BlockId {
+        self.id
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn length(&self) -> usize {
+        self.length
     }
 }
 
 impl Block {
-    fn get_block_header(start_pos: usize, file: &mut fs::File) -> Result<BlockHeader> {
-        /*
-        ** 移动到该块的起始位置
-        */
-        if let Err(err) = file.seek(SeekFrom::Start(start_pos as u64)) {
-            return Err(Error{
-                code: Some(Code::FileSeekError(Some(err.to_string())))
-            });
-        };
+    fn get_block_header(&mut self) -> Result<BlockHeader> {
         /*
         ** 读取块头内容
         */
-        let mut content: Vec<u8> = Vec::new();
-        if let Err(err) = file.take(*BLOCK_HEADER_LENGTH as u64).read_to_end(&mut content) {
-            return Err(Error{
-                code: Some(Code::FileReadError(Some(err.to_string())))
-            });
-        };
+        let offset = self.start_pos as u64;
+        let content = self.pager.read_at(offset, *BLOCK_HEADER_LENGTH)?;
         /*
         ** 反序列化块头内容
         */
-        let header = match bincode::deserialize(&content) {
-            Ok(h) => h,
-            Err(err) => {
-                return Err(Error{
-                    code: Some(Code::DeserdeError(Some(err.to_string())))
-                });
-            }
-        };
-        Ok(header)
+        bincode::deserialize(&content).map_err(|err| {
+            Error::bincode(Code::DeserdeError, err)
+                .with_path(&self.path)
+                .with_offset(offset)
+        })
     }
-}
 
-impl Block {
-    fn from_delete_stack_pos(pos: stack::Pos, file: fs::File) -> Self {
-        Self {
-            path: pos.path,
-            start_pos: pos.start_pos,
-            length: pos.length,
-            file: file
-        }
+    fn update_block_header(&mut self, block_header: &BlockHeader) -> Result<()> {
+        let block_header_vec = block_header.to_vec()?;
+        self.pager.write_at(self.start_pos as u64, block_header_vec.as_slice())
     }
+}
 
-    fn new(path: String, start_pos: usize, length: usize, file: fs::File) -> Self {
+impl Block {
+    fn new(path: String, id: BlockId, start_pos: usize, length: usize, pager: Pager, indexes: Indexes) -> Self {
         Self {
-            path: path,
-            start_pos: start_pos,
-            length: length,
-            file: file
+            path,
+            id,
+            start_pos,
+            length,
+            pager,
+            indexes
         }
     }
 }
@@ -171,8 +400,31 @@ impl Block {
 */
 pub struct Fixed {
     fixed_size: usize,
-    delete_record: stack::Delete,
-    file: fs::File,
+    free_space: FreeSpace,
+    options: FixedOptions,
+    /*
+    ** 第一个块的起始位置 / 块头 + fixed_size 补齐之后的大小
+    */
+    data_start: usize,
+    slot_size: usize,
+    direct_io: bool,
+    pager: Pager,
+    indexes: Indexes,
+    /*
+    ** 下一个未使用的块序号 (与文件头一致) / 文件中已经分配空间的块数量
+    */
+    high_water: u64,
+    file_slots: u64,
+    growth: Growth,
+    /*
+    ** 释放块时是否释放块内容占用的磁盘空间
+    */
+    punch_holes: bool,
+    limits: Limits,
+    /*
+    ** name 目录的使用量, 设置 max_dir_bytes 时才统计
+    */
+    quota: Option<DirQuota>,
     name: String,
     file_path: String
 }
@@ -180,126 +432,1545 @@ pub struct Fixed {
 impl Fixed {
     /*
     ** 在文件中创建一个块
+    **  存在可用位置时直接使用, 不存在时使用高水位处的块
+    **  高水位到达文件尾部时按 growth 扩展文件, 扩展部分为 0
     */
     pub fn new_block(&mut self) -> Result<Block> {
-        let file_clone = match self.file.try_clone() {
-            Ok(f) => f,
-            Err(_) => {
-                return Err(Error{
-                    code: Some(Code::FileTryCloneError(Some(String::from("file try clone error"))))
-                });
-            }
-        };
+        Ok(self.new_blocks(1)?.remove(0))
+    }
+
+    /*
+    ** 批量创建 n 个块
+    **  先从删除栈中一次取出最多 n 个位置, 不足的部分从高水位处连续分配, 文件最多扩展一次
+    **  删除记录, 块头以及文件头一次提交
+    */
+    pub fn new_blocks(&mut self, n: usize) -> Result<Vec<Block>> {
+        let allocation = self.allocate(n)?;
         /*
-        ** 从删除的栈顶获取可用位置
+        ** 清除重用的块的释放标记, 高水位处的块也写入块头 (next 为 NO_NEXT)
         */
-        let p = match self.delete_record.pop() {
-            Ok(p) => p,
-            Err(err) => {
-                return Err(err);
-            }
+        let block_header_vec = BlockHeader::default().to_vec()?;
+        let writes: Vec<WriteOp> = allocation.ids.iter()
+            .map(|id| WriteOp::data(self.start_pos(*id) as u64, block_header_vec.as_slice()))
+            .collect();
+        let ids = self.commit(allocation, writes)?;
+        Ok(ids.iter().map(|id| self.new_block_at(*id)).collect())
+    }
+
+    /*
+    ** 创建 n 个连续的块
+    **  Bitmap => 优先使用地址最低的 n 个连续空闲块, 不存在时从高水位处分配
+    **  Stack => 从高水位处分配
+    */
+    pub fn new_run(&mut self, n: usize) -> Result<Vec<Block>> {
+        let first = match &mut self.free_space {
+            FreeSpace::Bitmap(bitmap) => bitmap.take_run(n as u64)?,
+            FreeSpace::Stack(_) | FreeSpace::External => None
         };
-        match p {
-            Some(pos) => {
-                /*
-                ** 存在可用位置 => 直接使用pos作为block返回
-                */
-                return Ok(Block::from_delete_stack_pos(pos, file_clone))
-            }
+        let first = match first {
+            Some(first) => first,
             None => {
-                /*
-                ** 不存在可用位置 => 从文件尾部创建新的block
-                */
-                let file_size = match self.get_file_size() {
-                    Ok(l) => l,
-                    Err(err) => {
-                        return Err(err);
-                    }
-                };
-                /*
-                ** 写入初始化数据
-                */
-                if let Err(err) = self.file.seek(SeekFrom::End(0)) {
-                    return Err(Error{
-                        code: Some(Code::FileSeekError(Some(err.to_string())))
-                    });
-                };
-                /*
-                ** block size + fixed size
-                */
-                if let Err(err) = self.file.write(new_u8_vec_with_size(*BLOCK_HEADER_LENGTH + self.fixed_size).as_slice()) {
-                    return Err(Error{
-                        code: Some(Code::FileWriteError(Some(err.to_string())))
-                    });
+                let high_water = self.high_water;
+                self.reserve_high_water(n as u64)?;
+                let allocation = Allocation{
+                    ids: (high_water..high_water + n as u64).collect(),
+                    popped: 0,
+                    free_top: None,
+                    free_writes: Vec::new(),
+                    high_water: high_water + n as u64
                 };
-                return Ok(Block::new(self.file_path.clone(), file_size, self.fixed_size, file_clone));
+                let block_header_vec = BlockHeader::default().to_vec()?;
+                let writes: Vec<WriteOp> = allocation.ids.iter()
+                    .map(|id| WriteOp::data(self.start_pos(*id) as u64, block_header_vec.as_slice()))
+                    .collect();
+                self.commit(allocation, writes)?;
+                high_water
             }
+        };
+        Ok((first..first + n as u64).map(|id| self.new_block_at(id)).collect())
+    }
+
+    /*
+    ** 块是否空闲, 只有 Bitmap 支持
+    */
+    pub fn is_free(&self, id: BlockId) -> Result<bool> {
+        match &self.free_space {
+            FreeSpace::Bitmap(bitmap) => Ok(bitmap.is_free(id)),
+            FreeSpace::Stack(_) | FreeSpace::External => Err(Error::new(Code::NotImplement)
+                .with_message("stack allocator can not answer whether a block is free")
+                .with_path(&self.file_path))
         }
-        // Err(Error{
-        //     code: Some(Code::NewError(Some(String::from("delete and new block all error"))))
-        // })
     }
-}
 
-impl Fixed {
-    pub fn new<P: AsRef<Path>>(name: &str, fixed_size: usize, path: P) -> Result<Self> {
+    /*
+    ** 空闲块数量, 只有 Bitmap 支持
+    */
+    pub fn free_count(&self) -> Result<u64> {
+        match &self.free_space {
+            FreeSpace::Bitmap(bitmap) => Ok(bitmap.free_count()),
+            FreeSpace::Stack(_) | FreeSpace::External => Err(Error::new(Code::NotImplement)
+                .with_message("stack allocator does not count free blocks")
+                .with_path(&self.file_path))
+        }
+    }
+
+    pub fn allocator(&self) -> Allocator {
+        self.options.allocator
+    }
+
+    pub fn alignment(&self) -> u64 {
+        self.options.alignment
+    }
+
+    /*
+    ** 开启 / 关闭直接 I/O (O_DIRECT), 绕过页缓存, 只支持 Linux
+    **  要求文件创建时指定了不小于 512 的 alignment
+    */
+    pub fn set_direct_io(&mut self, direct_io: bool) -> Result<()> {
+        if direct_io && self.options.alignment < DIRECT_IO_ALIGNMENT {
+            return Err(Error::new(Code::MismatchError)
+                .with_message(format!("direct io requires an alignment of at least {}, but the file was created with {}",
+                    DIRECT_IO_ALIGNMENT, self.options.alignment))
+                .with_path(&self.file_path));
+        }
+        self.pager.set_direct_io(if direct_io { Some(self.options.alignment) } else { None })?;
+        self.direct_io = direct_io;
+        Ok(())
+    }
+
+    pub fn direct_io(&self) -> bool {
+        self.direct_io
+    }
+
+    /*
+    ** 释放块, 块的位置放入删除栈, 之后由 new_block 重新使用
+    **  块是记录的第一个块时连同续块一起释放, 续块不能单独释放
+    */
+    pub fn free_block(&mut self, id: BlockId) -> Result<()> {
+        self.free_blocks(vec![id])
+    }
+
+    /*
+    ** 批量释放块, 块头以及删除记录一次提交
+    **  遇到重复释放时, 之前已经释放的块仍然放入删除栈, 然后返回错误
+    */
+    pub fn free_blocks<I: IntoIterator<Item = BlockId>>(&mut self, ids: I) -> Result<()> {
+        let mut released: Vec<BlockId> = Vec::new();
+        let mut seen: HashSet<BlockId> = HashSet::new();
+        let mut result = Ok(());
+        for id in ids {
+            match self.release_ids(id, &mut seen) {
+                Ok(mut chain) => released.append(&mut chain),
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            }
+        }
+        self.release_blocks(&released)?;
+        result
+    }
+
+    /*
+    ** 清除块的块头以及业务header, 块保持占用状态, 不放入删除栈
+    **  块是记录的第一个块时, 先释放记录的续块
+    */
+    pub(crate) fn reset_block(&mut self, id: BlockId) -> Result<Block> {
+        let mut block = self.block(id)?;
+        let ids = self.release_ids(id, &mut HashSet::new())?;
+        self.release_blocks(&ids[1..])?;
+        block.update_block_header(&BlockHeader::default())?;
+        self.indexes.remove(id)?;
+        Ok(block)
+    }
+
+    /*
+    ** 写入一条记录, 返回记录第一个块的序号
+    **  记录超过 fixed_size 时, 拆分到多个块中, 通过块头中的 next 串联
+    **  记录从块头之后开始写入, 记录的块不能再调用 Block::update_header
+    */
+    pub fn write_record(&mut self, content: &[u8]) -> Result<BlockId> {
+        let mut chunks: Vec<&[u8]> = content.chunks(self.fixed_size.max(1)).collect();
+        if chunks.is_empty() {
+            chunks.push(&[]);
+        }
         /*
-        ** 打开文件
+        ** 一次分配所有的块, 分配失败时已经撤销
         */
-        let file_path = path.as_ref().join(&name);
-        let file_path_name = match file_path.to_str() {
-            Some(p) => p.to_string(),
-            None => {
-                return Err(Error{
-                    code: Some(Code::PathToStrError(Some(String::from("path to_str is none"))))
-                });
-            }
-        };
-        let f = match fs::OpenOptions::new()
-            .create(true)
-            .read(true)
-            .write(true)
-            .open(file_path) {
-            Ok(f) => f,
-            Err(err) => {
-                return Err(Error{
-                    code: Some(Code::OpenFileError(Some(err.to_string())))
-                })
-            }
-        };
+        let allocation = self.allocate(chunks.len())?;
+        let ids = allocation.ids.clone();
         /*
-        ** 使用 name 拼接 delete record name
+        ** 所有块的内容以及块头与删除记录 / 文件头一次提交
         */
-        let mut delete_record_name = String::new();
-        delete_record_name.push_str(name);
-        delete_record_name.push_str("_delete.rd");
-        let delete_record = match stack::Delete::new(path.as_ref().join(&delete_record_name)) {
-            Ok(d) => d,
-            Err(err) => {
-                return Err(err);
-            }
-        };
-        let fixed = Self {
-            fixed_size: fixed_size,
-            delete_record: delete_record,
-            file: f,
-            name: name.to_string(),
-            file_path: file_path_name
-        };
-        Ok(fixed)
+        let mut block_headers: Vec<Vec<u8>> = Vec::with_capacity(chunks.len());
+        for (i, chunk) in chunks.iter().enumerate() {
+            block_headers.push(BlockHeader{
+                used: chunk.len(),
+                next: ids.get(i + 1).cloned().unwrap_or(NO_NEXT),
+                head: i == 0,
+                ..Default::default()
+            }.to_vec()?);
+        }
+        let mut writes: Vec<WriteOp> = Vec::with_capacity(chunks.len() * 2);
+        for (i, chunk) in chunks.iter().enumerate() {
+            let start_pos = self.start_pos(ids[i]);
+            writes.push(WriteOp::data((start_pos + *BLOCK_HEADER_LENGTH) as u64, chunk));
+            writes.push(WriteOp::data(start_pos as u64, block_headers[i].as_slice()));
+        }
+        self.commit(allocation, writes)?;
+        Ok(ids[0])
     }
-}
 
-impl Fixed {
-    fn get_file_size(&self) -> Result<usize> {
-        let metadata = match self.file.metadata() {
-            Ok(l) => l,
-            Err(err) => {
-                return Err(Error{
-                    code: Some(Code::FileMetadataError(Some(err.to_string())))
-                });
-            }
-        };
-        Ok(metadata.len() as usize)
+    /*
+    ** 读取 write_record 写入的记录, id 必须是记录的第一个块
+    */
+    pub fn read_record(&self, id: BlockId) -> Result<Vec<u8>> {
+        self.check_record(id)?;
+        let mut content: Vec<u8> = Vec::new();
+        for block_id in self.record_chain(id)?.iter() {
+            let mut block = self.block(*block_id)?;
+            let block_header = block.get_block_header()?;
+            let start_pos = (block.start_pos + *BLOCK_HEADER_LENGTH) as u64;
+            content.append(&mut self.pager.read_at(start_pos, block_header.used)?);
+        }
+        Ok(content)
+    }
+
+    /*
+    ** 释放记录占用的所有块, id 必须是记录的第一个块
+    */
+    pub fn free_record(&mut self, id: BlockId) -> Result<()> {
+        self.check_record(id)?;
+        self.free_block(id)
+    }
+
+    /*
+    ** 将块复制到 other 中, 返回 other 中的块序号
+    **  目标块由 other 的删除记录 / 高水位分配, 块头 (业务header长度 / 使用的字节数) 以及块内容保持不变
+    **  单个块复制时不保留续块, 需要保留记录时使用 copy_blocks_to 复制整条记录
+    */
+    pub fn copy_block_to(&self, other: &mut Fixed, id: BlockId) -> Result<BlockId> {
+        Ok(self.copy_blocks_to(other, &[id])?[0])
+    }
+
+    /*
+    ** 批量复制块, 返回 other 中对应的块序号
+    **  所有块一次从 other 中分配, 内容通过 copy_file_range 在内核中复制, 不经过用户态缓冲区
+    **  记录的块头中的续块指向同一批复制的块时改为新的块序号, 否则清除
+    */
+    pub fn copy_blocks_to(&self, other: &mut Fixed, ids: &[BlockId]) -> Result<Vec<BlockId>> {
+        if other.fixed_size < self.fixed_size {
+            return Err(Error::new(Code::LimitError)
+                .with_message(format!("can not copy blocks of {} bytes into blocks of {} bytes", self.fixed_size, other.fixed_size))
+                .with_path(&other.file_path));
+        }
+        let mut block_headers: Vec<BlockHeader> = Vec::with_capacity(ids.len());
+        for id in ids.iter() {
+            let block_header = self.block(*id)?.get_block_header()?;
+            if block_header.freed {
+                return Err(Error::new(Code::NotFoundError)
+                    .with_message(format!("block {} is free", id))
+                    .with_path(&self.file_path)
+                    .with_offset(self.start_pos(*id) as u64));
+            }
+            block_headers.push(block_header);
+        }
+        let targets: Vec<BlockId> = other.new_blocks(ids.len())?.iter().map(|b| b.id()).collect();
+        let ranges: Vec<(u64, u64, usize)> = ids.iter().zip(targets.iter())
+            .map(|(id, target)| (self.start_pos(*id) as u64, other.start_pos(*target) as u64, *BLOCK_HEADER_LENGTH + self.fixed_size))
+            .collect();
+        /*
+        ** 同一批中被记录的块引用的续块
+        */
+        let referenced: HashSet<BlockId> = block_headers.iter()
+            .filter(|block_header| block_header.is_record())
+            .map(|block_header| block_header.next)
+            .collect();
+        let copied = self.pager.copy_to(&other.pager, &ranges).and_then(|_| {
+            for (i, block_header) in block_headers.iter_mut().enumerate() {
+                /*
+                ** 只有记录的块使用 next; 没有复制第一个块的续块成为一条新记录的第一个块
+                */
+                block_header.next = match ids.iter().position(|id| *id == block_header.next) {
+                    Some(p) if block_header.is_record() && block_header.next != NO_NEXT => targets[p],
+                    _ => NO_NEXT
+                };
+                if block_header.is_continuation() && !referenced.contains(&ids[i]) {
+                    block_header.head = true;
+                }
+                let mut block = other.block(targets[i])?;
+                block.update_block_header(block_header)?;
+                if block_header.header_size > 0 {
+                    let offset = (block.start_pos + *BLOCK_HEADER_LENGTH) as u64;
+                    other.indexes.update(targets[i], &other.pager.read_at(offset, block_header.header_size)?)?;
+                }
+            }
+            Ok(())
+        });
+        if let Err(err) = copied {
+            let _ = other.free_blocks(targets);
+            return Err(err);
+        }
+        Ok(targets)
+    }
+
+    /*
+    ** 将 value 序列化到块的内容区域 (业务header之后)
+    */
+    pub fn write_payload<T: serde::Serialize>(&self, id: BlockId, value: &T) -> Result<()> {
+        let content = to_vec(value)?;
+        let mut cursor = self.block(id)?.cursor()?;
+        if content.len() as u64 > cursor.capacity() {
+            return Err(Error::new(Code::LimitError)
+                .with_message(format!("payload size {} exceeds block capacity {}", content.len(), cursor.capacity()))
+                .with_path(&self.file_path)
+                .with_offset(self.start_pos(id) as u64));
+        }
+        cursor.write_all(&content).map_err(|err| {
+            Error::io(Code::FileWriteError, err)
+                .with_path(&self.file_path)
+                .with_offset(self.start_pos(id) as u64)
+        })
+    }
+
+    /*
+    ** 从块的内容区域反序列化 write_payload 写入的内容
+    */
+    pub fn read_payload<T: serde::de::DeserializeOwned>(&self, id: BlockId) -> Result<T> {
+        let mut cursor = self.block(id)?.cursor()?;
+        bincode::deserialize_from(&mut cursor).map_err(|err| {
+            Error::bincode(Code::DeserdeError, err)
+                .with_path(&self.file_path)
+                .with_offset(self.start_pos(id) as u64)
+        })
+    }
+
+    /*
+    ** 获取文件中已经存在的块
+    */
+    pub fn block(&self, id: BlockId) -> Result<Block> {
+        if id >= self.block_count()? {
+            return Err(Error::new(Code::NotFoundError)
+                .with_message(format!("block {} is out of range", id))
+                .with_path(&self.file_path));
+        }
+        Ok(Block::new(self.file_path.clone(), id, self.start_pos(id), self.fixed_size, self.pager.clone(), self.indexes.clone()))
+    }
+
+    /*
+    ** 文件中块的数量 (包括已删除的块, 不包括预分配的块)
+    */
+    pub fn block_count(&self) -> Result<u64> {
+        Ok(self.high_water)
+    }
+
+    /*
+    ** 设置数据文件的扩展方式, 批量创建块时减少扩展文件的次数
+    */
+    pub fn set_growth(&mut self, growth: Growth) {
+        self.growth = growth;
+    }
+
+    pub fn growth(&self) -> Growth {
+        self.growth
+    }
+
+    /*
+    ** 开启之后 free_block 对块内容打洞 (Linux fallocate PUNCH_HOLE), 释放的块读取为 0
+    **  不支持打洞的平台 / 文件系统写入 0
+    */
+    pub fn set_punch_holes(&mut self, punch_holes: bool) {
+        self.punch_holes = punch_holes;
+    }
+
+    pub fn punch_holes(&self) -> bool {
+        self.punch_holes
+    }
+
+    /*
+    ** 开启 / 关闭块缓存
+    **  Fixed 以及它创建的所有 Block 共享同一个缓存
+    */
+    pub fn set_cache(&mut self, config: Option<CacheConfig>) -> Result<()> {
+        self.pager.set_cache(config)
+    }
+
+    pub fn cache_stats(&self) -> Result<Option<CacheStats>> {
+        self.pager.cache_stats()
+    }
+
+    /*
+    ** 切换数据文件的读写方式
+    **  Uring: 所有读写经过 io_uring, 需要 feature = "io_uring"
+    **   new_blocks / write_record / free_blocks 的块头, 内容, 删除记录以及文件头 (高水位) 放入同一次提交
+    **   不能与块缓存或者直接 I/O 同时开启, 否则返回 MismatchError
+    */
+    pub fn set_io_backend(&mut self, backend: IoBackend) -> Result<()> {
+        self.pager.set_io_backend(backend)
+    }
+
+    pub fn io_backend(&self) -> Result<IoBackend> {
+        self.pager.io_backend()
+    }
+
+    /*
+    ** 将缓存中的脏块写入文件
+    */
+    pub fn flush(&self) -> Result<()> {
+        self.pager.flush()
+    }
+
+    /*
+    ** 将数据文件以及删除记录刷到磁盘
+    */
+    pub fn sync(&self) -> Result<()> {
+        self.pager.sync()?;
+        match &self.free_space {
+            FreeSpace::Stack(delete) => delete.sync(),
+            FreeSpace::Bitmap(bitmap) => bitmap.sync(),
+            FreeSpace::External => Ok(())
+        }
+    }
+
+    /*
+    ** 注册二级索引, extractor 从业务header中提取索引键, 返回 None 时该块不进入索引
+    **  注册之后通过全量扫描重建索引, 之后由 update_header / free_block 自动维护
+    */
+    pub fn register_index<H, K, F>(&mut self, name: &str, extractor: F) -> Result<()>
+        where H: serde::de::DeserializeOwned, K: serde::Serialize, F: Fn(&H) -> Option<K> + Send + 'static {
+        self.indexes.register(name, extractor)?;
+        if let Err(err) = self.rebuild_indexes() {
+            let _ = self.indexes.unregister(name);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    pub fn unregister_index(&mut self, name: &str) -> Result<bool> {
+        self.indexes.unregister(name)
+    }
+
+    /*
+    ** 查找业务header中索引键等于 key 的所有块
+    */
+    pub fn index_lookup<K: serde::Serialize>(&self, name: &str, key: &K) -> Result<Vec<BlockId>> {
+        self.indexes.lookup(name, key)
+    }
+
+    /*
+    ** 扫描所有写入过业务header的块, 重建全部二级索引
+    */
+    pub fn rebuild_indexes(&self) -> Result<()> {
+        if self.indexes.is_empty()? {
+            return Ok(());
+        }
+        let mut headers: Vec<(BlockId, Vec<u8>)> = Vec::new();
+        for id in 0..self.block_count()? {
+            let mut block = self.block(id)?;
+            let block_header = block.get_block_header()?;
+            if block_header.header_size == 0 {
+                continue;
+            }
+            let offset = (block.start_pos + *BLOCK_HEADER_LENGTH) as u64;
+            headers.push((id, self.pager.read_at(offset, block_header.header_size)?));
+        }
+        self.indexes.rebuild(&headers)
+    }
+
+    /*
+    ** 设置容量限制, 只影响之后的文件扩展
+    */
+    pub fn set_limits(&mut self, limits: Limits) -> Result<()> {
+        if limits.max_dir_bytes.is_some() && self.quota.is_none() {
+            let dir = match Path::new(&self.file_path).parent() {
+                Some(dir) => dir.to_path_buf(),
+                None => Path::new(".").to_path_buf()
+            };
+            self.quota = Some(DirQuota::new(dir)?);
+        }
+        self.limits = limits;
+        Ok(())
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /*
+    ** 当前使用量以及对应的限制
+    */
+    pub fn usage(&self) -> Result<Usage> {
+        let file_bytes = self.get_file_size()? as u64;
+        Ok(Usage{
+            blocks: self.block_count()?,
+            max_blocks: self.limits.max_blocks,
+            file_bytes,
+            max_file_bytes: self.limits.max_file_bytes,
+            dir_bytes: match &self.quota {
+                Some(quota) => quota.used()?,
+                None => file_bytes
+            },
+            max_dir_bytes: self.limits.max_dir_bytes
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /*
+    ** 数据文件路径
+    */
+    pub fn path(&self) -> &str {
+        &self.file_path
+    }
+
+    /*
+    ** 是否还有存活的 Block / 游标指向该数据文件
+    */
+    pub(crate) fn is_shared(&self) -> bool {
+        self.pager.ref_count() > 1
+    }
+
+    pub fn fixed_size(&self) -> usize {
+        self.fixed_size
+    }
+}
+
+impl Fixed {
+    /*
+    ** 创建文件时使用默认选项, 打开已经存在的文件时使用文件头中记录的选项
+    */
+    pub fn new<P: AsRef<Path>>(name: &str, fixed_size: usize, path: P) -> Result<Self> {
+        Fixed::open_with(name, Some((fixed_size, None)), path, true)
+    }
+
+    /*
+    ** 创建文件时选择空闲块的管理方式, 打开已经存在的文件时必须一致
+    */
+    pub fn with_allocator<P: AsRef<Path>>(name: &str, fixed_size: usize, allocator: Allocator, path: P) -> Result<Self> {
+        Fixed::with_options(name, fixed_size, FixedOptions::new().with_allocator(allocator), path)
+    }
+
+    /*
+    ** 创建文件时指定选项, 打开已经存在的文件时必须一致
+    */
+    pub fn with_options<P: AsRef<Path>>(name: &str, fixed_size: usize, options: FixedOptions, path: P) -> Result<Self> {
+        if options.alignment != 0 && !options.alignment.is_power_of_two() {
+            return Err(Error::new(Code::LimitError)
+                .with_message(format!("alignment {} is not a power of two", options.alignment))
+                .with_path(path.as_ref().join(name)));
+        }
+        Fixed::open_with(name, Some((fixed_size, Some(options))), path, true)
+    }
+
+    /*
+    ** 打开已经存在的文件, fixed_size 从文件头中读取
+    */
+    pub fn open<P: AsRef<Path>>(name: &str, path: P) -> Result<Self> {
+        Fixed::open_with(name, None, path, true)
+    }
+
+    /*
+    ** 创建 / 打开不带删除记录的文件, 释放的位置由调用方的删除记录管理 (retire_block / reclaim_block)
+    */
+    pub(crate) fn with_external_free<P: AsRef<Path>>(name: &str, fixed_size: usize, path: P) -> Result<Self> {
+        Fixed::open_with(name, Some((fixed_size, None)), path, false)
+    }
+
+    fn open_with<P: AsRef<Path>>(name: &str, create: Option<(usize, Option<FixedOptions>)>, path: P, local_free: bool) -> Result<Self> {
+        /*
+        ** 打开文件
+        **  create 为 None 时, 文件必须已经存在
+        **  local_free 为 false 时不打开删除记录
+        */
+        let file_path = path.as_ref().join(name);
+        let file_path_name = match file_path.to_str() {
+            Some(p) => p.to_string(),
+            None => {
+                return Err(Error::new(Code::PathToStrError)
+                    .with_message("path to_str is none")
+                    .with_path(&file_path));
+            }
+        };
+        let mut f = match fs::OpenOptions::new()
+            .create(create.is_some())
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(&file_path) {
+            Ok(f) => f,
+            Err(err) => {
+                return Err(Error::io(Code::OpenFileError, err).with_path(&file_path));
+            }
+        };
+        let file_header = Fixed::init_file_header(&mut f, &file_path, create)?;
+        let fixed_size = file_header.fixed_size;
+        /*
+        ** 打开删除记录
+        */
+        let free_space = match file_header.allocator {
+            _ if !local_free => FreeSpace::External,
+            Allocator::Stack => FreeSpace::Stack(stack::Delete::with_owner(path.as_ref().join(delete_record_name(name)), &file_path_name)?),
+            Allocator::Bitmap => FreeSpace::Bitmap(bitmap::Bitmap::new(path.as_ref().join(bitmap_record_name(name)))?)
+        };
+        let options = file_header.options();
+        let slot_size = align_up(*BLOCK_HEADER_LENGTH + fixed_size, options.alignment);
+        let data_start = align_up(*FILE_HEADER_LENGTH, options.alignment);
+        let file_size = match f.metadata() {
+            Ok(metadata) => metadata.len() as usize,
+            Err(err) => {
+                return Err(Error::io(Code::FileMetadataError, err).with_path(&file_path));
+            }
+        };
+        let file_slots = (file_size.saturating_sub(data_start) / slot_size) as u64;
+        if file_header.high_water > file_slots {
+            return Err(Error::new(Code::DeserdeError)
+                .with_message(format!("high water {} exceeds {} slots in file", file_header.high_water, file_slots))
+                .with_path(&file_path));
+        }
+        let fixed = Self {
+            fixed_size,
+            free_space,
+            options,
+            data_start,
+            slot_size,
+            direct_io: false,
+            pager: Pager::new(f, file_path_name.clone(), data_start as u64, slot_size as u64),
+            indexes: Indexes::default(),
+            high_water: file_header.high_water,
+            file_slots,
+            growth: Growth::default(),
+            punch_holes: false,
+            limits: Limits::default(),
+            quota: None,
+            name: name.to_string(),
+            file_path: file_path_name
+        };
+        Ok(fixed)
+    }
+}
+
+/*
+** 大小不超过 bytes 的数据文件最多容纳的块数量, 文件头以及块都按 alignment 补齐
+*/
+pub(crate) fn slots_within(fixed_size: usize, alignment: u64, bytes: u64) -> u64 {
+    let data_start = align_up(*FILE_HEADER_LENGTH, alignment) as u64;
+    let slot_size = align_up(*BLOCK_HEADER_LENGTH + fixed_size, alignment) as u64;
+    bytes.saturating_sub(data_start) / slot_size
+}
+
+impl Fixed {
+    /*
+    ** 文件为空 => 写入文件头
+    ** 文件不为空 => 读取文件头, 并校验 fixed_size 以及 allocator
+    */
+    fn init_file_header(file: &mut fs::File, file_path: &Path, create: Option<(usize, Option<FixedOptions>)>) -> Result<FileHeader> {
+        let file_size = match file.metadata() {
+            Ok(metadata) => metadata.len() as usize,
+            Err(err) => {
+                return Err(Error::io(Code::FileMetadataError, err).with_path(file_path));
+            }
+        };
+        if file_size == 0 {
+            let (fixed_size, options) = match create {
+                Some(c) => c,
+                None => {
+                    return Err(Error::new(Code::NotFoundError)
+                        .with_message("fixed file is empty")
+                        .with_path(file_path));
+                }
+            };
+            let file_header = FileHeader::new(fixed_size, 0, options.unwrap_or_default());
+            let file_header_vec = file_header.to_vec()?;
+            if let Err(err) = file.write_all(file_header_vec.as_slice()) {
+                return Err(Error::io(Code::FileWriteError, err)
+                    .with_path(file_path)
+                    .with_offset(0));
+            };
+            return Ok(file_header);
+        }
+        if let Err(err) = file.seek(SeekFrom::Start(0)) {
+            return Err(Error::io(Code::FileSeekError, err)
+                .with_path(file_path)
+                .with_offset(0));
+        };
+        let mut content: Vec<u8> = Vec::new();
+        if let Err(err) = file.take(*FILE_HEADER_LENGTH as u64).read_to_end(&mut content) {
+            return Err(Error::io(Code::FileReadError, err)
+                .with_path(file_path)
+                .with_offset(0));
+        };
+        /*
+        ** 先校验标识以及版本, 再按当前版本的布局读取
+        */
+        let (magic, version): (u32, u32) = match bincode::deserialize(&content) {
+            Ok(h) => h,
+            Err(err) => {
+                return Err(Error::bincode(Code::DeserdeError, err)
+                    .with_path(file_path)
+                    .with_offset(0));
+            }
+        };
+        if magic != FIXED_MAGIC {
+            return Err(Error::new(Code::MismatchError)
+                .with_message(format!("not a fixed file, magic is {:#x} (files of format version 0 have no magic and are not readable, see CHANGELOG.md)", magic))
+                .with_path(file_path)
+                .with_offset(0));
+        }
+        if version != FORMAT_VERSION {
+            return Err(Error::new(Code::VersionError)
+                .with_message(format!("fixed file format version {} is not supported, expected {}", version, FORMAT_VERSION))
+                .with_path(file_path)
+                .with_offset(0));
+        }
+        let file_header: FileHeader = match bincode::deserialize(&content) {
+            Ok(h) => h,
+            Err(err) => {
+                return Err(Error::bincode(Code::DeserdeError, err)
+                    .with_path(file_path)
+                    .with_offset(0));
+            }
+        };
+        match create {
+            Some((s, _)) if s != file_header.fixed_size => {
+                Err(Error::new(Code::MismatchError)
+                    .with_message(format!("fixed size is {}, but file was created with {}", s, file_header.fixed_size))
+                    .with_path(file_path))
+            },
+            Some((_, Some(o))) if o.allocator != file_header.allocator => {
+                Err(Error::new(Code::MismatchError)
+                    .with_message(format!("allocator is {:?}, but file was created with {:?}", o.allocator, file_header.allocator))
+                    .with_path(file_path))
+            },
+            Some((_, Some(o))) if o.alignment != file_header.alignment => {
+                Err(Error::new(Code::MismatchError)
+                    .with_message(format!("alignment is {}, but file was created with {}", o.alignment, file_header.alignment))
+                    .with_path(file_path))
+            },
+            _ => Ok(file_header)
+        }
+    }
+
+    /*
+    ** 块头 + 块内容, 按 alignment 补齐
+    */
+    fn slot_size(&self) -> usize {
+        self.slot_size
+    }
+
+    fn start_pos(&self, id: BlockId) -> usize {
+        self.data_start + id as usize * self.slot_size()
+    }
+
+    fn block_id(&self, start_pos: usize) -> BlockId {
+        ((start_pos - self.data_start) / self.slot_size()) as BlockId
+    }
+
+    /*
+    ** 按 growth 扩展文件, 扩展的块数量受 max_blocks / max_file_bytes / max_dir_bytes 限制, 至少扩展 min 个块
+    */
+    fn grow(&mut self, min: u64) -> Result<()> {
+        let slot_size = self.slot_size() as u64;
+        let mut extra = self.growth.extra_slots(self.file_slots).max(min);
+        if let Some(max) = self.limits.max_blocks {
+            extra = extra.min(max.saturating_sub(self.file_slots)).max(min);
+        }
+        if let Some(max) = self.limits.max_file_bytes {
+            let max_slots = max.saturating_sub(self.data_start as u64) / slot_size;
+            extra = extra.min(max_slots.saturating_sub(self.file_slots)).max(min);
+        }
+        let length = self.start_pos(self.file_slots + extra) as u64;
+        limit::check("data file size", length, self.limits.max_file_bytes)
+            .map_err(|err| err.with_path(&self.file_path))?;
+        /*
+        ** 持有目录配额的锁直到文件扩展完成
+        */
+        let guard = match (&self.quota, self.limits.max_dir_bytes) {
+            (Some(quota), Some(_)) => Some(quota.lock().map_err(|err| err.with_path(&self.file_path))?),
+            _ => None
+        };
+        if let Some(guard) = &guard {
+            if extra > min && guard.check(extra * slot_size, self.limits.max_dir_bytes).is_err() {
+                /*
+                ** 目录配额不足以预分配 => 只扩展需要的块
+                */
+                extra = min;
+            }
+            guard.check(extra * slot_size, self.limits.max_dir_bytes)
+                .map_err(|err| err.with_path(&self.file_path))?;
+        }
+        let length = self.start_pos(self.file_slots + extra) as u64;
+        self.pager.set_len(length)?;
+        drop(guard);
+        self.file_slots += extra;
+        Ok(())
+    }
+
+    /*
+    ** 检查块可以释放, duplicate 表示同一批中已经释放过
+    */
+    fn check_release(&self, id: BlockId, duplicate: bool) -> Result<BlockHeader> {
+        let mut block = self.block(id)?;
+        let block_header = block.get_block_header()?;
+        if duplicate || block_header.freed {
+            return Err(Error::new(Code::DoubleFreeError)
+                .with_message(format!("block {} is already free", id))
+                .with_path(&self.file_path)
+                .with_offset(block.start_pos as u64));
+        }
+        Ok(block_header)
+    }
+
+    /*
+    ** 释放 id 时需要释放的块: 记录的第一个块连同续块, 续块不能单独释放
+    **  seen: 同一批中已经释放的块
+    */
+    fn release_ids(&self, id: BlockId, seen: &mut HashSet<BlockId>) -> Result<Vec<BlockId>> {
+        let block_header = self.check_release(id, !seen.insert(id))?;
+        if block_header.is_continuation() {
+            return Err(Error::new(Code::MismatchError)
+                .with_message(format!("block {} is a continuation of a record, free the record instead", id))
+                .with_path(&self.file_path)
+                .with_offset(self.start_pos(id) as u64));
+        }
+        let mut ids = vec![id];
+        if block_header.head && block_header.next != NO_NEXT {
+            for next in self.record_chain(block_header.next)? {
+                self.check_release(next, !seen.insert(next))?;
+                ids.push(next);
+            }
+        }
+        Ok(ids)
+    }
+
+    /*
+    ** 块必须是 write_record 写入的记录的第一个块
+    */
+    fn check_record(&self, id: BlockId) -> Result<()> {
+        let block_header = self.block(id)?.get_block_header()?;
+        if !block_header.head || block_header.freed {
+            return Err(Error::new(Code::MismatchError)
+                .with_message(format!("block {} is not the first block of a record", id))
+                .with_path(&self.file_path)
+                .with_offset(self.start_pos(id) as u64));
+        }
+        Ok(())
+    }
+
+    /*
+    ** 清除块头 / 索引, 按需打洞
+    */
+    fn release_block(&mut self, id: BlockId) -> Result<()> {
+        self.check_release(id, false)?;
+        let mut block = self.block(id)?;
+        block.update_block_header(&BlockHeader{
+            freed: true,
+            ..Default::default()
+        })?;
+        self.indexes.remove(id)?;
+        if self.punch_holes {
+            self.pager.punch_hole((block.start_pos + *BLOCK_HEADER_LENGTH) as u64, block.length as u64)?;
+        }
+        Ok(())
+    }
+
+    /*
+    ** 批量释放已经检查过的块: 清除索引, 按需打洞, 然后依次提交块头以及删除记录
+    */
+    fn release_blocks(&mut self, ids: &[BlockId]) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let free_writes = self.stage_push_free(ids)?;
+        for id in ids.iter() {
+            self.indexes.remove(*id)?;
+            if self.punch_holes {
+                self.pager.punch_hole((self.start_pos(*id) + *BLOCK_HEADER_LENGTH) as u64, self.fixed_size as u64)?;
+            }
+        }
+        let block_header_vec = BlockHeader{
+            freed: true,
+            ..Default::default()
+        }.to_vec()?;
+        let mut stages: Vec<Vec<WriteOp>> = vec![ids.iter()
+            .map(|id| WriteOp::data(self.start_pos(*id) as u64, block_header_vec.as_slice()))
+            .collect()];
+        stages.append(&mut self.free_stages(&free_writes));
+        self.pager.submit(&stages)
+    }
+
+    fn new_block_at(&self, id: BlockId) -> Block {
+        Block::new(self.file_path.clone(), id, self.start_pos(id), self.fixed_size, self.pager.clone(), self.indexes.clone())
+    }
+
+    /*
+    ** 分配 n 个块, 还没有写入: 先从删除记录中取出最多 n 个位置, 不足的部分从高水位处连续分配, 需要时扩展文件
+    **  扩展失败时撤销取出的位置
+    */
+    fn allocate(&mut self, n: usize) -> Result<Allocation> {
+        let free_top = match &mut self.free_space {
+            FreeSpace::Stack(delete) => Some(delete.top_pos()?),
+            FreeSpace::Bitmap(_) | FreeSpace::External => None
+        };
+        let (mut ids, free_writes) = self.stage_pop_free(n)?;
+        let popped = ids.len();
+        let rest = (n - popped) as u64;
+        if rest > 0 {
+            if let Err(err) = self.reserve_high_water(rest) {
+                self.cancel_pop(&ids);
+                return Err(err);
+            }
+        }
+        ids.extend(self.high_water..self.high_water + rest);
+        Ok(Allocation{
+            ids,
+            popped,
+            free_top,
+            free_writes,
+            high_water: self.high_water + rest
+        })
+    }
+
+    /*
+    ** 提交分配: 1. 删除记录 2. writes (分配的块的块头以及内容) 3. 文件头 (高水位)
+    **  提交失败时撤销, 取出的位置不会丢失
+    */
+    fn commit(&mut self, allocation: Allocation, writes: Vec<WriteOp>) -> Result<Vec<BlockId>> {
+        let file_header_vec = FileHeader::new(self.fixed_size, allocation.high_water, self.options).to_vec()?;
+        let submitted = {
+            let mut stages = self.free_stages(&allocation.free_writes);
+            stages.push(writes);
+            if allocation.high_water != self.high_water {
+                stages.push(vec![WriteOp::data(0, file_header_vec.as_slice())]);
+            }
+            self.pager.submit(&stages)
+        };
+        if let Err(err) = submitted {
+            let _ = self.rollback(&allocation);
+            return Err(err);
+        }
+        self.high_water = allocation.high_water;
+        Ok(allocation.ids)
+    }
+
+    /*
+    ** 撤销提交失败的分配: 取出的位置放回删除记录, 然后重新标记为已释放, 文件头恢复为原来的高水位
+    **  删除栈的写入可能已经完成, 栈顶变化时才放回
+    */
+    fn rollback(&mut self, allocation: &Allocation) -> Result<()> {
+        let popped = &allocation.ids[..allocation.popped];
+        let positions: Vec<stack::Pos> = popped.iter().rev().map(|id| {
+            stack::Pos::new(self.file_path.clone(), self.start_pos(*id), self.fixed_size)
+        }).collect();
+        match &mut self.free_space {
+            FreeSpace::Stack(delete) => {
+                if Some(delete.top_pos()?) != allocation.free_top {
+                    delete.push_many(positions)?;
+                }
+            }
+            FreeSpace::Bitmap(bitmap) => {
+                bitmap.set_free(popped)?;
+            }
+            FreeSpace::External => {}
+        }
+        let block_header_vec = BlockHeader{
+            freed: true,
+            ..Default::default()
+        }.to_vec()?;
+        let file_header_vec = FileHeader::new(self.fixed_size, self.high_water, self.options).to_vec()?;
+        let mut writes: Vec<WriteOp> = popped.iter()
+            .map(|id| WriteOp::data(self.start_pos(*id) as u64, block_header_vec.as_slice()))
+            .collect();
+        writes.push(WriteOp::data(0, file_header_vec.as_slice()));
+        self.pager.submit(&[writes])
+    }
+
+    /*
+    ** 从删除记录中取出最多 n 个空闲块, 返回块序号以及删除记录需要的写入
+    **  Bitmap 立即修改内存中的位图, Stack 在写入之后才移除
+    */
+    fn stage_pop_free(&mut self, n: usize) -> Result<(Vec<BlockId>, Staged)> {
+        match &mut self.free_space {
+            FreeSpace::Stack(delete) => {
+                let (positions, writes) = delete.stage_pop_many(n)?;
+                Ok((positions.iter().map(|pos| self.block_id(pos.start_pos)).collect(), writes))
+            }
+            FreeSpace::Bitmap(bitmap) => bitmap.stage_take_lowest(n),
+            FreeSpace::External => Ok((Vec::new(), Vec::new()))
+        }
+    }
+
+    /*
+    ** 撤销没有提交的 stage_pop_free
+    */
+    fn cancel_pop(&mut self, ids: &[BlockId]) {
+        if let FreeSpace::Bitmap(bitmap) = &mut self.free_space {
+            let _ = bitmap.set_free(ids);
+        }
+    }
+
+    /*
+    ** 将空闲块放入删除记录需要的写入
+    */
+    fn stage_push_free(&mut self, ids: &[BlockId]) -> Result<Staged> {
+        let positions: Vec<stack::Pos> = match &self.free_space {
+            FreeSpace::Stack(_) => ids.iter().map(|id| {
+                stack::Pos::new(self.file_path.clone(), self.start_pos(*id), self.fixed_size)
+            }).collect(),
+            FreeSpace::Bitmap(_) | FreeSpace::External => Vec::new()
+        };
+        match &mut self.free_space {
+            FreeSpace::Stack(delete) => delete.stage_push_many(&positions),
+            FreeSpace::Bitmap(bitmap) => Ok(bitmap.stage_set_free(ids)?.1),
+            FreeSpace::External => Err(Error::new(Code::NotImplement)
+                .with_message(format!("free positions of {} are kept by its owner", self.name))
+                .with_path(&self.file_path))
+        }
+    }
+
+    /*
+    ** 删除记录的写入, 每个写入一个阶段, 按顺序完成
+    */
+    fn free_stages<'a>(&'a self, writes: &'a Staged) -> Vec<Vec<WriteOp<'a>>> {
+        let (file, path) = match &self.free_space {
+            FreeSpace::Stack(delete) => delete.file(),
+            FreeSpace::Bitmap(bitmap) => bitmap.file(),
+            FreeSpace::External => return Vec::new()
+        };
+        writes.iter().map(|(offset, content)| vec![WriteOp::to(file, path, *offset, content)]).collect()
+    }
+
+    /*
+    ** 检查块数量限制, 高水位向后移动 n 个块需要时扩展文件, 不修改高水位
+    */
+    fn reserve_high_water(&mut self, n: u64) -> Result<()> {
+        limit::check("block count", self.high_water + n, self.limits.max_blocks)
+            .map_err(|err| err.with_path(&self.file_path))?;
+        if self.high_water + n > self.file_slots {
+            self.grow(self.high_water + n - self.file_slots)?;
+        }
+        Ok(())
+    }
+
+    /*
+    ** 释放块但不放入删除记录, 由外部的删除记录管理该位置
+    */
+    pub(crate) fn retire_block(&mut self, id: BlockId) -> Result<stack::Pos> {
+        self.release_block(id)?;
+        Ok(stack::Pos::new(self.file_path.clone(), self.start_pos(id), self.fixed_size))
+    }
+
+    /*
+    ** 重新使用外部删除记录中的位置, 清除释放标记
+    */
+    pub(crate) fn reclaim_block(&mut self, start_pos: usize) -> Result<Block> {
+        let id = self.block_id(start_pos);
+        let mut block = self.block(id)?;
+        if !block.get_block_header()?.freed {
+            return Err(Error::new(Code::DeserdeError)
+                .with_message(format!("block {} in the delete record is not free", id))
+                .with_path(&self.file_path)
+                .with_offset(start_pos as u64));
+        }
+        block.update_block_header(&BlockHeader::default())?;
+        Ok(block)
+    }
+
+    /*
+    ** 与同一个 name 目录中的其他 Fixed 共享目录使用量
+    */
+    pub(crate) fn set_dir_quota(&mut self, quota: DirQuota) {
+        self.quota = Some(quota);
+    }
+
+    fn get_file_size(&self) -> Result<usize> {
+        Ok(self.pager.len()? as usize)
+    }
+
+    /*
+    ** 记录经过的所有块, 链的长度超过块的数量说明存在环
+    */
+    fn record_chain(&self, id: BlockId) -> Result<Vec<BlockId>> {
+        let block_count = self.block_count()?;
+        let mut chain: Vec<BlockId> = Vec::new();
+        let mut next = id;
+        while next != NO_NEXT {
+            if chain.len() as u64 >= block_count {
+                return Err(Error::new(Code::DeserdeError)
+                    .with_message(format!("record {} has a cyclic chain", id))
+                    .with_path(&self.file_path)
+                    .with_offset(self.start_pos(next) as u64));
+            }
+            chain.push(next);
+            next = self.block(next)?.get_block_header()?.next;
+        }
+        Ok(chain)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::multifile::run_test::TestDir;
+    use crate::multifile::pager::WritePolicy;
+
+    #[derive(serde_derive::Serialize, serde_derive::Deserialize, Debug, PartialEq)]
+    struct User {
+        id: u64,
+        status: u8
+    }
+
+    #[test]
+    fn cached_header_test() {
+        let dir = TestDir::new("cached_header_test");
+        let mut fixed = Fixed::new("user", 32, &dir).unwrap();
+        fixed.set_cache(Some(CacheConfig::new(1024, WritePolicy::WriteBack))).unwrap();
+        let mut block = fixed.new_block().unwrap();
+        block.update_header(User{ id: 7, status: 1 }).unwrap();
+        for _ in 0..3 {
+            assert_eq!(block.header::<User>().unwrap(), User{ id: 7, status: 1 });
+        }
+        let stats = fixed.cache_stats().unwrap().unwrap();
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.dirty_blocks, 1);
+        fixed.sync().unwrap();
+        assert_eq!(fixed.cache_stats().unwrap().unwrap().dirty_blocks, 0);
+        drop(block);
+        drop(fixed);
+        let fixed = Fixed::open("user", &dir).unwrap();
+        assert_eq!(fixed.block(0).unwrap().header::<User>().unwrap(), User{ id: 7, status: 1 });
+    }
+
+    #[test]
+    fn block_cursor_test() {
+        use std::io::{Read, Write, Seek, SeekFrom};
+        let dir = TestDir::new("block_cursor_test");
+        let mut fixed = Fixed::new("user", 32, &dir).unwrap();
+        let mut first = fixed.new_block().unwrap();
+        let mut second = fixed.new_block().unwrap();
+        second.update_header(User{ id: 2, status: 0 }).unwrap();
+        first.update_header(User{ id: 1, status: 0 }).unwrap();
+        let mut cursor = first.cursor().unwrap();
+        assert_eq!(cursor.capacity(), 32 - 9);
+        cursor.write_all(b"payload").unwrap();
+        /*
+        ** 越界写入被拒绝, 下一个块不受影响
+        */
+        assert!(cursor.write(&[0xff; 17]).is_err());
+        assert_eq!(cursor.position(), 7);
+        assert!(cursor.seek(SeekFrom::End(1)).is_err());
+        cursor.seek(SeekFrom::Start(0)).unwrap();
+        let mut content = Vec::new();
+        cursor.read_to_end(&mut content).unwrap();
+        assert_eq!(content.len(), 23);
+        assert_eq!(&content[..7], b"payload");
+        assert_eq!(second.header::<User>().unwrap(), User{ id: 2, status: 0 });
+        /*
+        ** 配合序列化库使用
+        */
+        let mut cursor = second.cursor().unwrap();
+        bincode::serialize_into(&mut cursor, &(3u32, String::from("abc"))).unwrap();
+        cursor.seek(SeekFrom::Start(0)).unwrap();
+        let value: (u32, String) = bincode::deserialize_from(&mut cursor).unwrap();
+        assert_eq!(value, (3, String::from("abc")));
+    }
+
+    #[test]
+    fn record_chain_test() {
+        let dir = TestDir::new("record_chain_test");
+        let mut fixed = Fixed::new("event", 16, &dir).unwrap();
+        let small = fixed.write_record(b"hello").unwrap();
+        let content: Vec<u8> = (0..40).collect();
+        let large = fixed.write_record(&content).unwrap();
+        let empty = fixed.write_record(&[]).unwrap();
+        assert_eq!(fixed.block_count().unwrap(), 5);
+        assert_eq!(fixed.read_record(small).unwrap(), b"hello");
+        assert_eq!(fixed.read_record(large).unwrap(), content);
+        assert!(fixed.read_record(empty).unwrap().is_empty());
+        /*
+        ** 释放之后的块被重新使用
+        */
+        fixed.free_record(large).unwrap();
+        let again = fixed.write_record(&content[..32]).unwrap();
+        assert_eq!(fixed.read_record(again).unwrap(), &content[..32]);
+        assert_eq!(fixed.block_count().unwrap(), 5);
+        /*
+        ** 记录的块没有业务header
+        */
+        assert_eq!(fixed.block(small).unwrap().update_header(1u64).err().unwrap().code(), Code::MismatchError);
+        assert_eq!(fixed.read_record(small).unwrap(), b"hello");
+        /*
+        ** 块不够时不分配任何块
+        */
+        fixed.free_record(again).unwrap();
+        fixed.set_limits(Limits::new().with_max_blocks(5)).unwrap();
+        assert!(fixed.write_record(&[1u8; 100]).err().unwrap().is_limit());
+        assert_eq!(fixed.block_count().unwrap(), 5);
+        let again = fixed.write_record(&content).unwrap();
+        assert_eq!(fixed.read_record(again).unwrap(), content);
+        /*
+        ** 重置记录的第一个块时释放续块
+        */
+        let chain = fixed.record_chain(again).unwrap();
+        assert_eq!(chain.len(), 3);
+        fixed.reset_block(again).unwrap();
+        assert_eq!(fixed.read_record(again).err().unwrap().code(), Code::MismatchError);
+        assert!(fixed.free_block(chain[1]).err().unwrap().is_invalid_free());
+        assert!(fixed.free_block(chain[2]).err().unwrap().is_invalid_free());
+        /*
+        ** 只能通过记录的第一个块读取 / 释放记录; 释放第一个块时连同续块一起释放
+        */
+        fixed.set_limits(Limits::new()).unwrap();
+        let plain = fixed.new_block().unwrap().id();
+        assert_eq!(fixed.block(plain).unwrap().get_block_header().unwrap().next, NO_NEXT);
+        assert_eq!(fixed.read_record(plain).err().unwrap().code(), Code::MismatchError);
+        assert_eq!(fixed.free_record(plain).err().unwrap().code(), Code::MismatchError);
+        let record = fixed.write_record(&content[..20]).unwrap();
+        let chain = fixed.record_chain(record).unwrap();
+        assert_eq!(fixed.read_record(chain[1]).err().unwrap().code(), Code::MismatchError);
+        assert_eq!(fixed.free_block(chain[1]).err().unwrap().code(), Code::MismatchError);
+        fixed.free_block(record).unwrap();
+        assert!(fixed.free_block(chain[1]).err().unwrap().is_invalid_free());
+        assert_eq!(fixed.read_record(small).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn secondary_index_test() {
+        let dir = TestDir::new("secondary_index_test");
+        let mut fixed = Fixed::new("user", 32, &dir).unwrap();
+        let mut ids = Vec::new();
+        for i in 0..6u64 {
+            let mut block = fixed.new_block().unwrap();
+            block.update_header(User{id: i, status: (i % 2) as u8}).unwrap();
+            ids.push(block.id());
+        }
+        /*
+        ** 注册时通过全量扫描建立索引
+        */
+        fixed.register_index("status", |user: &User| Some(user.status)).unwrap();
+        assert!(fixed.register_index("status", |user: &User| Some(user.status)).unwrap_err().code() == Code::ExistsError);
+        assert_eq!(fixed.index_lookup("status", &1u8).unwrap(), vec![ids[1], ids[3], ids[5]]);
+        /*
+        ** update_header / free_block 自动维护
+        */
+        fixed.block(ids[1]).unwrap().update_header(User{id: 1, status: 0}).unwrap();
+        fixed.free_block(ids[3]).unwrap();
+        assert_eq!(fixed.index_lookup("status", &1u8).unwrap(), vec![ids[5]]);
+        assert_eq!(fixed.index_lookup("status", &0u8).unwrap(), vec![ids[0], ids[1], ids[2], ids[4]]);
+        assert!(fixed.index_lookup("missing", &0u8).unwrap_err().is_not_found());
+        fixed.rebuild_indexes().unwrap();
+        assert_eq!(fixed.index_lookup("status", &1u8).unwrap(), vec![ids[5]]);
+    }
+
+    #[test]
+    fn growth_test() {
+        let dir = TestDir::new("growth_test");
+        let mut fixed = Fixed::new("user", 32, &dir).unwrap();
+        fixed.set_growth(Growth::Slots(8));
+        for i in 0..3 {
+            assert_eq!(fixed.new_block().unwrap().id(), i);
+        }
+        let slot_size = fixed.slot_size() as u64;
+        let file_size = || fs::metadata(dir.join("user")).unwrap().len();
+        assert_eq!(fixed.block_count().unwrap(), 3);
+        assert_eq!(file_size(), *FILE_HEADER_LENGTH as u64 + 8 * slot_size);
+        /*
+        ** 重新打开之后从高水位继续分配, 预分配的块不计入块数量
+        */
+        drop(fixed);
+        let mut fixed = Fixed::open("user", &dir).unwrap();
+        assert_eq!(fixed.block_count().unwrap(), 3);
+        assert!(fixed.block(3).err().unwrap().is_not_found());
+        fixed.set_growth(Growth::Percent(50));
+        for i in 3..9 {
+            assert_eq!(fixed.new_block().unwrap().id(), i);
+        }
+        assert_eq!(file_size(), *FILE_HEADER_LENGTH as u64 + 12 * slot_size);
+    }
+
+    #[test]
+    fn punch_hole_test() {
+        let dir = TestDir::new("punch_hole_test");
+        let mut fixed = Fixed::new("blob", 64 * 1024, &dir).unwrap();
+        fixed.set_cache(Some(CacheConfig::new(1024 * 1024, WritePolicy::WriteBack))).unwrap();
+        fixed.set_punch_holes(true);
+        let ids: Vec<BlockId> = (0..4).map(|_| fixed.new_block().unwrap().id()).collect();
+        for id in ids.iter() {
+            fixed.write_payload(*id, &vec![0xffu8; 60 * 1024]).unwrap();
+        }
+        fixed.free_block(ids[1]).unwrap();
+        fixed.flush().unwrap();
+        /*
+        ** 释放的块重新使用时内容为 0, 其他块不受影响
+        */
+        let reused = fixed.new_block().unwrap().id();
+        assert_eq!(reused, ids[1]);
+        let mut content = Vec::new();
+        fixed.block(reused).unwrap().cursor().unwrap().read_to_end(&mut content).unwrap();
+        assert!(content.iter().all(|b| *b == 0));
+        assert_eq!(fixed.read_payload::<Vec<u8>>(ids[2]).unwrap(), vec![0xffu8; 60 * 1024]);
+    }
+
+    #[test]
+    fn batch_block_test() {
+        let dir = TestDir::new("batch_block_test");
+        let mut fixed = Fixed::new("user", 32, &dir).unwrap();
+        let ids: Vec<BlockId> = fixed.new_blocks(100).unwrap().iter().map(|b| b.id()).collect();
+        assert_eq!(ids, (0..100).collect::<Vec<BlockId>>());
+        assert_eq!(fs::metadata(dir.join("user")).unwrap().len() as usize, *FILE_HEADER_LENGTH + 100 * fixed.slot_size());
+        fixed.free_blocks(vec![10, 20, 30]).unwrap();
+        /*
+        ** 先使用删除栈中的块, 再从高水位分配
+        */
+        let mut reused: Vec<BlockId> = fixed.new_blocks(5).unwrap().iter().map(|b| b.id()).collect();
+        reused.sort();
+        assert_eq!(reused, vec![10, 20, 30, 100, 101]);
+        assert!(fixed.new_blocks(0).unwrap().is_empty());
+        /*
+        ** 超出限制时取出的位置放回删除栈
+        */
+        fixed.free_blocks(vec![1, 2]).unwrap();
+        fixed.set_limits(Limits::new().with_max_blocks(102)).unwrap();
+        assert!(fixed.new_blocks(3).err().unwrap().is_limit());
+        /*
+        ** 放回的位置仍然是已释放状态, 不能再次释放
+        */
+        assert!(fixed.free_block(1).err().unwrap().is_invalid_free());
+        assert_eq!(fixed.new_blocks(2).unwrap().len(), 2);
+        assert_eq!(fixed.block_count().unwrap(), 102);
+        /*
+        ** 写入块头失败时, 已经从删除栈取出的位置放回删除栈
+        */
+        fixed.free_blocks(vec![3, 4]).unwrap();
+        let path = dir.join("user");
+        let pager = fixed.pager.clone();
+        let readonly = fs::File::open(&path).unwrap();
+        fixed.pager = Pager::new(readonly, path.to_str().unwrap().to_string(), fixed.data_start as u64, fixed.slot_size() as u64);
+        assert_eq!(fixed.new_blocks(2).err().unwrap().code(), Code::FileWriteError);
+        fixed.pager = pager;
+        assert!(fixed.free_block(3).err().unwrap().is_invalid_free());
+        let mut reused: Vec<BlockId> = fixed.new_blocks(2).unwrap().iter().map(|b| b.id()).collect();
+        reused.sort();
+        assert_eq!(reused, vec![3, 4]);
+        assert_eq!(fixed.block_count().unwrap(), 102);
+    }
+
+    #[test]
+    fn bitmap_allocator_test() {
+        let dir = TestDir::new("bitmap_allocator_test");
+        let mut fixed = Fixed::with_allocator("user", 32, Allocator::Bitmap, &dir).unwrap();
+        fixed.new_blocks(20).unwrap();
+        fixed.free_blocks(vec![15, 3, 4, 5, 9]).unwrap();
+        assert!(fixed.is_free(4).unwrap());
+        assert!(!fixed.is_free(6).unwrap());
+        assert_eq!(fixed.free_count().unwrap(), 5);
+        /*
+        ** 重新打开之后按地址从低到高分配, 连续分配使用最低的连续空闲块
+        */
+        drop(fixed);
+        let mut fixed = Fixed::open("user", &dir).unwrap();
+        assert_eq!(fixed.allocator(), Allocator::Bitmap);
+        let run: Vec<BlockId> = fixed.new_run(3).unwrap().iter().map(|b| b.id()).collect();
+        assert_eq!(run, vec![3, 4, 5]);
+        assert_eq!(fixed.new_block().unwrap().id(), 9);
+        assert_eq!(fixed.new_run(2).unwrap()[0].id(), 20);
+        assert_eq!(fixed.free_count().unwrap(), 1);
+        assert!(matches!(Fixed::with_allocator("user", 32, Allocator::Stack, &dir), Err(err) if err.code() == Code::MismatchError));
+        drop(fixed);
+        assert_eq!(Fixed::new("user", 32, &dir).unwrap().allocator(), Allocator::Bitmap);
+        assert!(Fixed::new("order", 32, &dir).unwrap().is_free(0).err().unwrap().code() == Code::NotImplement);
+    }
+
+    #[test]
+    fn double_free_test() {
+        let dir = TestDir::new("double_free_test");
+        let mut fixed = Fixed::new("user", 32, &dir).unwrap();
+        fixed.new_blocks(4).unwrap();
+        fixed.free_block(1).unwrap();
+        assert!(fixed.free_block(1).err().unwrap().is_invalid_free());
+        /*
+        ** 批量释放中的重复块, 之前的块仍然放入删除栈
+        */
+        assert!(fixed.free_blocks(vec![2, 2]).err().unwrap().is_invalid_free());
+        let mut reused: Vec<BlockId> = fixed.new_blocks(3).unwrap().iter().map(|b| b.id()).collect();
+        reused.sort();
+        assert_eq!(reused, vec![1, 2, 4]);
+        /*
+        ** 重新分配之后可以再次释放
+        */
+        fixed.free_block(1).unwrap();
+        let mut delete = stack::Delete::with_owner(dir.join("owner_delete.rd"), "user").unwrap();
+        let foreign = stack::Pos{
+            path: "order".to_string(),
+            start_pos: 0,
+            length: 32
+        };
+        assert!(delete.push(foreign).err().unwrap().is_invalid_free());
+    }
+
+    #[test]
+    fn file_header_version_test() {
+        let dir = TestDir::new("file_header_version_test");
+        Fixed::new("user", 16, &dir).unwrap().new_block().unwrap();
+        let path = dir.join("user");
+        let mut content = fs::read(&path).unwrap();
+        /*
+        ** 未知的版本
+        */
+        content[4..8].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        fs::write(&path, &content).unwrap();
+        assert_eq!(Fixed::open("user", &dir).err().unwrap().code(), Code::VersionError);
+        /*
+        ** 不是 Fixed 文件
+        */
+        content[0..4].copy_from_slice(&0u32.to_le_bytes());
+        fs::write(&path, &content).unwrap();
+        assert_eq!(Fixed::open("user", &dir).err().unwrap().code(), Code::MismatchError);
+        content[0..8].copy_from_slice(&[FIXED_MAGIC.to_le_bytes(), FORMAT_VERSION.to_le_bytes()].concat());
+        fs::write(&path, &content).unwrap();
+        assert_eq!(Fixed::open("user", &dir).unwrap().block_count().unwrap(), 1);
+    }
+
+    #[test]
+    fn io_backend_test() {
+        let dir = TestDir::new("io_backend_test");
+        let mut fixed = Fixed::new("user", 16, &dir).unwrap();
+        let result = fixed.set_io_backend(IoBackend::Uring);
+        if cfg!(all(feature = "io_uring", target_os = "linux")) {
+            result.unwrap();
+            assert_eq!(fixed.io_backend().unwrap(), IoBackend::Uring);
+            /*
+            ** 不能与缓存同时开启
+            */
+            let config = CacheConfig::new(1024, WritePolicy::WriteBack);
+            assert_eq!(fixed.set_cache(Some(config)).err().unwrap().code(), Code::MismatchError);
+            fixed.set_io_backend(IoBackend::Std).unwrap();
+            fixed.set_cache(Some(config)).unwrap();
+            assert_eq!(fixed.set_io_backend(IoBackend::Uring).err().unwrap().code(), Code::MismatchError);
+            fixed.set_cache(None).unwrap();
+            fixed.set_io_backend(IoBackend::Uring).unwrap();
+        } else {
+            assert_eq!(result.err().unwrap().code(), Code::NotImplement);
+            assert_eq!(fixed.io_backend().unwrap(), IoBackend::Std);
+        }
+        /*
+        ** 记录的内容以及块头批量写入
+        */
+        let content: Vec<u8> = (0..100u8).collect();
+        let id = fixed.write_record(&content).unwrap();
+        assert_eq!(fixed.read_record(id).unwrap(), content);
+        fixed.free_record(id).unwrap();
+        let id = fixed.write_record(&content[..40]).unwrap();
+        assert_eq!(fixed.read_record(id).unwrap(), &content[..40]);
+        assert_eq!(fixed.block_count().unwrap(), 7);
+        drop(fixed);
+        let fixed = Fixed::open("user", &dir).unwrap();
+        assert_eq!(fixed.read_record(id).unwrap(), &content[..40]);
+        drop(fixed);
+        /*
+        ** 位图删除记录的写入也在同一次提交中
+        */
+        let mut fixed = Fixed::with_allocator("order", 16, Allocator::Bitmap, &dir).unwrap();
+        let _ = fixed.set_io_backend(IoBackend::Uring);
+        let ids: Vec<BlockId> = fixed.new_blocks(10).unwrap().iter().map(|block| block.id).collect();
+        fixed.free_blocks(ids[2..6].to_vec()).unwrap();
+        drop(fixed);
+        let mut fixed = Fixed::open("order", &dir).unwrap();
+        let _ = fixed.set_io_backend(IoBackend::Uring);
+        assert_eq!(fixed.new_blocks(5).unwrap().iter().map(|block| block.id).collect::<Vec<_>>(), vec![2, 3, 4, 5, 10]);
+    }
+
+    #[test]
+    fn aligned_layout_test() {
+        let dir = TestDir::new("aligned_layout_test");
+        let options = FixedOptions::new().with_alignment(4096);
+        let mut fixed = Fixed::with_options("user", 1000, options, &dir).unwrap();
+        fixed.new_blocks(3).unwrap();
+        assert_eq!(fs::metadata(dir.join("user")).unwrap().len(), 4096 * 4);
+        assert_eq!(slots_within(1000, 4096, 4096 * 4), 3);
+        assert_eq!(slots_within(1000, 4096, 4096 * 4 - 1), 2);
+        assert_eq!(fixed.block(2).unwrap().start_pos % 4096, 0);
+        /*
+        ** 直接 I/O 下不对齐的读写经过对齐区域
+        */
+        if cfg!(target_os = "linux") {
+            fixed.set_direct_io(true).unwrap();
+        }
+        let mut block = fixed.block(1).unwrap();
+        block.update_header(User{ id: 9, status: 1 }).unwrap();
+        fixed.write_payload(1, &vec![7u8; 500]).unwrap();
+        let id = fixed.write_record(&[3u8; 2500]).unwrap();
+        assert_eq!(block.header::<User>().unwrap(), User{ id: 9, status: 1 });
+        assert_eq!(fixed.read_payload::<Vec<u8>>(1).unwrap(), vec![7u8; 500]);
+        assert_eq!(fixed.read_record(id).unwrap(), vec![3u8; 2500]);
+        drop(block);
+        drop(fixed);
+        let fixed = Fixed::open("user", &dir).unwrap();
+        assert_eq!(fixed.alignment(), 4096);
+        assert_eq!(fixed.block_count().unwrap(), 6);
+        assert_eq!(fixed.read_record(id).unwrap(), vec![3u8; 2500]);
+        assert_eq!(Fixed::with_options("user", 1000, FixedOptions::new(), &dir).err().unwrap().code(), Code::MismatchError);
+        drop(fixed);
+        assert_eq!(Fixed::new("user", 1000, &dir).unwrap().alignment(), 4096);
+        assert_eq!(Fixed::new("order", 32, &dir).unwrap().set_direct_io(true).err().unwrap().code(), Code::MismatchError);
+        let mut small = Fixed::with_options("small", 32, FixedOptions::new().with_alignment(64), &dir).unwrap();
+        assert_eq!(small.set_direct_io(true).err().unwrap().code(), Code::MismatchError);
+        assert!(!small.direct_io());
+        assert!(Fixed::with_options("item", 32, FixedOptions::new().with_alignment(3), &dir).err().unwrap().is_limit());
+    }
+
+    #[test]
+    fn copy_block_test() {
+        let dir = TestDir::new("copy_block_test");
+        let mut from = Fixed::new("user", 32, &dir).unwrap();
+        from.set_cache(Some(CacheConfig::new(1024, WritePolicy::WriteBack))).unwrap();
+        let mut block = from.new_block().unwrap();
+        assert_eq!(block.id(), 0);
+        block.update_header(User{ id: 3, status: 2 }).unwrap();
+        from.write_payload(block.id(), &7u64).unwrap();
+        let content: Vec<u8> = (0..80u8).collect();
+        let record = from.write_record(&content).unwrap();
+        let chain = from.record_chain(record).unwrap();
+        /*
+        ** 目标使用自己的分配方式: 地址最低的空闲块先被使用
+        */
+        let mut to = Fixed::with_allocator("user_v2", 64, Allocator::Bitmap, &dir).unwrap();
+        to.new_blocks(4).unwrap();
+        to.free_blocks(vec![2, 0]).unwrap();
+        let id = from.copy_block_to(&mut to, block.id()).unwrap();
+        assert_eq!(id, 0);
+        assert_eq!(to.block(id).unwrap().header::<User>().unwrap(), User{ id: 3, status: 2 });
+        assert_eq!(to.read_payload::<u64>(id).unwrap(), 7);
+        let ids = from.copy_blocks_to(&mut to, &chain).unwrap();
+        assert_eq!(ids, vec![2, 4, 5]);
+        assert_eq!(to.read_record(ids[0]).unwrap(), content);
+        /*
+        ** 单个块复制时不保留续块
+        */
+        let single = from.copy_block_to(&mut to, record).unwrap();
+        assert_eq!(to.read_record(single).unwrap(), &content[..32]);
+        /*
+        ** 普通块的 next 不参与映射, 同时复制块 0 以及 next 为 0 的普通块不会形成记录
+        */
+        let plain = from.new_block().unwrap().id();
+        let zeroed = BlockHeader{ next: 0, ..Default::default() }.to_vec().unwrap();
+        from.pager.write_at(from.start_pos(plain) as u64, &zeroed).unwrap();
+        let copies = from.copy_blocks_to(&mut to, &[block.id(), plain]).unwrap();
+        assert_eq!(to.block(copies[1]).unwrap().get_block_header().unwrap().next, NO_NEXT);
+        for copy in copies.iter() {
+            assert_eq!(to.read_record(*copy).err().unwrap().code(), Code::MismatchError);
+            assert_eq!(to.free_record(*copy).err().unwrap().code(), Code::MismatchError);
+        }
+        to.free_blocks(copies).unwrap();
+        /*
+        ** 没有复制第一个块的续块成为新的记录
+        */
+        let tail = from.copy_block_to(&mut to, chain[2]).unwrap();
+        assert_eq!(to.read_record(tail).unwrap(), &content[64..]);
+        to.free_record(tail).unwrap();
+        assert!(to.copy_block_to(&mut from, id).err().unwrap().is_limit());
+        from.free_block(block.id()).unwrap();
+        assert!(from.copy_block_to(&mut to, block.id()).err().unwrap().is_not_found());
     }
 }
@@ -0,0 +1,151 @@
+/*
+** 已打开的 Fixed 的共享句柄, 以及 MultiFile 内部的句柄缓存
+*/
+use crate::{Result, Error, Code};
+use super::fixed::Fixed;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/*
+** 共享句柄, clone 之后指向同一个 Fixed
+*/
+#[derive(Clone)]
+pub struct FixedHandle {
+    inner: Arc<Mutex<Fixed>>
+}
+
+impl FixedHandle {
+    pub fn new(fixed: Fixed) -> FixedHandle {
+        FixedHandle{
+            inner: Arc::new(Mutex::new(fixed))
+        }
+    }
+
+    pub fn lock(&self) -> Result<MutexGuard<'_, Fixed>> {
+        self.inner.lock().map_err(|_| {
+            Error::new(Code::LockError).with_message("fixed handle is poisoned")
+        })
+    }
+
+    /*
+    ** 是否只有缓存持有该句柄, 并且没有存活的 Block / 游标共享数据文件
+    */
+    fn is_idle(&self) -> bool {
+        if Arc::strong_count(&self.inner) != 1 {
+            return false;
+        }
+        match self.inner.try_lock() {
+            Ok(fixed) => !fixed.is_shared(),
+            Err(_) => false
+        }
+    }
+}
+
+struct Entry {
+    handle: FixedHandle,
+    last_used: u64
+}
+
+/*
+** 按 (name, fixed_name) 缓存句柄, 超出 max_open 时淘汰最久未使用的空闲句柄
+**  被外部持有的句柄 (包括它创建的 Block) 不会被淘汰, 保证同一个文件只有一个 Fixed
+**  没有可以淘汰的句柄时返回 LimitError
+*/
+pub(crate) struct Registry {
+    max_open: usize,
+    tick: u64,
+    entries: HashMap<(String, String), Entry>
+}
+
+impl Registry {
+    pub(crate) fn new(max_open: usize) -> Registry {
+        Registry{
+            max_open,
+            tick: 0,
+            entries: HashMap::new()
+        }
+    }
+
+    pub(crate) fn get_or_open<F>(&mut self, name: &str, fixed_name: &str, open: F) -> Result<FixedHandle>
+        where F: FnOnce() -> Result<Fixed> {
+        self.tick += 1;
+        let key = (name.to_string(), fixed_name.to_string());
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.last_used = self.tick;
+            return Ok(entry.handle.clone());
+        }
+        while self.entries.len() >= self.max_open {
+            if !self.evict() {
+                return Err(Error::new(Code::LimitError)
+                    .with_message(format!("{} open fixed tables are all in use", self.entries.len())));
+            }
+        }
+        let handle = FixedHandle::new(open()?);
+        self.entries.insert(key, Entry{
+            handle: handle.clone(),
+            last_used: self.tick
+        });
+        Ok(handle)
+    }
+
+    pub(crate) fn remove(&mut self, name: &str, fixed_name: &str) -> Option<FixedHandle> {
+        let key = (name.to_string(), fixed_name.to_string());
+        self.entries.remove(&key).map(|entry| entry.handle)
+    }
+
+    /*
+    ** 移除所有空闲的句柄, 返回所有句柄 (包括仍然被持有的) 用于刷盘
+    **  被持有的句柄保留在缓存中, 之后打开时仍然返回同一个 Fixed
+    */
+    pub(crate) fn drain(&mut self) -> Vec<FixedHandle> {
+        let idle: Vec<(String, String)> = self.entries.iter()
+            .filter(|(_, entry)| entry.handle.is_idle())
+            .map(|(key, _)| key.clone())
+            .collect();
+        let mut handles: Vec<FixedHandle> = idle.iter()
+            .filter_map(|key| self.entries.remove(key))
+            .map(|entry| entry.handle)
+            .collect();
+        handles.extend(self.entries.values().map(|entry| entry.handle.clone()));
+        handles
+    }
+
+    /*
+    ** 句柄仍然被外部持有 (或者它创建的 Block 仍然存活)
+    */
+    pub(crate) fn is_busy(&self, name: &str, fixed_name: &str) -> bool {
+        let key = (name.to_string(), fixed_name.to_string());
+        match self.entries.get(&key) {
+            Some(entry) => !entry.handle.is_idle(),
+            None => false
+        }
+    }
+
+    /*
+    ** 所有缓存的句柄以及所在的 name 目录
+    */
+    pub(crate) fn handles(&self) -> Vec<(String, FixedHandle)> {
+        self.entries.iter()
+            .map(|((name, _), entry)| (name.clone(), entry.handle.clone()))
+            .collect()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn evict(&mut self) -> bool {
+        let key = self.entries.iter()
+            .filter(|(_, entry)| entry.handle.is_idle())
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| key.clone());
+        match key {
+            Some(key) => {
+                self.entries.remove(&key);
+                true
+            },
+            None => false
+        }
+    }
+}
@@ -0,0 +1,491 @@
+/*
+** 保存在 Fixed 块中的线性哈希表
+**  每个桶占用一个块, 桶放满时通过 overflow 串联溢出桶
+**  插入时产生溢出桶 => 分裂 split 指向的桶, split 走完一轮之后 level 加一
+**  桶的目录 (桶号 -> 块序号) 保存在串联的目录页中, 分裂时只追加到最后一页; 打开时读入内存, 查找不需要读取目录
+**  目录只追加, 已有的桶号不会改变: 每次操作在 Fixed 的锁内比较桶数量, 其它 HashIndex 分裂之后重新读入目录
+**  元信息保存在第 0 个块的 header 中
+**  空的溢出桶以及分裂时回收的溢出桶通过 Fixed::free_block 放入 stack::Delete
+*/
+use crate::{Result, Error, Code};
+use super::fixed::{Fixed, BlockId};
+use super::handle::FixedHandle;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_derive::{Serialize, Deserialize};
+
+use std::marker::PhantomData;
+use std::sync::{Mutex, MutexGuard};
+
+const META_ID: BlockId = 0;
+const NO_BUCKET: BlockId = BlockId::MAX;
+/*
+** 初始桶数量
+*/
+const INITIAL_BUCKETS: u64 = 4;
+
+#[derive(Serialize, Deserialize)]
+struct Meta {
+    level: u32,
+    split: u64,
+    count: u64,
+    /*
+    ** 第一个目录页
+    */
+    directory: BlockId
+}
+
+/*
+** 目录页, 通过 next 串联
+*/
+#[derive(Serialize, Deserialize)]
+struct DirPage {
+    buckets: Vec<BlockId>,
+    next: BlockId
+}
+
+/*
+** 内存中的目录: 桶号 -> 块序号, 以及所有目录页
+*/
+struct Directory {
+    buckets: Vec<BlockId>,
+    pages: Vec<BlockId>
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(bound(serialize = "K: Serialize", deserialize = "K: DeserializeOwned"))]
+struct Bucket<K> {
+    entries: Vec<(K, BlockId)>,
+    overflow: BlockId
+}
+
+impl<K> Bucket<K> {
+    fn new(entries: Vec<(K, BlockId)>) -> Bucket<K> {
+        Bucket{
+            entries,
+            overflow: NO_BUCKET
+        }
+    }
+}
+
+/*
+** FNV-1a, 结果不随 rust 版本变化, 可以保存到磁盘
+*/
+fn fnv1a(content: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in content.iter() {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+fn fits<T: Serialize>(fixed: &Fixed, value: &T) -> Result<bool> {
+    match bincode::serialized_size(value) {
+        Ok(size) => Ok(size as usize <= fixed.fixed_size()),
+        Err(err) => Err(Error::bincode(Code::SerdeError, err))
+    }
+}
+
+pub struct HashIndex<K> {
+    fixed: FixedHandle,
+    directory: Mutex<Directory>,
+    marker: PhantomData<fn() -> K>
+}
+
+impl<K: Serialize + DeserializeOwned + Eq> HashIndex<K> {
+    /*
+    ** 打开哈希表, Fixed 为空时创建元信息块以及初始桶
+    */
+    pub fn open(fixed: FixedHandle) -> Result<HashIndex<K>> {
+        let directory = {
+            let mut f = fixed.lock()?;
+            if f.block_count()? == 0 {
+                let mut meta = f.new_block()?;
+                let mut buckets: Vec<BlockId> = Vec::new();
+                for _ in 0..INITIAL_BUCKETS {
+                    let id = f.new_block()?.id();
+                    f.write_payload(id, &Bucket::<K>::new(Vec::new()))?;
+                    buckets.push(id);
+                }
+                let page = DirPage{
+                    buckets,
+                    next: NO_BUCKET
+                };
+                if !fits(&f, &page)? {
+                    return Err(Error::new(Code::LimitError)
+                        .with_message(format!("hash directory page does not fit in {} bytes", f.fixed_size())));
+                }
+                let directory = f.new_block()?.id();
+                f.write_payload(directory, &page)?;
+                meta.update_header(Meta{
+                    level: 0,
+                    split: 0,
+                    count: 0,
+                    directory
+                })?;
+            }
+            load_directory(&f)?
+        };
+        Ok(HashIndex{
+            fixed,
+            directory: Mutex::new(directory),
+            marker: PhantomData
+        })
+    }
+
+    /*
+    ** 插入 / 覆盖, 返回原来的值
+    */
+    pub fn insert(&self, key: K, value: BlockId) -> Result<Option<BlockId>> {
+        let mut fixed = self.fixed.lock()?;
+        if !fits(&fixed, &Bucket::new(vec![(&key, value)]))? {
+            return Err(Error::new(Code::LimitError)
+                .with_message(format!("key is too large for hash bucket of {} bytes", fixed.fixed_size())));
+        }
+        let mut meta = self.meta(&fixed)?;
+        let mut directory = self.directory(&fixed, &meta)?;
+        let hash = self.hash(&key)?;
+        let head = directory.buckets[bucket_of(hash, &meta) as usize];
+        /*
+        ** 已经存在 => 覆盖
+        */
+        let mut id = head;
+        while id != NO_BUCKET {
+            let mut bucket: Bucket<K> = fixed.read_payload(id)?;
+            if let Some(entry) = bucket.entries.iter_mut().find(|(k, _)| k == &key) {
+                let old = entry.1;
+                entry.1 = value;
+                fixed.write_payload(id, &bucket)?;
+                return Ok(Some(old));
+            }
+            id = bucket.overflow;
+        }
+        /*
+        ** 放入链上第一个有空间的桶, 都没有空间 => 在链尾添加溢出桶, 并触发一次分裂
+        */
+        self.append(&mut fixed, &mut meta, &mut directory, head, key, value)?;
+        Ok(None)
+    }
+
+    pub fn get(&self, key: &K) -> Result<Option<BlockId>> {
+        let fixed = self.fixed.lock()?;
+        let meta = self.meta(&fixed)?;
+        let mut id = self.directory(&fixed, &meta)?.buckets[bucket_of(self.hash(key)?, &meta) as usize];
+        while id != NO_BUCKET {
+            let bucket: Bucket<K> = fixed.read_payload(id)?;
+            if let Some((_, v)) = bucket.entries.iter().find(|(k, _)| k == key) {
+                return Ok(Some(*v));
+            }
+            id = bucket.overflow;
+        }
+        Ok(None)
+    }
+
+    /*
+    ** 删除, 返回被删除的值
+    **  溢出桶为空时从链上摘除并释放
+    */
+    pub fn remove(&self, key: &K) -> Result<Option<BlockId>> {
+        let mut fixed = self.fixed.lock()?;
+        let mut meta = self.meta(&fixed)?;
+        let mut prev = NO_BUCKET;
+        let mut id = self.directory(&fixed, &meta)?.buckets[bucket_of(self.hash(key)?, &meta) as usize];
+        while id != NO_BUCKET {
+            let mut bucket: Bucket<K> = fixed.read_payload(id)?;
+            if let Some(i) = bucket.entries.iter().position(|(k, _)| k == key) {
+                let (_, old) = bucket.entries.remove(i);
+                if bucket.entries.is_empty() && prev != NO_BUCKET {
+                    let mut prev_bucket: Bucket<K> = fixed.read_payload(prev)?;
+                    prev_bucket.overflow = bucket.overflow;
+                    fixed.write_payload(prev, &prev_bucket)?;
+                    fixed.free_block(id)?;
+                } else {
+                    fixed.write_payload(id, &bucket)?;
+                }
+                meta.count -= 1;
+                self.set_meta(&fixed, &meta)?;
+                return Ok(Some(old));
+            }
+            prev = id;
+            id = bucket.overflow;
+        }
+        Ok(None)
+    }
+
+    /*
+    ** 键的数量
+    */
+    pub fn len(&self) -> Result<u64> {
+        let fixed = self.fixed.lock()?;
+        Ok(self.meta(&fixed)?.count)
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /*
+    ** 桶的数量 (不包括溢出桶)
+    */
+    pub fn bucket_count(&self) -> Result<u64> {
+        let fixed = self.fixed.lock()?;
+        Ok(bucket_count(&self.meta(&fixed)?))
+    }
+}
+
+fn to_vec<T: Serialize>(t: &T) -> Result<Vec<u8>> {
+    bincode::serialize(t).map_err(|err| Error::bincode(Code::SerdeError, err))
+}
+
+/*
+** 沿目录页读入整个目录
+*/
+fn load_directory(fixed: &Fixed) -> Result<Directory> {
+    let meta: Meta = fixed.block(META_ID)?.header()?;
+    let mut directory = Directory{
+        buckets: Vec::new(),
+        pages: Vec::new()
+    };
+    let mut id = meta.directory;
+    while id != NO_BUCKET {
+        let page: DirPage = fixed.read_payload(id)?;
+        directory.buckets.extend(page.buckets);
+        directory.pages.push(id);
+        id = page.next;
+    }
+    if directory.buckets.len() as u64 != bucket_count(&meta) {
+        return Err(Error::new(Code::DeserdeError)
+            .with_message(format!("hash directory of {} has {} buckets, expected {}", fixed.name(), directory.buckets.len(), bucket_count(&meta)))
+            .with_path(fixed.path()));
+    }
+    Ok(directory)
+}
+
+fn bucket_count(meta: &Meta) -> u64 {
+    (INITIAL_BUCKETS << meta.level) + meta.split
+}
+
+/*
+** 线性哈希的桶号: 先按当前轮次取模, 已经分裂过的桶按下一轮次取模
+*/
+fn bucket_of(hash: u64, meta: &Meta) -> u64 {
+    let bucket = hash % (INITIAL_BUCKETS << meta.level);
+    if bucket < meta.split {
+        hash % (INITIAL_BUCKETS << (meta.level + 1))
+    } else {
+        bucket
+    }
+}
+
+impl<K: Serialize + DeserializeOwned + Eq> HashIndex<K> {
+    fn hash(&self, key: &K) -> Result<u64> {
+        Ok(fnv1a(&to_vec(key)?))
+    }
+
+    fn meta(&self, fixed: &Fixed) -> Result<Meta> {
+        fixed.block(META_ID)?.header()
+    }
+
+    fn set_meta(&self, fixed: &Fixed, meta: &Meta) -> Result<()> {
+        fixed.block(META_ID)?.update_header(meta)
+    }
+
+    /*
+    ** 内存中的目录, 桶数量与元信息不一致时 (同一个 Fixed 上的其它 HashIndex 分裂过) 重新读入
+    **  调用方持有 Fixed 的锁
+    */
+    fn directory(&self, fixed: &Fixed, meta: &Meta) -> Result<MutexGuard<'_, Directory>> {
+        let mut directory = self.directory.lock().map_err(|_| {
+            Error::new(Code::LockError).with_message("hash directory is poisoned")
+        })?;
+        if directory.buckets.len() as u64 != bucket_count(meta) {
+            *directory = load_directory(fixed)?;
+        }
+        Ok(directory)
+    }
+
+    /*
+    ** 追加一个桶: 写入最后一个目录页, 放不下时分配新的目录页
+    */
+    fn push_bucket(&self, fixed: &mut Fixed, directory: &mut Directory, bucket: BlockId) -> Result<()> {
+        let last = match directory.pages.last() {
+            Some(last) => *last,
+            None => {
+                return Err(Error::new(Code::NotFoundError)
+                    .with_message(format!("hash directory of {} has no page", fixed.name()))
+                    .with_path(fixed.path()));
+            }
+        };
+        let mut page: DirPage = fixed.read_payload(last)?;
+        page.buckets.push(bucket);
+        if fits(fixed, &page)? {
+            fixed.write_payload(last, &page)?;
+        } else {
+            let next = fixed.new_block()?.id();
+            fixed.write_payload(next, &DirPage{
+                buckets: vec![bucket],
+                next: NO_BUCKET
+            })?;
+            page.buckets.pop();
+            page.next = next;
+            fixed.write_payload(last, &page)?;
+            directory.pages.push(next);
+        }
+        directory.buckets.push(bucket);
+        Ok(())
+    }
+
+    /*
+    ** 从 start 开始沿链查找有空间的桶, 链尾仍然放不下时分配溢出桶并分裂一次
+    */
+    fn append(&self, fixed: &mut Fixed, meta: &mut Meta, directory: &mut Directory, start: BlockId, key: K, value: BlockId) -> Result<()> {
+        let mut id = start;
+        let mut entry = Some((key, value));
+        let mut overflowed = false;
+        while let Some(e) = entry.take() {
+            let mut bucket: Bucket<K> = fixed.read_payload(id)?;
+            bucket.entries.push(e);
+            if fits(fixed, &bucket)? {
+                fixed.write_payload(id, &bucket)?;
+                break;
+            }
+            let e = match bucket.entries.pop() {
+                Some(e) => e,
+                None => unreachable!()
+            };
+            if bucket.overflow != NO_BUCKET {
+                id = bucket.overflow;
+                entry = Some(e);
+                continue;
+            }
+            let overflow = fixed.new_block()?.id();
+            fixed.write_payload(overflow, &Bucket::new(vec![e]))?;
+            bucket.overflow = overflow;
+            fixed.write_payload(id, &bucket)?;
+            overflowed = true;
+        }
+        meta.count += 1;
+        if overflowed {
+            self.split(fixed, meta, directory)?;
+        }
+        self.set_meta(fixed, meta)
+    }
+
+    /*
+    ** 分裂 split 指向的桶: 链上的所有键按下一轮次重新分布到原来的桶与新桶
+    */
+    fn split(&self, fixed: &mut Fixed, meta: &mut Meta, directory: &mut Directory) -> Result<()> {
+        let head = directory.buckets[meta.split as usize];
+        let mut entries: Vec<(K, BlockId)> = Vec::new();
+        let mut id = head;
+        while id != NO_BUCKET {
+            let bucket: Bucket<K> = fixed.read_payload(id)?;
+            entries.extend(bucket.entries);
+            if id != head {
+                fixed.free_block(id)?;
+            }
+            id = bucket.overflow;
+        }
+        let new_index = bucket_count(meta);
+        let new_head = fixed.new_block()?.id();
+        let next_modulo = INITIAL_BUCKETS << (meta.level + 1);
+        let mut stay: Vec<(K, BlockId)> = Vec::new();
+        let mut moved: Vec<(K, BlockId)> = Vec::new();
+        for entry in entries.into_iter() {
+            if self.hash(&entry.0)? % next_modulo == new_index {
+                moved.push(entry);
+            } else {
+                stay.push(entry);
+            }
+        }
+        self.write_chain(fixed, head, stay)?;
+        self.write_chain(fixed, new_head, moved)?;
+        self.push_bucket(fixed, directory, new_head)?;
+        meta.split += 1;
+        if meta.split == INITIAL_BUCKETS << meta.level {
+            meta.level += 1;
+            meta.split = 0;
+        }
+        Ok(())
+    }
+
+    /*
+    ** 将 entries 写入以 head 开始的新链, 放不下时分配溢出桶
+    */
+    fn write_chain(&self, fixed: &mut Fixed, head: BlockId, entries: Vec<(K, BlockId)>) -> Result<()> {
+        let mut id = head;
+        let mut bucket: Bucket<K> = Bucket::new(Vec::new());
+        for entry in entries.into_iter() {
+            bucket.entries.push(entry);
+            if !fits(fixed, &bucket)? {
+                let entry = match bucket.entries.pop() {
+                    Some(e) => e,
+                    None => unreachable!()
+                };
+                let overflow = fixed.new_block()?.id();
+                bucket.overflow = overflow;
+                fixed.write_payload(id, &bucket)?;
+                id = overflow;
+                bucket = Bucket::new(vec![entry]);
+            }
+        }
+        fixed.write_payload(id, &bucket)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::multifile::run_test::TestDir;
+
+    #[test]
+    fn hash_index_test() {
+        let dir = TestDir::new("hash_index_test");
+        let handle = FixedHandle::new(Fixed::new("hash", 96, &dir).unwrap());
+        let index: HashIndex<String> = HashIndex::open(handle.clone()).unwrap();
+        for i in 0..1000u64 {
+            assert_eq!(index.insert(format!("user-{}", i), i).unwrap(), None);
+        }
+        assert_eq!(index.len().unwrap(), 1000);
+        assert!(index.bucket_count().unwrap() > INITIAL_BUCKETS);
+        assert!(index.directory.lock().unwrap().pages.len() > 1);
+        assert_eq!(index.insert(String::from("user-7"), 70).unwrap(), Some(7));
+        for i in 0..1000u64 {
+            let expect = if i == 7 { 70 } else { i };
+            assert_eq!(index.get(&format!("user-{}", i)).unwrap(), Some(expect));
+        }
+        assert_eq!(index.get(&String::from("user-1000")).unwrap(), None);
+        /*
+        ** 删除之后重新打开
+        */
+        for i in 0..500u64 {
+            assert!(index.remove(&format!("user-{}", i)).unwrap().is_some());
+        }
+        assert_eq!(index.remove(&String::from("user-1")).unwrap(), None);
+        /*
+        ** 同一个 Fixed 上的另一个 HashIndex 分裂之后, 两者看到相同的目录
+        */
+        let other: HashIndex<String> = HashIndex::open(handle.clone()).unwrap();
+        let buckets = index.bucket_count().unwrap();
+        for i in 1000..1500u64 {
+            other.insert(format!("user-{}", i), i).unwrap();
+        }
+        assert!(other.bucket_count().unwrap() > buckets);
+        for i in 500..1500u64 {
+            assert_eq!(index.get(&format!("user-{}", i)).unwrap(), Some(if i == 7 { 70 } else { i }));
+        }
+        index.insert(String::from("user-1500"), 1500).unwrap();
+        assert_eq!(other.get(&String::from("user-1500")).unwrap(), Some(1500));
+        for i in 1000..1501u64 {
+            assert!(index.remove(&format!("user-{}", i)).unwrap().is_some());
+        }
+        drop(other);
+        drop(index);
+        let index: HashIndex<String> = HashIndex::open(handle).unwrap();
+        assert_eq!(index.len().unwrap(), 500);
+        assert_eq!(index.get(&String::from("user-1")).unwrap(), None);
+        assert_eq!(index.get(&String::from("user-999")).unwrap(), Some(999));
+    }
+}
@@ -0,0 +1,143 @@
+/*
+** 由业务header派生的二级索引
+**  每个 Fixed 可以注册多个提取函数, 提取函数从业务header中取出索引键
+**  Block::update_header 以及 Fixed::free_block 时自动维护, 索引只保存在内存中, 注册时通过全量扫描重建
+*/
+use crate::{Result, Error, Code};
+use super::fixed::BlockId;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use std::collections::{HashMap, BTreeSet};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/*
+** 擦除类型之后的提取函数: 业务header的序列化内容 -> 索引键的序列化内容
+**  header 无法反序列化为注册时的类型时返回 None, 该块不进入索引
+*/
+type Extractor = Box<dyn Fn(&[u8]) -> Option<Vec<u8>> + Send>;
+
+struct Index {
+    extractor: Extractor,
+    entries: HashMap<Vec<u8>, BTreeSet<BlockId>>,
+    keys: HashMap<BlockId, Vec<u8>>
+}
+
+impl Index {
+    fn remove(&mut self, id: BlockId) {
+        if let Some(key) = self.keys.remove(&id) {
+            if let Some(ids) = self.entries.get_mut(&key) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.entries.remove(&key);
+                }
+            }
+        }
+    }
+
+    fn update(&mut self, id: BlockId, header: &[u8]) {
+        self.remove(id);
+        if let Some(key) = (self.extractor)(header) {
+            self.entries.entry(key.clone()).or_default().insert(id);
+            self.keys.insert(id, key);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.keys.clear();
+    }
+}
+
+/*
+** Fixed 以及它创建的所有 Block 共享同一组索引
+*/
+#[derive(Clone, Default)]
+pub(crate) struct Indexes {
+    inner: Arc<Mutex<HashMap<String, Index>>>
+}
+
+impl Indexes {
+    pub(crate) fn register<H, K, F>(&self, name: &str, extractor: F) -> Result<()>
+        where H: DeserializeOwned, K: Serialize, F: Fn(&H) -> Option<K> + Send + 'static {
+        let mut inner = self.lock()?;
+        if inner.contains_key(name) {
+            return Err(Error::new(Code::ExistsError)
+                .with_message(format!("index {} is already registered", name)));
+        }
+        let extractor: Extractor = Box::new(move |content: &[u8]| {
+            let header: H = bincode::deserialize(content).ok()?;
+            bincode::serialize(&extractor(&header)?).ok()
+        });
+        inner.insert(name.to_string(), Index{
+            extractor,
+            entries: HashMap::new(),
+            keys: HashMap::new()
+        });
+        Ok(())
+    }
+
+    pub(crate) fn unregister(&self, name: &str) -> Result<bool> {
+        Ok(self.lock()?.remove(name).is_some())
+    }
+
+    pub(crate) fn is_empty(&self) -> Result<bool> {
+        Ok(self.lock()?.is_empty())
+    }
+
+    /*
+    ** 块的业务header被更新
+    */
+    pub(crate) fn update(&self, id: BlockId, header: &[u8]) -> Result<()> {
+        for index in self.lock()?.values_mut() {
+            index.update(id, header);
+        }
+        Ok(())
+    }
+
+    /*
+    ** 块被释放 / 业务header被清除
+    */
+    pub(crate) fn remove(&self, id: BlockId) -> Result<()> {
+        for index in self.lock()?.values_mut() {
+            index.remove(id);
+        }
+        Ok(())
+    }
+
+    /*
+    ** 重建: headers 为全量扫描得到的 (块序号, 业务header) 列表
+    */
+    pub(crate) fn rebuild(&self, headers: &[(BlockId, Vec<u8>)]) -> Result<()> {
+        for index in self.lock()?.values_mut() {
+            index.clear();
+            for (id, header) in headers.iter() {
+                index.update(*id, header);
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn lookup<K: Serialize>(&self, name: &str, key: &K) -> Result<Vec<BlockId>> {
+        let key = bincode::serialize(key).map_err(|err| Error::bincode(Code::SerdeError, err))?;
+        let inner = self.lock()?;
+        let index = match inner.get(name) {
+            Some(index) => index,
+            None => {
+                return Err(Error::new(Code::NotFoundError)
+                    .with_message(format!("index {} is not registered", name)));
+            }
+        };
+        Ok(match index.entries.get(&key) {
+            Some(ids) => ids.iter().cloned().collect(),
+            None => Vec::new()
+        })
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, HashMap<String, Index>>> {
+        self.inner.lock().map_err(|_| {
+            Error::new(Code::LockError).with_message("index set is poisoned")
+        })
+    }
+}
@@ -0,0 +1,189 @@
+/*
+** 基于 MultiFile 的键值存储
+**  name 目录下的两个 Fixed:
+**      kv_index.rd: 键 -> 值记录第一个块的 B+ 树
+**      kv_value.rd: 值通过 Fixed::write_record 写入, 超过块大小时串联多个块
+**  覆盖 / 删除后旧的值记录通过 Fixed::free_record 放入 stack::Delete, 之后被重新使用
+*/
+use crate::{Result, Error, Code};
+use super::MultiFile;
+use super::btree::BTree;
+use super::handle::FixedHandle;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use std::marker::PhantomData;
+use std::ops::Bound;
+
+const INDEX_NAME: &str = "kv_index.rd";
+const VALUE_NAME: &str = "kv_value.rd";
+/*
+** 默认的索引节点大小以及值块大小
+*/
+const DEFAULT_INDEX_SIZE: usize = 512;
+const DEFAULT_VALUE_SIZE: usize = 128;
+
+/*
+** 支持前缀扫描的键, 键的顺序必须保证相同前缀的键是连续的 (字典序)
+*/
+pub trait Prefix {
+    fn has_prefix(&self, prefix: &Self) -> bool;
+}
+
+impl Prefix for String {
+    fn has_prefix(&self, prefix: &Self) -> bool {
+        self.starts_with(prefix.as_str())
+    }
+}
+
+impl Prefix for Vec<u8> {
+    fn has_prefix(&self, prefix: &Self) -> bool {
+        self.starts_with(prefix.as_slice())
+    }
+}
+
+pub struct KvStore<K, V> {
+    index: BTree<K>,
+    index_fixed: FixedHandle,
+    values: FixedHandle,
+    marker: PhantomData<fn() -> V>
+}
+
+impl<K, V> KvStore<K, V>
+    where K: Serialize + DeserializeOwned + Ord + Clone, V: Serialize + DeserializeOwned {
+    pub fn open(multi: &MultiFile, name: &str) -> Result<KvStore<K, V>> {
+        KvStore::with_sizes(multi, name, DEFAULT_INDEX_SIZE, DEFAULT_VALUE_SIZE)
+    }
+
+    /*
+    ** index_size: 索引节点大小, 决定单个键的最大长度
+    ** value_size: 值块大小
+    */
+    pub fn with_sizes(multi: &MultiFile, name: &str, index_size: usize, value_size: usize) -> Result<KvStore<K, V>> {
+        let index_fixed = multi.open_fixed(name, INDEX_NAME, index_size)?;
+        let index = BTree::open(index_fixed.clone())?;
+        let values = multi.open_fixed(name, VALUE_NAME, value_size)?;
+        Ok(KvStore{
+            index,
+            index_fixed,
+            values,
+            marker: PhantomData
+        })
+    }
+
+    /*
+    ** 锁的顺序固定为 值 -> 索引, 读取时持有值的锁, 保证读到的值记录不会被并发释放
+    */
+    pub fn get(&self, key: &K) -> Result<Option<V>> {
+        let values = self.values.lock()?;
+        match self.index.get(key)? {
+            Some(id) => Ok(Some(deserde(&values.read_record(id)?)?)),
+            None => Ok(None)
+        }
+    }
+
+    /*
+    ** 写入 / 覆盖
+    **  先写入新的值记录, 再更新索引, 最后释放旧的值记录
+    */
+    pub fn put(&self, key: K, value: &V) -> Result<()> {
+        let content = bincode::serialize(value).map_err(|err| Error::bincode(Code::SerdeError, err))?;
+        let mut values = self.values.lock()?;
+        let id = values.write_record(&content)?;
+        let old = match self.index.insert(key, id) {
+            Ok(old) => old,
+            Err(err) => {
+                let _ = values.free_record(id);
+                return Err(err);
+            }
+        };
+        if let Some(old) = old {
+            values.free_record(old)?;
+        }
+        Ok(())
+    }
+
+    /*
+    ** 删除, 返回键是否存在
+    */
+    pub fn delete(&self, key: &K) -> Result<bool> {
+        let mut values = self.values.lock()?;
+        match self.index.remove(key)? {
+            Some(id) => {
+                values.free_record(id)?;
+                Ok(true)
+            }
+            None => Ok(false)
+        }
+    }
+
+    /*
+    ** 按键的顺序返回所有以 prefix 开头的键值
+    */
+    pub fn scan_prefix(&self, prefix: &K) -> Result<Vec<(K, V)>>
+        where K: Prefix {
+        let values = self.values.lock()?;
+        let mut items: Vec<(K, V)> = Vec::new();
+        for item in self.index.range((Bound::Included(prefix.clone()), Bound::Unbounded))? {
+            let (key, id) = item?;
+            if !key.has_prefix(prefix) {
+                break;
+            }
+            let value = deserde(&values.read_record(id)?)?;
+            items.push((key, value));
+        }
+        Ok(items)
+    }
+
+    /*
+    ** 将两个 Fixed 刷到磁盘
+    */
+    pub fn sync(&self) -> Result<()> {
+        self.values.lock()?.sync()?;
+        self.index_fixed.lock()?.sync()
+    }
+}
+
+fn deserde<V: DeserializeOwned>(content: &[u8]) -> Result<V> {
+    bincode::deserialize(content).map_err(|err| Error::bincode(Code::DeserdeError, err))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::multifile::run_test::TestDir;
+
+    #[test]
+    fn kv_store_test() {
+        let dir = TestDir::new("kv_store_test");
+        let multi = MultiFile::new(dir.to_str().unwrap().to_string());
+        let kv: KvStore<String, Vec<u64>> = KvStore::open(&multi, "kv").unwrap();
+        for i in 0..100u64 {
+            kv.put(format!("user/{:03}", i), &vec![i; i as usize]).unwrap();
+        }
+        kv.put(String::from("order/1"), &vec![1]).unwrap();
+        assert_eq!(kv.get(&String::from("user/042")).unwrap(), Some(vec![42; 42]));
+        assert_eq!(kv.get(&String::from("user/100")).unwrap(), None);
+        /*
+        ** 覆盖 / 删除之后值块被重新使用
+        */
+        let blocks = multi.open_fixed("kv", VALUE_NAME, DEFAULT_VALUE_SIZE).unwrap().lock().unwrap().block_count().unwrap();
+        assert!(kv.delete(&String::from("user/098")).unwrap());
+        assert!(!kv.delete(&String::from("user/098")).unwrap());
+        kv.put(String::from("user/099"), &vec![0]).unwrap();
+        kv.put(String::from("user/098"), &vec![98; 98]).unwrap();
+        assert_eq!(multi.open_fixed("kv", VALUE_NAME, DEFAULT_VALUE_SIZE).unwrap().lock().unwrap().block_count().unwrap(), blocks);
+        let items = kv.scan_prefix(&String::from("user/01")).unwrap();
+        let keys: Vec<String> = items.iter().map(|(k, _)| k.clone()).collect();
+        assert_eq!(keys, (10..20).map(|i| format!("user/{:03}", i)).collect::<Vec<String>>());
+        assert_eq!(kv.scan_prefix(&String::from("order/")).unwrap(), vec![(String::from("order/1"), vec![1])]);
+        kv.sync().unwrap();
+        drop(kv);
+        multi.close().unwrap();
+        let kv: KvStore<String, Vec<u64>> = KvStore::open(&multi, "kv").unwrap();
+        assert_eq!(kv.get(&String::from("user/099")).unwrap(), Some(vec![0]));
+        drop(kv);
+        multi.close().unwrap();
+    }
+}
@@ -0,0 +1,146 @@
+/*
+** 容量以及配额限制
+**  max_blocks: 单个 Fixed 中块的数量 (包括已删除的块)
+**  max_file_bytes: 单个 Fixed 数据文件的大小
+**  max_dir_bytes: name 目录中所有文件的总大小, 同一个目录中的 Fixed 共享
+**  只有 new_block 需要扩展文件时才检查, 重新使用删除栈中的块不受限制
+**  删除记录, 清单等文件的增长不会被拒绝, 但计入目录大小, 之后的扩展因此受限
+*/
+use crate::{Result, Error, Code};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Limits {
+    pub max_blocks: Option<u64>,
+    pub max_file_bytes: Option<u64>,
+    pub max_dir_bytes: Option<u64>
+}
+
+impl Limits {
+    pub fn new() -> Limits {
+        Limits::default()
+    }
+
+    pub fn with_max_blocks(mut self, max_blocks: u64) -> Limits {
+        self.max_blocks = Some(max_blocks);
+        self
+    }
+
+    pub fn with_max_file_bytes(mut self, max_file_bytes: u64) -> Limits {
+        self.max_file_bytes = Some(max_file_bytes);
+        self
+    }
+
+    pub fn with_max_dir_bytes(mut self, max_dir_bytes: u64) -> Limits {
+        self.max_dir_bytes = Some(max_dir_bytes);
+        self
+    }
+}
+
+/*
+** 当前使用量以及对应的限制
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Usage {
+    pub blocks: u64,
+    pub max_blocks: Option<u64>,
+    pub file_bytes: u64,
+    pub max_file_bytes: Option<u64>,
+    pub dir_bytes: u64,
+    pub max_dir_bytes: Option<u64>
+}
+
+/*
+** name 目录的使用量
+**  每次统计目录中所有文件的大小: 数据文件, 删除记录, 位图, 清单, 日志段以及其它写入目录的文件都计入
+**  同一个目录的文件扩展持有同一个锁, 统计之后到扩展完成之前其它扩展等待
+*/
+#[derive(Clone)]
+pub(crate) struct DirQuota {
+    dir: Arc<PathBuf>,
+    lock: Arc<Mutex<()>>
+}
+
+/*
+** 持有目录的扩展锁, used 为加锁时统计的目录大小
+*/
+pub(crate) struct QuotaGuard<'a> {
+    used: u64,
+    _guard: MutexGuard<'a, ()>
+}
+
+impl<'a> QuotaGuard<'a> {
+    /*
+    ** 扩展 length 字节之后超出 max 时返回 LimitError
+    */
+    pub(crate) fn check(&self, length: u64, max: Option<u64>) -> Result<()> {
+        check("directory size", self.used + length, max)
+    }
+}
+
+impl DirQuota {
+    pub(crate) fn new<P: AsRef<Path>>(dir: P) -> Result<DirQuota> {
+        dir_size(dir.as_ref())?;
+        Ok(DirQuota{
+            dir: Arc::new(dir.as_ref().to_path_buf()),
+            lock: Arc::new(Mutex::new(()))
+        })
+    }
+
+    pub(crate) fn used(&self) -> Result<u64> {
+        dir_size(&self.dir)
+    }
+
+    /*
+    ** 加锁并统计目录大小, 扩展文件之后再释放
+    */
+    pub(crate) fn lock(&self) -> Result<QuotaGuard<'_>> {
+        let guard = self.lock.lock().map_err(|_| {
+            Error::new(Code::LockError)
+                .with_message("directory quota is poisoned")
+                .with_path(self.dir.as_path())
+        })?;
+        Ok(QuotaGuard{
+            used: dir_size(&self.dir)?,
+            _guard: guard
+        })
+    }
+}
+
+/*
+** 检查 value 是否超出 max
+*/
+pub(crate) fn check(what: &str, value: u64, max: Option<u64>) -> Result<()> {
+    match max {
+        Some(max) if value > max => Err(exceeded(what, value, max)),
+        _ => Ok(())
+    }
+}
+
+fn exceeded(what: &str, value: u64, max: u64) -> Error {
+    Error::new(Code::LimitError)
+        .with_message(format!("{} {} exceeds limit {}", what, value, max))
+}
+
+fn dir_size(dir: &Path) -> Result<u64> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            return Err(Error::io(Code::FileReadError, err).with_path(dir));
+        }
+    };
+    let mut size = 0;
+    for entry in entries {
+        let entry = entry.map_err(|err| Error::io(Code::FileReadError, err).with_path(dir))?;
+        let metadata = entry.metadata().map_err(|err| {
+            Error::io(Code::FileMetadataError, err).with_path(entry.path())
+        })?;
+        if metadata.is_file() {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
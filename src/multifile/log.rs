@@ -0,0 +1,566 @@
+/*
+** 只追加的记录日志
+**  name 目录下的段文件, 文件名为段中第一条记录的偏移 (20 位数字) + .log
+**  记录格式: 内容长度 (u64 小端) + 内容 + Tail (长度前缀 + 内容的长度), 可以正向以及反向读取
+**  偏移为记录在整个日志中的字节位置, 段文件大小超过 segment_size 时创建新的段
+*/
+use crate::{Result, Error, Code};
+use super::MultiFile;
+use super::delete::stack::{Tail, TAIL_LENGTH};
+
+use std::fs;
+use std::io::SeekFrom;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+const SEGMENT_SUFFIX: &str = ".log";
+/*
+** 记录开头的内容长度 (u64 小端)
+*/
+const LENGTH_PREFIX: usize = 8;
+
+fn segment_name(base: u64) -> String {
+    format!("{:020}{}", base, SEGMENT_SUFFIX)
+}
+
+/*
+** 保留策略, 只删除不再写入的旧段
+*/
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Retention {
+    /*
+    ** 所有段的总大小上限
+    */
+    pub max_bytes: Option<u64>,
+    /*
+    ** 段最后一次修改之后的保留时间
+    */
+    pub max_age: Option<Duration>
+}
+
+struct Segment {
+    base: u64,
+    path: PathBuf
+}
+
+pub struct Log {
+    dir: PathBuf,
+    segment_size: u64,
+    segments: Vec<Segment>,
+    active: fs::File,
+    /*
+    ** 当前段的大小
+    */
+    active_size: u64
+}
+
+impl Log {
+    /*
+    ** 打开 name 目录中的日志, 最后一个段末尾不完整的记录 (写入过程中崩溃) 被截断
+    **  之前的段必须完整, 否则返回 DeserdeError
+    */
+    pub fn open(multi: &MultiFile, name: &str, segment_size: u64) -> Result<Log> {
+        Log::new(Path::new(&multi.root).join(name), segment_size)
+    }
+
+    pub fn new<P: AsRef<Path>>(dir: P, segment_size: u64) -> Result<Log> {
+        let dir = dir.as_ref().to_path_buf();
+        if let Err(err) = fs::create_dir_all(&dir) {
+            return Err(Error::io(Code::CreateDirError, err).with_path(&dir));
+        }
+        let mut segments = list_segments(&dir)?;
+        if segments.is_empty() {
+            segments.push(Segment{
+                base: 0,
+                path: dir.join(segment_name(0))
+            });
+        }
+        for pair in segments.windows(2) {
+            check_segment(&pair[0], pair[1].base)?;
+        }
+        let last = &segments[segments.len() - 1];
+        let mut active = open_segment(&last.path, true)?;
+        let active_size = recover(&mut active, &last.path)?;
+        Ok(Log{
+            dir,
+            segment_size,
+            segments,
+            active,
+            active_size
+        })
+    }
+
+    /*
+    ** 追加一条记录, 返回记录的偏移
+    */
+    pub fn append(&mut self, content: &[u8]) -> Result<u64> {
+        let mut tail_vec = Tail::new(LENGTH_PREFIX + content.len()).to_vec()?;
+        let mut body_vec: Vec<u8> = Vec::with_capacity(LENGTH_PREFIX + content.len() + tail_vec.len());
+        body_vec.extend_from_slice(&(content.len() as u64).to_le_bytes());
+        body_vec.extend_from_slice(content);
+        body_vec.append(&mut tail_vec);
+        if self.active_size > 0 && self.active_size + body_vec.len() as u64 > self.segment_size {
+            self.roll()?;
+        }
+        let offset = self.end_offset();
+        let path = &self.segments[self.segments.len() - 1].path;
+        if let Err(err) = self.active.seek(SeekFrom::Start(self.active_size)) {
+            return Err(Error::io(Code::FileSeekError, err).with_path(path).with_offset(self.active_size));
+        }
+        if let Err(err) = self.active.write_all(&body_vec) {
+            return Err(Error::io(Code::FileWriteError, err).with_path(path).with_offset(self.active_size));
+        }
+        self.active_size += body_vec.len() as u64;
+        Ok(offset)
+    }
+
+    /*
+    ** 读取 offset 处的记录
+    */
+    pub fn read(&self, offset: u64) -> Result<Vec<u8>> {
+        let segment = self.segment_of(offset)?;
+        let mut f = open_segment(&segment.path, false)?;
+        match read_forward(&mut f, &segment.path, offset - segment.base, self.segment_end(segment))? {
+            Some((content, _)) => Ok(content),
+            None => Err(self.out_of_range(offset))
+        }
+    }
+
+    /*
+    ** 从 offset 开始正向遍历, 返回 (偏移, 内容)
+    */
+    pub fn iter_from(&self, offset: u64) -> Result<Iter<'_>> {
+        if offset < self.start_offset() || offset > self.end_offset() {
+            return Err(self.out_of_range(offset));
+        }
+        Ok(Iter{
+            log: self,
+            offset,
+            file: None
+        })
+    }
+
+    /*
+    ** 从 offset 之前的记录开始反向遍历, offset 为 end_offset() 时从最后一条记录开始
+    */
+    pub fn iter_back_from(&self, offset: u64) -> Result<IterBack<'_>> {
+        if offset < self.start_offset() || offset > self.end_offset() {
+            return Err(self.out_of_range(offset));
+        }
+        Ok(IterBack{
+            log: self,
+            offset,
+            file: None
+        })
+    }
+
+    /*
+    ** 第一条保留的记录的偏移
+    */
+    pub fn start_offset(&self) -> u64 {
+        self.segments[0].base
+    }
+
+    /*
+    ** 下一条记录的偏移
+    */
+    pub fn end_offset(&self) -> u64 {
+        self.segments[self.segments.len() - 1].base + self.active_size
+    }
+
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+
+    /*
+    ** 按保留策略删除旧段, 当前写入的段不会被删除, 返回删除的段数量
+    */
+    pub fn apply_retention(&mut self, retention: &Retention) -> Result<usize> {
+        let now = SystemTime::now();
+        let mut total = self.end_offset() - self.start_offset();
+        let mut removed = 0;
+        while self.segments.len() > 1 {
+            let size = self.segments[1].base - self.segments[0].base;
+            let path = self.segments[0].path.clone();
+            let over_size = match retention.max_bytes {
+                Some(max) => total > max,
+                None => false
+            };
+            let expired = match retention.max_age {
+                Some(max) => {
+                    let modified = fs::metadata(&path).and_then(|m| m.modified()).map_err(|err| {
+                        Error::io(Code::FileMetadataError, err).with_path(&path)
+                    })?;
+                    now.duration_since(modified).unwrap_or_default() > max
+                }
+                None => false
+            };
+            if !over_size && !expired {
+                break;
+            }
+            if let Err(err) = fs::remove_file(&path) {
+                return Err(Error::io(Code::FileWriteError, err).with_path(&path));
+            }
+            self.segments.remove(0);
+            total -= size;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    pub fn sync(&self) -> Result<()> {
+        self.active.sync_all().map_err(|err| {
+            Error::io(Code::FileWriteError, err).with_path(&self.segments[self.segments.len() - 1].path)
+        })
+    }
+}
+
+impl Log {
+    fn roll(&mut self) -> Result<()> {
+        self.sync()?;
+        let base = self.end_offset();
+        let path = self.dir.join(segment_name(base));
+        self.active = open_segment(&path, true)?;
+        self.active_size = 0;
+        self.segments.push(Segment{
+            base,
+            path
+        });
+        Ok(())
+    }
+
+    fn segment_of(&self, offset: u64) -> Result<&Segment> {
+        if offset < self.start_offset() || offset >= self.end_offset() {
+            return Err(self.out_of_range(offset));
+        }
+        let index = match self.segments.binary_search_by(|s| s.base.cmp(&offset)) {
+            Ok(i) => i,
+            Err(i) => i - 1
+        };
+        Ok(&self.segments[index])
+    }
+
+    /*
+    ** 段中有效数据的结束位置 (段内偏移)
+    */
+    fn segment_end(&self, segment: &Segment) -> u64 {
+        match self.segments.iter().position(|s| s.base == segment.base) {
+            Some(i) if i + 1 < self.segments.len() => self.segments[i + 1].base - segment.base,
+            _ => self.active_size
+        }
+    }
+
+    fn out_of_range(&self, offset: u64) -> Error {
+        Error::new(Code::NotFoundError)
+            .with_message(format!("offset {} is out of range [{}, {})", offset, self.start_offset(), self.end_offset()))
+            .with_path(&self.dir)
+            .with_offset(offset)
+    }
+}
+
+pub struct Iter<'a> {
+    log: &'a Log,
+    offset: u64,
+    file: Option<(u64, fs::File)>
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = Result<(u64, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.log.end_offset() {
+            return None;
+        }
+        let segment = match self.log.segment_of(self.offset) {
+            Ok(s) => s,
+            Err(err) => return Some(Err(err))
+        };
+        let reuse = matches!(self.file, Some((base, _)) if base == segment.base);
+        if !reuse {
+            match open_segment(&segment.path, false) {
+                Ok(f) => self.file = Some((segment.base, f)),
+                Err(err) => return Some(Err(err))
+            }
+        }
+        let f = match self.file.as_mut() {
+            Some((_, f)) => f,
+            None => unreachable!()
+        };
+        match read_forward(f, &segment.path, self.offset - segment.base, self.log.segment_end(segment)) {
+            Ok(Some((content, length))) => {
+                let offset = self.offset;
+                self.offset += length;
+                Some(Ok((offset, content)))
+            }
+            Ok(None) => None,
+            Err(err) => Some(Err(err))
+        }
+    }
+}
+
+pub struct IterBack<'a> {
+    log: &'a Log,
+    offset: u64,
+    file: Option<(u64, fs::File)>
+}
+
+impl<'a> Iterator for IterBack<'a> {
+    type Item = Result<(u64, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset <= self.log.start_offset() || self.offset > self.log.end_offset() {
+            return None;
+        }
+        /*
+        ** offset 之前的最后一条记录所在的段
+        */
+        let segment = match self.log.segment_of(self.offset - 1) {
+            Ok(s) => s,
+            Err(err) => return Some(Err(err))
+        };
+        let reuse = matches!(self.file, Some((base, _)) if base == segment.base);
+        if !reuse {
+            match open_segment(&segment.path, false) {
+                Ok(f) => self.file = Some((segment.base, f)),
+                Err(err) => return Some(Err(err))
+            }
+        }
+        let f = match self.file.as_mut() {
+            Some((_, f)) => f,
+            None => unreachable!()
+        };
+        match read_backward(f, &segment.path, self.offset - segment.base) {
+            Ok((content, length)) => {
+                self.offset -= length;
+                Some(Ok((self.offset, content)))
+            }
+            Err(err) => Some(Err(err))
+        }
+    }
+}
+
+fn list_segments(dir: &Path) -> Result<Vec<Segment>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            return Err(Error::io(Code::FileReadError, err).with_path(dir));
+        }
+    };
+    let mut segments: Vec<Segment> = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|err| Error::io(Code::FileReadError, err).with_path(dir))?;
+        let file_name = entry.file_name();
+        let base = match file_name.to_str()
+            .and_then(|n| n.strip_suffix(SEGMENT_SUFFIX))
+            .and_then(|n| n.parse::<u64>().ok()) {
+            Some(base) => base,
+            None => continue
+        };
+        segments.push(Segment{
+            base,
+            path: entry.path()
+        });
+    }
+    segments.sort_by_key(|s| s.base);
+    Ok(segments)
+}
+
+fn open_segment(path: &Path, write: bool) -> Result<fs::File> {
+    fs::OpenOptions::new()
+        .create(write)
+        .truncate(false)
+        .read(true)
+        .write(write)
+        .open(path)
+        .map_err(|err| Error::io(Code::OpenFileError, err).with_path(path))
+}
+
+fn read_exact_at(f: &mut fs::File, path: &Path, offset: u64, length: usize) -> Result<Vec<u8>> {
+    if let Err(err) = f.seek(SeekFrom::Start(offset)) {
+        return Err(Error::io(Code::FileSeekError, err).with_path(path).with_offset(offset));
+    }
+    let mut content = vec![0; length];
+    if let Err(err) = f.read_exact(&mut content) {
+        return Err(Error::io(Code::FileReadError, err).with_path(path).with_offset(offset));
+    }
+    Ok(content)
+}
+
+fn deserde<D: serde::de::DeserializeOwned>(content: &[u8], path: &Path, offset: u64) -> Result<D> {
+    bincode::deserialize(content).map_err(|err| {
+        Error::bincode(Code::DeserdeError, err)
+            .with_path(path)
+            .with_offset(offset)
+    })
+}
+
+/*
+** 读取段内 position 处的记录, 返回 (内容, 记录长度), position 到达 end 时返回 None
+*/
+fn read_forward(f: &mut fs::File, path: &Path, position: u64, end: u64) -> Result<Option<(Vec<u8>, u64)>> {
+    match position.checked_add((LENGTH_PREFIX + *TAIL_LENGTH) as u64) {
+        Some(min_end) if min_end <= end => {},
+        _ => return Ok(None)
+    }
+    let mut prefix_vec = [0u8; LENGTH_PREFIX];
+    prefix_vec.copy_from_slice(&read_exact_at(f, path, position, LENGTH_PREFIX)?);
+    let prefix = u64::from_le_bytes(prefix_vec);
+    let length = prefix.checked_add((LENGTH_PREFIX + *TAIL_LENGTH) as u64);
+    let length = match length {
+        Some(length) if position.checked_add(length).is_some_and(|record_end| record_end <= end) => length,
+        _ => {
+            return Err(Error::new(Code::DeserdeError)
+                .with_message(format!("record length {} exceeds segment end {}", prefix, end))
+                .with_path(path)
+                .with_offset(position));
+        }
+    };
+    let content = read_exact_at(f, path, position + LENGTH_PREFIX as u64, prefix as usize)?;
+    Ok(Some((content, length)))
+}
+
+/*
+** 读取段内 position 之前的记录, 返回 (内容, 记录长度)
+*/
+fn read_backward(f: &mut fs::File, path: &Path, position: u64) -> Result<(Vec<u8>, u64)> {
+    let tail_offset = match position.checked_sub(*TAIL_LENGTH as u64) {
+        Some(o) => o,
+        None => {
+            return Err(Error::new(Code::DeserdeError)
+                .with_message(format!("no record ends at {}", position))
+                .with_path(path)
+                .with_offset(position));
+        }
+    };
+    let tail: Tail = deserde(&read_exact_at(f, path, tail_offset, *TAIL_LENGTH)?, path, tail_offset)?;
+    let body_offset = match tail_offset.checked_sub(tail.length as u64) {
+        Some(o) if tail.length >= LENGTH_PREFIX => o,
+        _ => {
+            return Err(Error::new(Code::DeserdeError)
+                .with_message(format!("record length {} is invalid", tail.length))
+                .with_path(path)
+                .with_offset(tail_offset));
+        }
+    };
+    let content = read_exact_at(f, path, body_offset + LENGTH_PREFIX as u64, tail.length - LENGTH_PREFIX)?;
+    Ok((content, (tail.length + *TAIL_LENGTH) as u64))
+}
+
+/*
+** 正向扫描段, 返回最后一条完整记录的结束位置以及段文件的大小
+*/
+fn scan(f: &mut fs::File, path: &Path) -> Result<(u64, u64)> {
+    let size = f.metadata().map_err(|err| Error::io(Code::FileMetadataError, err).with_path(path))?.len();
+    let mut position = 0;
+    loop {
+        match read_forward(f, path, position, size) {
+            Ok(Some((_, length))) => position += length,
+            Ok(None) => break,
+            Err(err) if err.code() == Code::DeserdeError => break,
+            Err(err) => return Err(err)
+        }
+    }
+    Ok((position, size))
+}
+
+/*
+** 不再写入的段: 大小必须等于下一个段的偏移之差, 并且由完整的记录组成
+*/
+fn check_segment(segment: &Segment, next_base: u64) -> Result<()> {
+    let mut f = open_segment(&segment.path, false)?;
+    let (position, size) = scan(&mut f, &segment.path)?;
+    let expected = next_base.saturating_sub(segment.base);
+    if position != expected || size != expected {
+        return Err(Error::new(Code::DeserdeError)
+            .with_message(format!("segment holds {} bytes of records in {} bytes, but the next segment starts after {} bytes",
+                position, size, expected))
+            .with_path(&segment.path)
+            .with_offset(position));
+    }
+    Ok(())
+}
+
+/*
+** 正向扫描段, 截断末尾不完整的记录, 返回有效数据的长度
+*/
+fn recover(f: &mut fs::File, path: &Path) -> Result<u64> {
+    let (position, size) = scan(f, path)?;
+    if position < size {
+        f.set_len(position).map_err(|err| {
+            Error::io(Code::FileWriteError, err).with_path(path).with_offset(position)
+        })?;
+    }
+    Ok(position)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::multifile::run_test::TestDir;
+
+    #[test]
+    fn log_test() {
+        let dir = TestDir::new("log_test");
+        let multi = MultiFile::new(dir.to_str().unwrap().to_string());
+        let mut log = Log::open(&multi, "event", 100).unwrap();
+        let mut offsets = Vec::new();
+        for i in 0..20u8 {
+            offsets.push(log.append(&vec![i; i as usize]).unwrap());
+        }
+        assert!(log.segment_count() > 1);
+        assert_eq!(log.read(offsets[7]).unwrap(), vec![7; 7]);
+        let forward: Vec<(u64, Vec<u8>)> = log.iter_from(offsets[3]).unwrap().map(|r| r.unwrap()).collect();
+        assert_eq!(forward.len(), 17);
+        assert_eq!(forward[0], (offsets[3], vec![3; 3]));
+        let backward: Vec<u64> = log.iter_back_from(log.end_offset()).unwrap().map(|r| r.unwrap().0).collect();
+        assert_eq!(backward, offsets.iter().rev().cloned().collect::<Vec<u64>>());
+        /*
+        ** 末尾不完整的记录在重新打开时被截断
+        */
+        let end = log.end_offset();
+        let last = log.segments[log.segments.len() - 1].path.clone();
+        drop(log);
+        fs::OpenOptions::new().append(true).open(&last).unwrap().write_all(&[9, 0, 0]).unwrap();
+        let mut log = Log::open(&multi, "event", 100).unwrap();
+        assert_eq!(log.end_offset(), end);
+        /*
+        ** 保留策略
+        */
+        let count = log.segment_count();
+        let removed = log.apply_retention(&Retention{max_bytes: Some(150), max_age: None}).unwrap();
+        assert!(removed > 0);
+        assert_eq!(log.segment_count(), count - removed);
+        assert!(log.read(offsets[0]).unwrap_err().is_not_found());
+        assert_eq!(log.read(offsets[19]).unwrap(), vec![19; 19]);
+        assert_eq!(log.iter_from(log.start_offset()).unwrap().count(), log.iter_back_from(log.end_offset()).unwrap().count());
+        /*
+        ** 记录以 u64 小端的内容长度开头
+        */
+        let first = log.segments[0].path.clone();
+        let content = fs::read(&first).unwrap();
+        let mut prefix = [0u8; 8];
+        prefix.copy_from_slice(&content[..8]);
+        let length = u64::from_le_bytes(prefix) as usize;
+        assert_eq!(&content[8..8 + length], log.read(log.start_offset()).unwrap().as_slice());
+        /*
+        ** 损坏的长度前缀不会溢出, 作为不完整的记录截断
+        */
+        for i in 0..5u8 {
+            log.append(&[i; 30]).unwrap();
+        }
+        assert!(log.segment_count() > 1);
+        let end = log.end_offset();
+        let last = log.segments[log.segments.len() - 1].path.clone();
+        drop(log);
+        fs::OpenOptions::new().append(true).open(&last).unwrap().write_all(&[0xff; 24]).unwrap();
+        let log = Log::open(&multi, "event", 100).unwrap();
+        assert_eq!(log.end_offset(), end);
+        drop(log);
+        /*
+        ** 之前的段不完整时打开失败
+        */
+        fs::OpenOptions::new().write(true).open(&first).unwrap().set_len(content.len() as u64 - 1).unwrap();
+        assert_eq!(Log::open(&multi, "event", 100).err().unwrap().code(), Code::DeserdeError);
+    }
+}
@@ -0,0 +1,120 @@
+/*
+** 目录清单
+**  每个 name 目录中保存一个 manifest.rd, 记录目录中所有的 Fixed
+*/
+use crate::{Result, Error, Code};
+
+use serde_derive::{Serialize, Deserialize};
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MANIFEST_NAME: &str = "manifest.rd";
+const MANIFEST_TMP_NAME: &str = "manifest.rd.tmp";
+
+/*
+** Fixed 的描述信息
+**  created_at: 创建时间 (unix 秒)
+*/
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TableInfo {
+    pub name: String,
+    pub fixed_size: usize,
+    pub created_at: u64
+}
+
+impl TableInfo {
+    pub fn new(name: &str, fixed_size: usize) -> TableInfo {
+        let created_at = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_secs(),
+            Err(_) => 0
+        };
+        TableInfo{
+            name: name.to_string(),
+            fixed_size,
+            created_at
+        }
+    }
+}
+
+pub(crate) struct Manifest {
+    dir: PathBuf,
+    tables: Vec<TableInfo>
+}
+
+impl Manifest {
+    /*
+    ** 读取目录中的清单, 清单文件不存在时为空
+    */
+    pub(crate) fn load<P: AsRef<Path>>(dir: P) -> Result<Manifest> {
+        let path = dir.as_ref().join(MANIFEST_NAME);
+        let tables = match fs::read(&path) {
+            Ok(content) => {
+                match bincode::deserialize(&content) {
+                    Ok(t) => t,
+                    Err(err) => {
+                        return Err(Error::bincode(Code::DeserdeError, err).with_path(&path));
+                    }
+                }
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => {
+                return Err(Error::io(Code::FileReadError, err).with_path(&path));
+            }
+        };
+        Ok(Manifest{
+            dir: dir.as_ref().to_path_buf(),
+            tables
+        })
+    }
+
+    /*
+    ** 先写入临时文件, 再重命名, 避免写入一半的清单
+    */
+    pub(crate) fn save(&self) -> Result<()> {
+        let content = match bincode::serialize(&self.tables) {
+            Ok(c) => c,
+            Err(err) => {
+                return Err(Error::bincode(Code::SerdeError, err));
+            }
+        };
+        let tmp_path = self.dir.join(MANIFEST_TMP_NAME);
+        if let Err(err) = fs::write(&tmp_path, content) {
+            return Err(Error::io(Code::FileWriteError, err).with_path(&tmp_path));
+        };
+        let path = self.dir.join(MANIFEST_NAME);
+        if let Err(err) = fs::rename(&tmp_path, &path) {
+            return Err(Error::io(Code::FileWriteError, err).with_path(&path));
+        };
+        Ok(())
+    }
+
+    pub(crate) fn tables(&self) -> &[TableInfo] {
+        &self.tables
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&TableInfo> {
+        self.tables.iter().find(|t| t.name == name)
+    }
+
+    pub(crate) fn insert(&mut self, info: TableInfo) {
+        self.tables.push(info);
+    }
+
+    pub(crate) fn remove(&mut self, name: &str) -> Option<TableInfo> {
+        let index = self.tables.iter().position(|t| t.name == name)?;
+        Some(self.tables.remove(index))
+    }
+
+    pub(crate) fn rename(&mut self, from: &str, to: &str) -> bool {
+        match self.tables.iter_mut().find(|t| t.name == from) {
+            Some(t) => {
+                t.name = to.to_string();
+                true
+            },
+            None => false
+        }
+    }
+}
@@ -1,89 +1,661 @@
-use crate::{Result, Error, Code};
-
-use std::path;
-use std::fs;
-
-pub struct MultiFile {
-    root: String
-}
-
-impl MultiFile {
-    pub fn open_fixed(&self, name: &str, fixed_name: &str, fixed_size: usize) -> Result<fixed::Fixed> {
-        /*
-        ** 1. 检测 self.root 中是否存在 name 为名称的目录
-        **  不存在 => 创建
-        */
-        let root_path = path::Path::new(&self.root);
-        let name_path = root_path.join(name);
-        if name_path.exists() {
-            /*
-            ** name目录存在
-            */
-        } else {
-            /*
-            ** name目录不存在
-            */
-            if let Err(err) = fs::create_dir_all(name_path.clone()) {
-                return Err(Error{
-                    code: Some(Code::CreateDirError(Some(err.to_string())))
-                });
-            };
-        }
-        let fixed = match fixed::Fixed::new(fixed_name, fixed_size, name_path) {
-            Ok(f) => f,
-            Err(err) => {
-                return Err(err);
-            }
-        };
-        Ok(fixed)
-    }
-}
-
-impl MultiFile {
-    pub fn new(root: String) -> MultiFile {
-        let f = MultiFile{
-            root: root
-        };
-        f
-    }
-}
-
-pub mod delete;
-pub mod fixed;
-
-#[cfg(test)]
-mod test {
-    use super::*;
-    #[test]
-    #[ignore]
-    fn multi_file_open_fixed_test() {
-        let multi_file = MultiFile::new(String::from("run_test"));
-        match multi_file.open_fixed("test.db", "user_index", 64) {
-            Ok(f) => f,
-            Err(err) => {
-                println!("{:?}", err);
-                return;
-            }
-        };
-    }
-
-    #[test]
-    #[ignore]
-    fn fixed_new_block_test() {
-        let multi_file = MultiFile::new(String::from("run_test"));
-        let mut fixed = match multi_file.open_fixed("test.db", "user_index", 64) {
-            Ok(f) => f,
-            Err(err) => {
-                println!("{:?}", err);
-                return;
-            }
-        };
-        let block = match fixed.new_block() {
-            Ok(b) => b,
-            Err(err) => {
-                println!("{:?}", err);
-                return;
-            }
-        };
-    }
-}
+use crate::{Result, Error, Code};
+
+use std::path;
+use std::fs;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+/*
+** 默认最多同时打开的 Fixed 数量
+*/
+const DEFAULT_MAX_OPEN: usize = 64;
+
+pub struct MultiFile {
+    root: String,
+    tables: Mutex<handle::Registry>,
+    limits: limit::Limits,
+    /*
+    ** 每个 name 目录共享的配额, 设置 max_dir_bytes 时才使用
+    */
+    quotas: Mutex<HashMap<String, limit::DirQuota>>
+}
+
+impl MultiFile {
+    /*
+    ** 打开 Fixed, 返回共享句柄
+    **  已经打开过的 Fixed 直接从缓存中返回
+    */
+    pub fn open_fixed(&self, name: &str, fixed_name: &str, fixed_size: usize) -> Result<handle::FixedHandle> {
+        let handle = self.registry()?.get_or_open(name, fixed_name, || {
+            self.open_fixed_file(name, fixed_name, fixed_size)
+        })?;
+        let opened_size = handle.lock()?.fixed_size();
+        if opened_size != fixed_size {
+            return Err(Error::new(Code::MismatchError)
+                .with_message(format!("fixed size is {}, but {} was opened with {}", fixed_size, fixed_name, opened_size))
+                .with_path(path::Path::new(&self.root).join(name).join(fixed_name)));
+        }
+        Ok(handle)
+    }
+
+    /*
+    ** 刷盘所有缓存的 Fixed, 关闭空闲的 Fixed
+    **  外部仍然持有的句柄 (以及 Block) 保留在缓存中, 之后打开时返回同一个 Fixed
+    */
+    pub fn close(&self) -> Result<()> {
+        let handles = self.registry()?.drain();
+        for handle in handles.iter() {
+            handle.lock()?.sync()?;
+        }
+        Ok(())
+    }
+
+    /*
+    ** 当前缓存中打开的 Fixed 数量
+    */
+    pub fn open_count(&self) -> Result<usize> {
+        Ok(self.registry()?.len())
+    }
+
+    fn open_fixed_file(&self, name: &str, fixed_name: &str, fixed_size: usize) -> Result<fixed::Fixed> {
+        /*
+        ** 1. 检测 self.root 中是否存在 name 为名称的目录
+        **  不存在 => 创建
+        */
+        let root_path = path::Path::new(&self.root);
+        let name_path = root_path.join(name);
+        if !name_path.exists() {
+            /*
+            ** name目录不存在
+            */
+            if let Err(err) = fs::create_dir_all(&name_path) {
+                return Err(Error::io(Code::CreateDirError, err).with_path(&name_path));
+            };
+        }
+        /*
+        ** 2. 打开 Fixed, 清单中没有记录 => 添加到清单
+        **  同名的条带化 Fixed 已经存在 => MismatchError
+        */
+        if name_path.join(striped::stripe_record_name(fixed_name)).exists() {
+            return Err(Error::new(Code::MismatchError)
+                .with_message(format!("{} is a striped fixed", fixed_name))
+                .with_path(&name_path));
+        }
+        let mut manifest = manifest::Manifest::load(&name_path)?;
+        let mut fixed = fixed::Fixed::new(fixed_name, fixed_size, &name_path)?;
+        self.apply_limits(name, &mut fixed)?;
+        if manifest.get(fixed_name).is_none() {
+            manifest.insert(manifest::TableInfo::new(fixed_name, fixed_size));
+            manifest.save()?;
+        }
+        Ok(fixed)
+    }
+
+    /*
+    ** 在 name 目录中打开条带化的 Fixed, 每个条带数据文件不超过 max_stripe_bytes
+    **  条带化的 Fixed 不进入句柄缓存以及清单, list 不返回, drop_fixed / rename_fixed 返回 MismatchError
+    **  清单中已经存在同名的 Fixed 时返回 ExistsError
+    */
+    pub fn open_striped(&self, name: &str, striped_name: &str, fixed_size: usize, max_stripe_bytes: u64) -> Result<striped::Striped> {
+        let name_path = path::Path::new(&self.root).join(name);
+        if let Err(err) = fs::create_dir_all(&name_path) {
+            return Err(Error::io(Code::CreateDirError, err).with_path(&name_path));
+        };
+        /*
+        ** 持有缓存的锁, 检查期间不会创建同名的 Fixed
+        */
+        let _registry = self.registry()?;
+        if manifest::Manifest::load(&name_path)?.get(striped_name).is_some() {
+            return Err(Error::new(Code::ExistsError)
+                .with_message(format!("fixed {} already exists", striped_name))
+                .with_path(name_path.join(striped_name)));
+        }
+        let mut striped = striped::Striped::new(striped_name, fixed_size, max_stripe_bytes, name_path)?;
+        if self.limits.max_dir_bytes.is_some() {
+            striped.set_dir_quota(self.dir_quota(name)?, self.limits.max_dir_bytes)?;
+        }
+        Ok(striped)
+    }
+
+    /*
+    ** 在 name 目录中打开固定容量的环, 环使用的 Fixed 应用目录配额以及容量限制
+    **  环与条带化的 Fixed 一样不进入句柄缓存以及清单; 清单中已经存在同名的 Fixed 时返回 ExistsError
+    */
+    pub fn open_ring(&self, name: &str, ring_name: &str, fixed_size: usize, capacity: u64) -> Result<ring::Ring> {
+        ring::check_capacity(capacity)?;
+        let name_path = path::Path::new(&self.root).join(name);
+        if let Err(err) = fs::create_dir_all(&name_path) {
+            return Err(Error::io(Code::CreateDirError, err).with_path(&name_path));
+        };
+        let _registry = self.registry()?;
+        if manifest::Manifest::load(&name_path)?.get(ring_name).is_some() {
+            return Err(Error::new(Code::ExistsError)
+                .with_message(format!("fixed {} already exists", ring_name))
+                .with_path(name_path.join(ring_name)));
+        }
+        let mut fixed = fixed::Fixed::new(ring_name, fixed_size, &name_path)?;
+        self.apply_limits(name, &mut fixed)?;
+        ring::Ring::with_fixed(ring_name, fixed, capacity)
+    }
+
+    /*
+    ** 列出 name 目录中所有的 Fixed (不包括条带化的 Fixed)
+    */
+    pub fn list(&self, name: &str) -> Result<Vec<manifest::TableInfo>> {
+        let manifest = self.load_manifest(name)?;
+        Ok(manifest.tables().to_vec())
+    }
+
+    /*
+    ** 获取 Fixed 的描述信息
+    */
+    pub fn describe(&self, name: &str, fixed_name: &str) -> Result<manifest::TableInfo> {
+        let manifest = self.load_manifest(name)?;
+        match manifest.get(fixed_name) {
+            Some(info) => Ok(info.clone()),
+            None => Err(self.table_not_found(name, fixed_name))
+        }
+    }
+
+    /*
+    ** 删除 Fixed (数据文件 + 删除记录文件)
+    **  Fixed 的句柄仍然被持有时返回 LockError
+    */
+    pub fn drop_fixed(&self, name: &str, fixed_name: &str) -> Result<()> {
+        let name_path = path::Path::new(&self.root).join(name);
+        /*
+        ** 持有缓存的锁直到清单保存完成, 期间不会重新打开或者创建
+        */
+        let mut registry = self.registry()?;
+        let mut manifest = self.load_manifest(name)?;
+        if manifest.remove(fixed_name).is_none() {
+            return Err(self.table_not_found(name, fixed_name));
+        }
+        if registry.is_busy(name, fixed_name) {
+            return Err(self.table_in_use(name, fixed_name));
+        }
+        registry.remove(name, fixed_name);
+        for file_name in [fixed_name.to_string(), fixed::delete_record_name(fixed_name), fixed::bitmap_record_name(fixed_name)].iter() {
+            let file_path = name_path.join(file_name);
+            if let Err(err) = fs::remove_file(&file_path) {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    return Err(Error::io(Code::FileWriteError, err).with_path(&file_path));
+                }
+            };
+        }
+        manifest.save()
+    }
+
+    /*
+    ** 重命名 Fixed (数据文件 + 删除记录文件)
+    **  Fixed 的句柄仍然被持有时返回 LockError; 中途失败时撤销已经完成的重命名
+    */
+    pub fn rename_fixed(&self, name: &str, from: &str, to: &str) -> Result<()> {
+        let name_path = path::Path::new(&self.root).join(name);
+        let mut registry = self.registry()?;
+        let mut manifest = self.load_manifest(name)?;
+        if manifest.get(from).is_none() {
+            return Err(self.table_not_found(name, from));
+        }
+        if manifest.get(to).is_some() || name_path.join(to).exists() || name_path.join(striped::stripe_record_name(to)).exists() {
+            return Err(Error::new(Code::ExistsError)
+                .with_message(format!("fixed {} already exists", to))
+                .with_path(name_path.join(to)));
+        }
+        if registry.is_busy(name, from) {
+            return Err(self.table_in_use(name, from));
+        }
+        registry.remove(name, from);
+        let renames = [
+            (from.to_string(), to.to_string()),
+            (fixed::delete_record_name(from), fixed::delete_record_name(to)),
+            (fixed::bitmap_record_name(from), fixed::bitmap_record_name(to))
+        ];
+        let mut done: Vec<(path::PathBuf, path::PathBuf)> = Vec::new();
+        let mut result: Result<()> = Ok(());
+        for (from_name, to_name) in renames.iter() {
+            let from_path = name_path.join(from_name);
+            let to_path = name_path.join(to_name);
+            match fs::rename(&from_path, &to_path) {
+                Ok(_) => done.push((from_path, to_path)),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {},
+                Err(err) => {
+                    result = Err(Error::io(Code::FileWriteError, err).with_path(&from_path));
+                    break;
+                }
+            }
+        }
+        if result.is_ok() {
+            manifest.rename(from, to);
+            result = manifest.save();
+        }
+        if result.is_err() {
+            for (from_path, to_path) in done.iter().rev() {
+                let _ = fs::rename(to_path, from_path);
+            }
+        }
+        result
+    }
+
+    /*
+    ** 解引用指针
+    **  只有在解引用时才打开指针指向的 Fixed, fixed_size 从文件头中读取
+    */
+    pub fn deref<T>(&self, ptr: &pointer::FilePtr<T>) -> Result<pointer::TypedBlock<T>> {
+        let handle = self.registry()?.get_or_open(ptr.name(), ptr.fixed_name(), || {
+            let name_path = path::Path::new(&self.root).join(ptr.name());
+            let mut fixed = fixed::Fixed::open(ptr.fixed_name(), name_path)?;
+            self.apply_limits(ptr.name(), &mut fixed)?;
+            Ok(fixed)
+        })?;
+        let block = handle.lock()?.block(ptr.id())?;
+        Ok(pointer::TypedBlock::new(block))
+    }
+
+    /*
+    ** Fixed 当前的使用量以及对应的限制
+    */
+    pub fn usage(&self, name: &str, fixed_name: &str) -> Result<limit::Usage> {
+        let manifest = self.load_manifest(name)?;
+        if manifest.get(fixed_name).is_none() {
+            return Err(self.table_not_found(name, fixed_name));
+        }
+        let handle = self.registry()?.get_or_open(name, fixed_name, || {
+            let name_path = path::Path::new(&self.root).join(name);
+            let mut fixed = fixed::Fixed::open(fixed_name, name_path)?;
+            self.apply_limits(name, &mut fixed)?;
+            Ok(fixed)
+        })?;
+        let usage = handle.lock()?.usage()?;
+        Ok(usage)
+    }
+
+    pub fn limits(&self) -> limit::Limits {
+        self.limits
+    }
+
+    /*
+    ** 设置容量限制, 应用到缓存中已经打开的 Fixed 以及之后打开的 Fixed
+    **  已经返回的 Striped / Ring 不受影响; 调用时不能持有句柄的锁
+    */
+    pub fn set_limits(&mut self, limits: limit::Limits) -> Result<()> {
+        self.limits = limits;
+        let handles = self.registry()?.handles();
+        for (name, handle) in handles.iter() {
+            self.apply_limits(name, &mut *handle.lock()?)?;
+        }
+        Ok(())
+    }
+}
+
+impl MultiFile {
+    fn registry(&self) -> Result<MutexGuard<'_, handle::Registry>> {
+        self.tables.lock().map_err(|_| {
+            Error::new(Code::LockError).with_message("multifile registry is poisoned")
+        })
+    }
+
+    fn apply_limits(&self, name: &str, fixed: &mut fixed::Fixed) -> Result<()> {
+        if self.limits.max_dir_bytes.is_some() {
+            fixed.set_dir_quota(self.dir_quota(name)?);
+        }
+        fixed.set_limits(self.limits)
+    }
+
+    /*
+    ** name 目录共享的配额, 同一个目录中的 Fixed 以及条带使用同一个
+    */
+    fn dir_quota(&self, name: &str) -> Result<limit::DirQuota> {
+        let mut quotas = self.quotas.lock().map_err(|_| {
+            Error::new(Code::LockError).with_message("multifile quotas are poisoned")
+        })?;
+        match quotas.get(name) {
+            Some(quota) => Ok(quota.clone()),
+            None => {
+                let quota = limit::DirQuota::new(path::Path::new(&self.root).join(name))?;
+                quotas.insert(name.to_string(), quota.clone());
+                Ok(quota)
+            }
+        }
+    }
+
+    fn load_manifest(&self, name: &str) -> Result<manifest::Manifest> {
+        let name_path = path::Path::new(&self.root).join(name);
+        if !name_path.is_dir() {
+            return Err(Error::new(Code::NotFoundError)
+                .with_message(format!("{} is not a directory", name))
+                .with_path(&name_path));
+        }
+        manifest::Manifest::load(&name_path)
+    }
+
+    fn table_in_use(&self, name: &str, fixed_name: &str) -> Error {
+        Error::new(Code::LockError)
+            .with_message(format!("fixed {} is still in use", fixed_name))
+            .with_path(path::Path::new(&self.root).join(name).join(fixed_name))
+    }
+
+    /*
+    ** 条带化的 Fixed 不在清单中, 明确返回 MismatchError 而不是当作不存在
+    */
+    fn table_not_found(&self, name: &str, fixed_name: &str) -> Error {
+        let name_path = path::Path::new(&self.root).join(name);
+        if name_path.join(striped::stripe_record_name(fixed_name)).exists() {
+            return Error::new(Code::MismatchError)
+                .with_message(format!("{} is a striped fixed, striped fixeds are not in the manifest", fixed_name))
+                .with_path(name_path);
+        }
+        Error::new(Code::NotFoundError)
+            .with_message(format!("fixed {} is not in the manifest", fixed_name))
+            .with_path(path::Path::new(&self.root).join(name))
+    }
+}
+
+impl MultiFile {
+    pub fn new(root: String) -> MultiFile {
+        MultiFile::with_max_open(root, DEFAULT_MAX_OPEN)
+    }
+
+    /*
+    ** max_open: 最多缓存的 Fixed 数量, 超出时淘汰最久未使用的
+    */
+    pub fn with_max_open(root: String, max_open: usize) -> MultiFile {
+        MultiFile{
+            root,
+            tables: Mutex::new(handle::Registry::new(max_open)),
+            limits: limit::Limits::default(),
+            quotas: Mutex::new(HashMap::new())
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+pub mod async_fixed;
+pub mod btree;
+pub mod cursor;
+pub mod delete;
+#[cfg(target_os = "linux")]
+mod direct;
+pub mod fixed;
+pub mod handle;
+pub mod hash;
+mod index;
+pub mod kv;
+pub mod limit;
+pub mod log;
+pub mod manifest;
+pub mod pager;
+pub mod pointer;
+pub mod queue;
+pub mod ring;
+#[cfg(test)]
+pub(crate) mod run_test;
+pub mod striped;
+#[cfg(all(feature = "io_uring", target_os = "linux"))]
+mod uring;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::multifile::run_test::TestDir;
+    #[test]
+    #[ignore]
+    #[allow(clippy::needless_return)]
+    fn multi_file_open_fixed_test() {
+        let multi_file = MultiFile::new(String::from("run_test"));
+        match multi_file.open_fixed("test.db", "user_index", 64) {
+            Ok(f) => f,
+            Err(err) => {
+                println!("{:?}", err);
+                return;
+            }
+        };
+    }
+
+    #[test]
+    #[ignore]
+    fn fixed_new_block_test() {
+        let multi_file = MultiFile::new(String::from("run_test"));
+        let fixed = match multi_file.open_fixed("test.db", "user_index", 64) {
+            Ok(f) => f,
+            Err(err) => {
+                println!("{:?}", err);
+                return;
+            }
+        };
+        if let Err(err) = fixed.lock().and_then(|mut f| f.new_block()) {
+            println!("{:?}", err);
+        }
+    }
+
+    #[derive(serde_derive::Serialize, serde_derive::Deserialize)]
+    struct Parent {
+        age: u32
+    }
+
+    #[derive(serde_derive::Serialize, serde_derive::Deserialize)]
+    struct Child {
+        parent: pointer::FilePtr<Parent>
+    }
+
+    #[test]
+    fn deref_file_ptr_test() {
+        let root = TestDir::new("deref_file_ptr_test");
+        let multi_file = MultiFile::new(root.to_str().unwrap().to_string());
+        let parents = multi_file.open_fixed("test.db", "parent", 64).unwrap();
+        let children = multi_file.open_fixed("test.db", "child", 128).unwrap();
+        parents.lock().unwrap().new_block().unwrap();
+        let mut parent = parents.lock().unwrap().new_block().unwrap();
+        parent.update_header(Parent{ age: 42 }).unwrap();
+        let mut child = children.lock().unwrap().new_block().unwrap();
+        child.update_header(Child{
+            parent: pointer::FilePtr::new("test.db", "parent", parent.id())
+        }).unwrap();
+        drop(parents);
+        multi_file.close().unwrap();
+        let child: Child = child.header().unwrap();
+        assert_eq!(child.parent.id(), 1);
+        let mut parent = multi_file.deref(&child.parent).unwrap();
+        assert_eq!(parent.header().unwrap().age, 42);
+        let missing = pointer::FilePtr::<Parent>::new("test.db", "parent", 9);
+        assert!(multi_file.deref(&missing).err().unwrap().is_not_found());
+    }
+
+    #[test]
+    fn handle_cache_test() {
+        let root = TestDir::new("handle_cache_test");
+        let multi_file = MultiFile::with_max_open(root.to_str().unwrap().to_string(), 2);
+        let user = multi_file.open_fixed("test.db", "user", 64).unwrap();
+        let block = user.lock().unwrap().new_block().unwrap();
+        /*
+        ** 同一个 Fixed 返回同一个句柄
+        */
+        let same = multi_file.open_fixed("test.db", "user", 64).unwrap();
+        assert_eq!(same.lock().unwrap().new_block().unwrap().id(), block.id() + 1);
+        multi_file.open_fixed("test.db", "order", 64).unwrap();
+        multi_file.open_fixed("test.db", "item", 64).unwrap();
+        assert_eq!(multi_file.open_count().unwrap(), 2);
+        /*
+        ** user 仍然被持有, 淘汰的是 order
+        */
+        let again = multi_file.open_fixed("test.db", "user", 64).unwrap();
+        assert_eq!(again.lock().unwrap().new_block().unwrap().id(), block.id() + 2);
+        assert_eq!(multi_file.open_fixed("test.db", "user", 32).err().unwrap().code(), Code::MismatchError);
+        /*
+        ** 仍然被持有的 user 在 close 之后保留
+        */
+        multi_file.close().unwrap();
+        assert_eq!(multi_file.open_count().unwrap(), 1);
+        drop((user, same, again, block));
+        multi_file.close().unwrap();
+        assert_eq!(multi_file.open_count().unwrap(), 0);
+    }
+
+    #[test]
+    fn open_fixed_options_test() {
+        let root = TestDir::new("open_fixed_options_test");
+        let dir = root.join("test.db");
+        fs::create_dir_all(&dir).unwrap();
+        let options = fixed::FixedOptions::new()
+            .with_allocator(fixed::Allocator::Bitmap)
+            .with_alignment(512);
+        fixed::Fixed::with_options("user", 64, options, &dir).unwrap();
+        /*
+        ** 没有指定选项 => 使用文件头中记录的选项
+        */
+        let multi_file = MultiFile::new(root.to_str().unwrap().to_string());
+        let user = multi_file.open_fixed("test.db", "user", 64).unwrap();
+        assert_eq!(user.lock().unwrap().allocator(), fixed::Allocator::Bitmap);
+        assert_eq!(user.lock().unwrap().alignment(), 512);
+        drop(user);
+        multi_file.close().unwrap();
+    }
+
+    #[test]
+    fn held_handle_test() {
+        let root = TestDir::new("held_handle_test");
+        let multi_file = MultiFile::with_max_open(root.to_str().unwrap().to_string(), 1);
+        let user = multi_file.open_fixed("test.db", "user", 64).unwrap();
+        let block = user.lock().unwrap().new_block().unwrap();
+        /*
+        ** 超出上限时不淘汰被持有的句柄, 重新打开返回同一个 Fixed
+        */
+        assert!(multi_file.open_fixed("test.db", "order", 64).err().unwrap().is_limit());
+        let again = multi_file.open_fixed("test.db", "user", 64).unwrap();
+        assert_eq!(again.lock().unwrap().new_block().unwrap().id(), block.id() + 1);
+        /*
+        ** 只剩下 Block 时仍然不能淘汰
+        */
+        drop((user, again));
+        assert!(multi_file.open_fixed("test.db", "order", 64).err().unwrap().is_limit());
+        drop(block);
+        multi_file.open_fixed("test.db", "order", 64).unwrap();
+        let user = multi_file.open_fixed("test.db", "user", 64).unwrap();
+        assert_eq!(user.lock().unwrap().new_block().unwrap().id(), 2);
+        drop(user);
+        multi_file.close().unwrap();
+    }
+
+    #[test]
+    fn manifest_test() {
+        let root = TestDir::new("manifest_test");
+        let multi_file = MultiFile::new(root.to_str().unwrap().to_string());
+        assert!(multi_file.list("test.db").err().unwrap().is_not_found());
+        multi_file.open_fixed("test.db", "user", 64).unwrap();
+        multi_file.open_fixed("test.db", "order", 32).unwrap();
+        multi_file.open_fixed("test.db", "user", 64).unwrap();
+        let names: Vec<String> = multi_file.list("test.db").unwrap().into_iter().map(|t| t.name).collect();
+        assert_eq!(names, vec!["user", "order"]);
+        assert_eq!(multi_file.describe("test.db", "order").unwrap().fixed_size, 32);
+        assert_eq!(multi_file.open_fixed("test.db", "order", 16).err().unwrap().code(), Code::MismatchError);
+
+        multi_file.rename_fixed("test.db", "order", "orders").unwrap();
+        assert!(multi_file.describe("test.db", "order").err().unwrap().is_not_found());
+        assert_eq!(multi_file.describe("test.db", "orders").unwrap().fixed_size, 32);
+        assert!(root.join("test.db").join("orders_delete.rd").exists());
+        assert_eq!(multi_file.rename_fixed("test.db", "orders", "user").err().unwrap().code(), Code::ExistsError);
+
+        /*
+        ** 仍然被持有的 Fixed 不能删除 / 重命名
+        */
+        let held = multi_file.open_fixed("test.db", "user", 64).unwrap();
+        assert_eq!(multi_file.drop_fixed("test.db", "user").err().unwrap().code(), Code::LockError);
+        assert_eq!(multi_file.rename_fixed("test.db", "user", "users").err().unwrap().code(), Code::LockError);
+        assert!(root.join("test.db").join("user").exists());
+        drop(held);
+        /*
+        ** 重命名中途失败 => 已经完成的重命名被撤销
+        */
+        fs::create_dir_all(root.join("test.db").join("users_delete.rd").join("x")).unwrap();
+        assert_eq!(multi_file.rename_fixed("test.db", "user", "users").err().unwrap().code(), Code::FileWriteError);
+        assert!(root.join("test.db").join("user").exists());
+        assert!(!root.join("test.db").join("users").exists());
+        assert_eq!(multi_file.describe("test.db", "user").unwrap().fixed_size, 64);
+        fs::remove_dir_all(root.join("test.db").join("users_delete.rd")).unwrap();
+
+        multi_file.drop_fixed("test.db", "user").unwrap();
+        assert!(!root.join("test.db").join("user").exists());
+        assert!(!root.join("test.db").join("user_delete.rd").exists());
+        assert_eq!(multi_file.list("test.db").unwrap().len(), 1);
+        assert!(multi_file.drop_fixed("test.db", "user").err().unwrap().is_not_found());
+        /*
+        ** 条带化的 Fixed 不在清单中, 不能作为 Fixed 删除 / 重命名 / 打开; 同名的 Fixed 不能打开为条带
+        */
+        multi_file.open_striped("test.db", "event", 64, 1024).unwrap();
+        assert_eq!(multi_file.list("test.db").unwrap().len(), 1);
+        assert_eq!(multi_file.drop_fixed("test.db", "event").err().unwrap().code(), Code::MismatchError);
+        assert_eq!(multi_file.rename_fixed("test.db", "event", "events").err().unwrap().code(), Code::MismatchError);
+        assert_eq!(multi_file.rename_fixed("test.db", "orders", "event").err().unwrap().code(), Code::ExistsError);
+        assert_eq!(multi_file.open_fixed("test.db", "event", 64).err().unwrap().code(), Code::MismatchError);
+        assert_eq!(multi_file.open_striped("test.db", "orders", 32, 1024).err().unwrap().code(), Code::ExistsError);
+        assert_eq!(multi_file.open_ring("test.db", "orders", 32, 4).err().unwrap().code(), Code::ExistsError);
+        assert_eq!(multi_file.open_ring("test.db", "metric", 32, 4).unwrap().capacity(), 4);
+    }
+
+    #[test]
+    fn limits_test() {
+        let root = TestDir::new("limits_test");
+        let mut multi_file = MultiFile::new(root.to_str().unwrap().to_string());
+        multi_file.set_limits(limit::Limits::new().with_max_blocks(4).with_max_dir_bytes(3000)).unwrap();
+        let user = multi_file.open_fixed("test.db", "user", 64).unwrap();
+        let ids: Vec<fixed::BlockId> = (0..4).map(|_| user.lock().unwrap().new_block().unwrap().id()).collect();
+        assert!(user.lock().unwrap().new_block().err().unwrap().is_limit());
+        /*
+        ** 删除栈中的块不受限制
+        */
+        user.lock().unwrap().free_block(ids[0]).unwrap();
+        assert_eq!(user.lock().unwrap().new_block().unwrap().id(), ids[0]);
+        let usage = multi_file.usage("test.db", "user").unwrap();
+        assert_eq!(usage.blocks, 4);
+        assert_eq!(usage.max_blocks, Some(4));
+        assert!(usage.dir_bytes >= usage.file_bytes);
+        /*
+        ** 新的限制应用到缓存中已经打开的 Fixed
+        */
+        multi_file.set_limits(limit::Limits::new().with_max_blocks(5).with_max_dir_bytes(3000)).unwrap();
+        assert_eq!(multi_file.usage("test.db", "user").unwrap().max_blocks, Some(5));
+        user.lock().unwrap().new_block().unwrap();
+        assert!(user.lock().unwrap().new_block().err().unwrap().is_limit());
+        /*
+        ** 同一个目录中的 Fixed 共享目录配额
+        */
+        let order = multi_file.open_fixed("test.db", "order", 1024).unwrap();
+        let mut order = order.lock().unwrap();
+        order.new_block().unwrap();
+        order.new_block().unwrap();
+        assert!(order.new_block().err().unwrap().is_limit());
+        drop(order);
+        assert!(multi_file.usage("test.db", "order").unwrap().dir_bytes <= 3000);
+        /*
+        ** 删除 Fixed 归还它占用的配额, 其它 Fixed 继续共享同一个配额
+        */
+        let before = multi_file.usage("test.db", "order").unwrap().dir_bytes;
+        let user_bytes = fs::metadata(root.join("test.db").join("user")).unwrap().len();
+        drop(user);
+        multi_file.drop_fixed("test.db", "user").unwrap();
+        assert!(multi_file.usage("test.db", "order").unwrap().dir_bytes <= before - user_bytes);
+        /*
+        ** 目录中的所有文件 (清单, 删除记录 ...) 都计入目录大小
+        */
+        let dir_bytes = |root: &path::Path| -> u64 {
+            fs::read_dir(root.join("test.db")).unwrap().map(|entry| entry.unwrap().metadata().unwrap().len()).sum()
+        };
+        assert_eq!(multi_file.usage("test.db", "order").unwrap().dir_bytes, dir_bytes(&root));
+        /*
+        ** 条带的扩展同样计入目录配额
+        */
+        let mut striped = multi_file.open_striped("test.db", "event", 64, 1024).unwrap();
+        let filler = root.join("test.db").join("filler");
+        fs::write(&filler, vec![0u8; (3000 - dir_bytes(&root)) as usize]).unwrap();
+        assert!(striped.new_block().err().unwrap().is_limit());
+        fs::remove_file(&filler).unwrap();
+        let mut created = 0;
+        while striped.new_block().is_ok() {
+            created += 1;
+            assert!(created < 100);
+        }
+        assert!(created > 0);
+        assert!(!root.join("test.db").join(fixed::delete_record_name(&striped::stripe_file_name("event", 0))).exists());
+        multi_file.close().unwrap();
+    }
+}
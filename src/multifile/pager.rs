@@ -0,0 +1,851 @@
+/*
+** Fixed 数据文件的读写入口
+**  Fixed 以及它创建的所有 Block 共享同一个 Pager
+**  可选的块缓存: 按块 (块头 + fixed_size) 缓存文件内容, 使用 CLOCK 淘汰
+*/
+use crate::{Result, Error, Code};
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+#[cfg(not(unix))]
+use std::io::SeekFrom;
+#[cfg(not(unix))]
+use std::io::prelude::*;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/*
+** 写策略
+**  WriteThrough: 写入同时更新缓存与文件
+**  WriteBack: 只更新缓存并标记为脏, 淘汰 / flush / sync / drop 时写入文件
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WritePolicy {
+    WriteThrough,
+    WriteBack
+}
+
+/*
+** 文件读写方式
+**  Std: 每次调用 pread / pwrite
+**  Uring: 所有读写经过 io_uring; 一次操作的数据文件, 删除记录以及文件头的写入放入同一次提交 (需要 feature = "io_uring", 只支持 Linux)
+**   不能与块缓存或者直接 I/O 同时使用, 同时开启时返回 MismatchError
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IoBackend {
+    #[default]
+    Std,
+    Uring
+}
+
+/*
+** 一次提交中的写入, file 为 None 时写入数据文件, 否则写入其它文件 (删除记录)
+*/
+pub(crate) struct WriteOp<'a> {
+    pub(crate) file: Option<(&'a fs::File, &'a Path)>,
+    pub(crate) offset: u64,
+    pub(crate) content: &'a [u8]
+}
+
+impl<'a> WriteOp<'a> {
+    pub(crate) fn data(offset: u64, content: &'a [u8]) -> WriteOp<'a> {
+        WriteOp{
+            file: None,
+            offset,
+            content
+        }
+    }
+
+    pub(crate) fn to(file: &'a fs::File, path: &'a Path, offset: u64, content: &'a [u8]) -> WriteOp<'a> {
+        WriteOp{
+            file: Some((file, path)),
+            offset,
+            content
+        }
+    }
+}
+
+/*
+** budget: 缓存可以使用的字节数
+*/
+#[derive(Debug, Clone, Copy)]
+pub struct CacheConfig {
+    pub budget: usize,
+    pub policy: WritePolicy
+}
+
+impl CacheConfig {
+    pub fn new(budget: usize, policy: WritePolicy) -> CacheConfig {
+        CacheConfig{
+            budget,
+            policy
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub cached_blocks: usize,
+    pub dirty_blocks: usize
+}
+
+struct CacheEntry {
+    slot: u64,
+    data: Vec<u8>,
+    referenced: bool,
+    dirty: bool
+}
+
+struct BlockCache {
+    capacity: usize,
+    policy: WritePolicy,
+    entries: Vec<CacheEntry>,
+    index: HashMap<u64, usize>,
+    hand: usize,
+    hits: u64,
+    misses: u64
+}
+
+impl BlockCache {
+    fn new(config: CacheConfig, slot_size: usize) -> BlockCache {
+        BlockCache{
+            capacity: config.budget / slot_size,
+            policy: config.policy,
+            entries: Vec::new(),
+            index: HashMap::new(),
+            hand: 0,
+            hits: 0,
+            misses: 0
+        }
+    }
+
+    fn get_mut(&mut self, slot: u64) -> Option<&mut CacheEntry> {
+        match self.index.get(&slot) {
+            Some(i) => {
+                let entry = &mut self.entries[*i];
+                entry.referenced = true;
+                Some(entry)
+            },
+            None => None
+        }
+    }
+
+    /*
+    ** CLOCK: 跳过最近被访问过的块 (清除访问标记), 选中第一个未被访问的块
+    */
+    fn victim(&mut self) -> usize {
+        loop {
+            if self.hand >= self.entries.len() {
+                self.hand = 0;
+            }
+            let entry = &mut self.entries[self.hand];
+            if entry.referenced {
+                entry.referenced = false;
+                self.hand += 1;
+            } else {
+                return self.hand;
+            }
+        }
+    }
+
+    /*
+    ** 插入块, 返回被淘汰的脏块 (需要调用方写入文件)
+    */
+    fn insert(&mut self, slot: u64, data: Vec<u8>, dirty: bool) -> Option<CacheEntry> {
+        let entry = CacheEntry{
+            slot,
+            data,
+            referenced: true,
+            dirty
+        };
+        if self.entries.len() < self.capacity {
+            self.index.insert(slot, self.entries.len());
+            self.entries.push(entry);
+            return None;
+        }
+        let i = self.victim();
+        let old = std::mem::replace(&mut self.entries[i], entry);
+        self.index.remove(&old.slot);
+        self.index.insert(slot, i);
+        self.hand = i + 1;
+        if old.dirty {
+            Some(old)
+        } else {
+            None
+        }
+    }
+
+    fn remove(&mut self, slot: u64) -> Option<CacheEntry> {
+        let i = self.index.remove(&slot)?;
+        let entry = self.entries.swap_remove(i);
+        if i < self.entries.len() {
+            let moved = self.entries[i].slot;
+            self.index.insert(moved, i);
+        }
+        Some(entry)
+    }
+
+    fn take_dirty(&mut self) -> Vec<(u64, Vec<u8>)> {
+        let mut dirty = Vec::new();
+        for entry in self.entries.iter_mut() {
+            if entry.dirty {
+                entry.dirty = false;
+                dirty.push((entry.slot, entry.data.clone()));
+            }
+        }
+        dirty
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats{
+            hits: self.hits,
+            misses: self.misses,
+            cached_blocks: self.entries.len(),
+            dirty_blocks: self.entries.iter().filter(|e| e.dirty).count()
+        }
+    }
+}
+
+struct Inner {
+    file: fs::File,
+    path: String,
+    data_start: u64,
+    slot_size: u64,
+    cache: Option<BlockCache>,
+    /*
+    ** 以 O_DIRECT 打开时的对齐大小
+    */
+    direct: Option<u64>,
+    #[cfg(all(feature = "io_uring", target_os = "linux"))]
+    uring: Option<super::uring::Uring>
+}
+
+/*
+** 按位置读写文件, 不修改文件的读写位置
+**  unix 使用 pread / pwrite, 其他平台使用 seek + read / write
+*/
+#[cfg(unix)]
+fn read_file(file: &fs::File, path: &str, offset: u64, length: usize) -> Result<Vec<u8>> {
+    use std::os::unix::fs::FileExt;
+    let mut content: Vec<u8> = vec![0; length];
+    let mut read = 0;
+    while read < length {
+        match file.read_at(&mut content[read..], offset + read as u64) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => {
+                return Err(Error::io(Code::FileReadError, err)
+                    .with_path(path)
+                    .with_offset(offset));
+            }
+        }
+    }
+    content.truncate(read);
+    Ok(content)
+}
+
+#[cfg(unix)]
+fn write_file(file: &fs::File, path: &str, offset: u64, content: &[u8]) -> Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.write_all_at(content, offset).map_err(|err| {
+        Error::io(Code::FileWriteError, err)
+            .with_path(path)
+            .with_offset(offset)
+    })
+}
+
+#[cfg(not(unix))]
+fn read_file(file: &fs::File, path: &str, offset: u64, length: usize) -> Result<Vec<u8>> {
+    let mut file = file;
+    if let Err(err) = file.seek(SeekFrom::Start(offset)) {
+        return Err(Error::io(Code::FileSeekError, err)
+            .with_path(path)
+            .with_offset(offset));
+    };
+    let mut content: Vec<u8> = Vec::with_capacity(length);
+    if let Err(err) = file.take(length as u64).read_to_end(&mut content) {
+        return Err(Error::io(Code::FileReadError, err)
+            .with_path(path)
+            .with_offset(offset));
+    };
+    Ok(content)
+}
+
+#[cfg(not(unix))]
+fn write_file(file: &fs::File, path: &str, offset: u64, content: &[u8]) -> Result<()> {
+    let mut file = file;
+    if let Err(err) = file.seek(SeekFrom::Start(offset)) {
+        return Err(Error::io(Code::FileSeekError, err)
+            .with_path(path)
+            .with_offset(offset));
+    };
+    if let Err(err) = file.write_all(content) {
+        return Err(Error::io(Code::FileWriteError, err)
+            .with_path(path)
+            .with_offset(offset));
+    };
+    Ok(())
+}
+
+/*
+** 释放文件区域占用的磁盘空间, 返回 false 表示平台或者文件系统不支持
+*/
+#[cfg(target_os = "linux")]
+fn punch_file(file: &fs::File, offset: u64, length: u64) -> bool {
+    use std::os::unix::io::AsRawFd;
+    let ret = unsafe {
+        libc::fallocate(file.as_raw_fd(), libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            offset as libc::off_t, length as libc::off_t)
+    };
+    ret == 0
+}
+
+#[cfg(not(target_os = "linux"))]
+fn punch_file(_file: &fs::File, _offset: u64, _length: u64) -> bool {
+    false
+}
+
+/*
+** 在内核中复制文件区域 (copy_file_range), 返回复制的字节数
+**  不支持或者出错时返回已经复制的字节数, 剩余部分由调用方复制
+*/
+#[cfg(target_os = "linux")]
+fn copy_file(from: &fs::File, from_offset: u64, to: &fs::File, to_offset: u64, length: usize) -> usize {
+    use std::os::unix::io::AsRawFd;
+    let mut copied = 0;
+    while copied < length {
+        let mut off_in = (from_offset + copied as u64) as libc::loff_t;
+        let mut off_out = (to_offset + copied as u64) as libc::loff_t;
+        let ret = unsafe {
+            libc::copy_file_range(from.as_raw_fd(), &mut off_in, to.as_raw_fd(), &mut off_out, length - copied, 0)
+        };
+        if ret <= 0 {
+            break;
+        }
+        copied += ret as usize;
+    }
+    copied
+}
+
+#[cfg(not(target_os = "linux"))]
+fn copy_file(_from: &fs::File, _from_offset: u64, _to: &fs::File, _to_offset: u64, _length: usize) -> usize {
+    0
+}
+
+impl Inner {
+    #[cfg(target_os = "linux")]
+    fn read_raw(&mut self, offset: u64, length: usize) -> Result<Vec<u8>> {
+        #[cfg(feature = "io_uring")]
+        {
+            if let Some(uring) = &mut self.uring {
+                return uring.read_at(&self.file, &self.path, offset, length);
+            }
+        }
+        match self.direct {
+            Some(alignment) => super::direct::read_aligned(&self.file, &self.path, alignment, offset, length),
+            None => read_file(&self.file, &self.path, offset, length)
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn write_raw(&mut self, offset: u64, content: &[u8]) -> Result<()> {
+        #[cfg(feature = "io_uring")]
+        {
+            if let Some(uring) = &mut self.uring {
+                return uring.submit(&self.file, &self.path, &[vec![WriteOp::data(offset, content)]]);
+            }
+        }
+        match self.direct {
+            Some(alignment) => super::direct::write_aligned(&self.file, &self.path, alignment, offset, content),
+            None => write_file(&self.file, &self.path, offset, content)
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn read_raw(&mut self, offset: u64, length: usize) -> Result<Vec<u8>> {
+        read_file(&self.file, &self.path, offset, length)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn write_raw(&mut self, offset: u64, content: &[u8]) -> Result<()> {
+        write_file(&self.file, &self.path, offset, content)
+    }
+
+    fn slot_start(&self, slot: u64) -> u64 {
+        self.data_start + slot * self.slot_size
+    }
+
+    /*
+    ** [offset, offset + length) 完全落在某个块内 => 返回块序号
+    */
+    fn slot_of(&self, offset: u64, length: usize) -> Option<u64> {
+        if offset < self.data_start || self.slot_size == 0 {
+            return None;
+        }
+        let slot = (offset - self.data_start) / self.slot_size;
+        if offset + length as u64 <= self.slot_start(slot + 1) {
+            Some(slot)
+        } else {
+            None
+        }
+    }
+
+    fn write_slot(&mut self, slot: u64, data: &[u8]) -> Result<()> {
+        let offset = self.slot_start(slot);
+        self.write_raw(offset, data)
+    }
+
+    /*
+    ** 保证块在缓存中, 返回 false 表示缓存容量为 0
+    */
+    fn load(&mut self, slot: u64) -> Result<bool> {
+        let capacity = match &mut self.cache {
+            Some(cache) => {
+                if cache.index.contains_key(&slot) {
+                    cache.hits += 1;
+                    return Ok(true);
+                }
+                cache.misses += 1;
+                cache.capacity
+            },
+            None => 0
+        };
+        if capacity == 0 {
+            return Ok(false);
+        }
+        let offset = self.slot_start(slot);
+        let mut data = self.read_raw(offset, self.slot_size as usize)?;
+        data.resize(self.slot_size as usize, 0);
+        let evicted = match &mut self.cache {
+            Some(cache) => cache.insert(slot, data, false),
+            None => None
+        };
+        if let Some(entry) = evicted {
+            self.write_slot(entry.slot, &entry.data)?;
+        }
+        Ok(true)
+    }
+
+    fn read_at(&mut self, offset: u64, length: usize) -> Result<Vec<u8>> {
+        if let Some(slot) = self.slot_of(offset, length) {
+            if self.load(slot)? {
+                let start = (offset - self.slot_start(slot)) as usize;
+                if let Some(entry) = self.cache.as_mut().and_then(|c| c.get_mut(slot)) {
+                    return Ok(entry.data[start..start + length].to_vec());
+                }
+            }
+        }
+        self.read_raw(offset, length)
+    }
+
+    fn write_at(&mut self, offset: u64, content: &[u8]) -> Result<()> {
+        let policy = match &self.cache {
+            Some(cache) => cache.policy,
+            None => {
+                return self.write_raw(offset, content);
+            }
+        };
+        match self.slot_of(offset, content.len()) {
+            Some(slot) => {
+                if policy == WritePolicy::WriteThrough {
+                    self.write_raw(offset, content)?;
+                } else if !self.load(slot)? {
+                    return self.write_raw(offset, content);
+                }
+                let start = (offset - self.slot_start(slot)) as usize;
+                if let Some(entry) = self.cache.as_mut().and_then(|c| c.get_mut(slot)) {
+                    entry.data[start..start + content.len()].copy_from_slice(content);
+                    if policy == WritePolicy::WriteBack {
+                        entry.dirty = true;
+                    }
+                }
+                Ok(())
+            },
+            None => {
+                /*
+                ** 跨越多个块 => 先将涉及的块从缓存中移除, 再直接写入文件
+                */
+                self.invalidate(offset, content.len())?;
+                self.write_raw(offset, content)
+            }
+        }
+    }
+
+    /*
+    ** 按阶段写入, 前一阶段全部完成之后才开始下一阶段, 同一阶段内的顺序不保证
+    **  开启缓存时数据文件的写入逐个经过缓存
+    */
+    fn submit(&mut self, stages: &[Vec<WriteOp>]) -> Result<()> {
+        #[cfg(all(feature = "io_uring", target_os = "linux"))]
+        {
+            if let Some(uring) = &mut self.uring {
+                return uring.submit(&self.file, &self.path, stages);
+            }
+        }
+        for write in stages.iter().flatten() {
+            match write.file {
+                Some((file, path)) => write_file(file, &path.to_string_lossy(), write.offset, write.content)?,
+                None => self.write_at(write.offset, write.content)?
+            }
+        }
+        Ok(())
+    }
+
+    fn invalidate(&mut self, offset: u64, length: usize) -> Result<()> {
+        if offset + length as u64 <= self.data_start || self.slot_size == 0 {
+            return Ok(());
+        }
+        let first = offset.saturating_sub(self.data_start) / self.slot_size;
+        let last = (offset + length as u64 - 1 - self.data_start) / self.slot_size;
+        for slot in first..=last {
+            let removed = match &mut self.cache {
+                Some(cache) => cache.remove(slot),
+                None => None
+            };
+            if let Some(entry) = removed {
+                if entry.dirty {
+                    self.write_slot(entry.slot, &entry.data)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let dirty = match &mut self.cache {
+            Some(cache) => cache.take_dirty(),
+            None => Vec::new()
+        };
+        for (slot, data) in dirty.iter() {
+            self.write_slot(*slot, data)?;
+        }
+        Ok(())
+    }
+
+    /*
+    ** io_uring 不与缓存 / 直接 I/O 同时使用
+    */
+    fn check_uring(&self, feature: &str) -> Result<()> {
+        #[cfg(all(feature = "io_uring", target_os = "linux"))]
+        {
+            if self.uring.is_some() {
+                return Err(Error::new(Code::MismatchError)
+                    .with_message(format!("{} can not be combined with the io_uring backend", feature))
+                    .with_path(&self.path));
+            }
+        }
+        let _ = feature;
+        Ok(())
+    }
+
+    fn len(&self) -> Result<u64> {
+        match self.file.metadata() {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(err) => {
+                Err(Error::io(Code::FileMetadataError, err).with_path(&self.path))
+            }
+        }
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Pager {
+    inner: Arc<Mutex<Inner>>
+}
+
+impl Pager {
+    /*
+    ** data_start: 第一个块的起始位置 (文件头之后)
+    ** slot_size: 块头 + fixed_size
+    */
+    pub(crate) fn new(file: fs::File, path: String, data_start: u64, slot_size: u64) -> Pager {
+        Pager{
+            inner: Arc::new(Mutex::new(Inner{
+                file,
+                path,
+                data_start,
+                slot_size,
+                cache: None,
+                direct: None,
+                #[cfg(all(feature = "io_uring", target_os = "linux"))]
+                uring: None
+            }))
+        }
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Inner>> {
+        self.inner.lock().map_err(|_| {
+            Error::new(Code::LockError).with_message("pager is poisoned")
+        })
+    }
+
+    pub(crate) fn read_at(&self, offset: u64, length: usize) -> Result<Vec<u8>> {
+        self.lock()?.read_at(offset, length)
+    }
+
+    pub(crate) fn write_at(&self, offset: u64, content: &[u8]) -> Result<()> {
+        self.lock()?.write_at(offset, content)
+    }
+
+    /*
+    ** 按阶段批量写入数据文件以及删除记录, 前一阶段全部完成之后才开始下一阶段
+    **  同一阶段内的顺序不保证, 写入的区域不应该重叠
+    */
+    pub(crate) fn submit(&self, stages: &[Vec<WriteOp>]) -> Result<()> {
+        self.lock()?.submit(stages)
+    }
+
+    pub(crate) fn len(&self) -> Result<u64> {
+        self.lock()?.len()
+    }
+
+    /*
+    ** 共享该 Pager 的数量 (Fixed 以及它创建的 Block / 游标)
+    */
+    pub(crate) fn ref_count(&self) -> usize {
+        Arc::strong_count(&self.inner)
+    }
+
+    /*
+    ** 以 O_DIRECT 重新打开文件 (alignment 为 None 时恢复普通打开), 只支持 Linux
+    **  读写按 alignment 对齐, 不对齐的读写先读取覆盖它的对齐区域
+    */
+    #[cfg(target_os = "linux")]
+    pub(crate) fn set_direct_io(&self, alignment: Option<u64>) -> Result<()> {
+        let mut inner = self.lock()?;
+        if alignment.is_some() {
+            inner.check_uring("direct io")?;
+        }
+        inner.flush()?;
+        let file = super::direct::open_direct(&inner.path, alignment.is_some())?;
+        inner.file = file;
+        inner.direct = alignment;
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn set_direct_io(&self, alignment: Option<u64>) -> Result<()> {
+        match alignment {
+            Some(_) => Err(Error::new(Code::NotImplement)
+                .with_message("direct io is only supported on Linux")
+                .with_path(&self.lock()?.path)),
+            None => Ok(())
+        }
+    }
+
+    /*
+    ** 切换文件读写方式, 不支持 io_uring 时返回 NotImplement
+    */
+    #[cfg(all(feature = "io_uring", target_os = "linux"))]
+    pub(crate) fn set_io_backend(&self, backend: IoBackend) -> Result<()> {
+        let mut inner = self.lock()?;
+        match backend {
+            IoBackend::Std => inner.uring = None,
+            IoBackend::Uring => {
+                if inner.cache.is_some() || inner.direct.is_some() {
+                    return Err(Error::new(Code::MismatchError)
+                        .with_message("io_uring backend can not be combined with the block cache or direct io")
+                        .with_path(&inner.path));
+                }
+                if inner.uring.is_none() {
+                    inner.uring = Some(super::uring::Uring::new().map_err(|err| err.with_path(&inner.path))?);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(all(feature = "io_uring", target_os = "linux")))]
+    pub(crate) fn set_io_backend(&self, backend: IoBackend) -> Result<()> {
+        match backend {
+            IoBackend::Std => Ok(()),
+            IoBackend::Uring => {
+                Err(Error::new(Code::NotImplement)
+                    .with_message("io_uring backend requires the io_uring feature on Linux")
+                    .with_path(&self.lock()?.path))
+            }
+        }
+    }
+
+    pub(crate) fn io_backend(&self) -> Result<IoBackend> {
+        #[cfg(all(feature = "io_uring", target_os = "linux"))]
+        {
+            if self.lock()?.uring.is_some() {
+                return Ok(IoBackend::Uring);
+            }
+        }
+        Ok(IoBackend::Std)
+    }
+
+    /*
+    ** 调整文件大小, 扩展的部分为 0
+    */
+    pub(crate) fn set_len(&self, length: u64) -> Result<()> {
+        let inner = self.lock()?;
+        inner.file.set_len(length).map_err(|err| {
+            Error::io(Code::FileWriteError, err)
+                .with_path(&inner.path)
+                .with_offset(length)
+        })
+    }
+
+    /*
+    ** 释放 [offset, offset + length) 占用的磁盘空间, 之后读取为 0
+    **  涉及的块先从缓存中移除, 不支持打洞时写入 0
+    */
+    pub(crate) fn punch_hole(&self, offset: u64, length: u64) -> Result<()> {
+        let mut inner = self.lock()?;
+        let inner = &mut *inner;
+        inner.invalidate(offset, length as usize)?;
+        if punch_file(&inner.file, offset, length) {
+            return Ok(());
+        }
+        inner.write_raw(offset, &vec![0; length as usize])
+    }
+
+    /*
+    ** 将本文件的区域复制到 to 中, ranges: (本文件中的位置, to 中的位置, 长度)
+    **  本文件的脏块先写入文件, to 中涉及的块从缓存中移除
+    **  两个文件都没有开启直接 I/O 时使用 copy_file_range, 否则读取之后写入
+    */
+    pub(crate) fn copy_to(&self, to: &Pager, ranges: &[(u64, u64, usize)]) -> Result<()> {
+        if Arc::ptr_eq(&self.inner, &to.inner) {
+            let mut inner = self.lock()?;
+            inner.flush()?;
+            for (from_offset, to_offset, length) in ranges.iter() {
+                let content = inner.read_raw(*from_offset, *length)?;
+                inner.write_at(*to_offset, &content)?;
+            }
+            return Ok(());
+        }
+        /*
+        ** 按地址顺序加锁, 避免相反方向的复制死锁
+        */
+        let (mut from, mut dest) = if Arc::as_ptr(&self.inner) < Arc::as_ptr(&to.inner) {
+            let from = self.lock()?;
+            (from, to.lock()?)
+        } else {
+            let dest = to.lock()?;
+            (self.lock()?, dest)
+        };
+        from.flush()?;
+        for (from_offset, to_offset, length) in ranges.iter() {
+            dest.invalidate(*to_offset, *length)?;
+            let copied = if from.direct.is_none() && dest.direct.is_none() {
+                copy_file(&from.file, *from_offset, &dest.file, *to_offset, *length)
+            } else {
+                0
+            };
+            if copied < *length {
+                let content = from.read_raw(from_offset + copied as u64, length - copied)?;
+                dest.write_raw(to_offset + copied as u64, &content)?;
+            }
+        }
+        Ok(())
+    }
+
+    /*
+    ** 将脏块写入文件
+    */
+    pub(crate) fn flush(&self) -> Result<()> {
+        self.lock()?.flush()
+    }
+
+    /*
+    ** 将脏块写入文件, 并刷到磁盘
+    */
+    pub(crate) fn sync(&self) -> Result<()> {
+        let mut inner = self.lock()?;
+        inner.flush()?;
+        if let Err(err) = inner.file.sync_all() {
+            return Err(Error::io(Code::FileWriteError, err).with_path(&inner.path));
+        };
+        Ok(())
+    }
+
+    /*
+    ** 替换缓存配置, 原有缓存中的脏块先写入文件
+    */
+    pub(crate) fn set_cache(&self, config: Option<CacheConfig>) -> Result<()> {
+        let mut inner = self.lock()?;
+        if config.is_some() {
+            inner.check_uring("the block cache")?;
+        }
+        inner.flush()?;
+        let slot_size = inner.slot_size as usize;
+        inner.cache = config.map(|c| BlockCache::new(c, slot_size));
+        Ok(())
+    }
+
+    pub(crate) fn cache_stats(&self) -> Result<Option<CacheStats>> {
+        Ok(self.lock()?.cache.as_ref().map(|c| c.stats()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::multifile::run_test::TestDir;
+
+    fn open(name: &str) -> (Pager, std::path::PathBuf, TestDir) {
+        let dir = TestDir::new(name);
+        let path = dir.join("data");
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let pager = Pager::new(file, path.to_str().unwrap().to_string(), 4, 8);
+        pager.set_len(4 + 8 * 4).unwrap();
+        (pager, path, dir)
+    }
+
+    #[test]
+    fn write_back_cache_test() {
+        let (pager, path, _dir) = open("write_back_cache_test");
+        pager.set_cache(Some(CacheConfig::new(16, WritePolicy::WriteBack))).unwrap();
+        pager.write_at(4 + 2, &[1, 2, 3]).unwrap();
+        assert_eq!(pager.read_at(4 + 2, 3).unwrap(), vec![1, 2, 3]);
+        /*
+        ** 还没有写入文件
+        */
+        assert_eq!(&fs::read(&path).unwrap()[6..9], &[0, 0, 0]);
+        let stats = pager.cache_stats().unwrap().unwrap();
+        assert_eq!((stats.hits, stats.misses, stats.dirty_blocks), (1, 1, 1));
+        /*
+        ** 容量为 2 个块, 读取另外两个块会淘汰脏块
+        */
+        pager.read_at(4 + 8, 8).unwrap();
+        pager.read_at(4 + 16, 8).unwrap();
+        assert_eq!(&fs::read(&path).unwrap()[6..9], &[1, 2, 3]);
+        pager.write_at(4 + 24, &[9]).unwrap();
+        drop(pager);
+        assert_eq!(fs::read(&path).unwrap()[28], 9);
+    }
+
+    #[test]
+    fn write_through_cache_test() {
+        let (pager, path, _dir) = open("write_through_cache_test");
+        pager.set_cache(Some(CacheConfig::new(64, WritePolicy::WriteThrough))).unwrap();
+        pager.write_at(4, &[7; 8]).unwrap();
+        assert_eq!(&fs::read(&path).unwrap()[4..12], &[7; 8]);
+        assert_eq!(pager.read_at(4 + 4, 4).unwrap(), vec![7; 4]);
+        /*
+        ** 跨越块的写入使缓存失效
+        */
+        pager.write_at(4 + 6, &[5; 4]).unwrap();
+        assert_eq!(pager.read_at(4, 8).unwrap(), vec![7, 7, 7, 7, 7, 7, 5, 5]);
+        assert_eq!(pager.cache_stats().unwrap().unwrap().dirty_blocks, 0);
+    }
+}
@@ -0,0 +1,113 @@
+/*
+** 指向某个 Fixed 中某个块的指针
+**  可以通过 serde 写入到其他块的 header 中, 用于在表之间建立关联
+*/
+use crate::Result;
+use super::fixed::{Block, BlockId};
+
+use serde_derive::{Serialize, Deserialize};
+
+use std::fmt;
+use std::marker::PhantomData;
+
+/*
+** name: MultiFile 中的目录名称
+** fixed_name: 目录中的 Fixed 名称
+** id: 块的序号
+** T: 块的业务header类型
+*/
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct FilePtr<T> {
+    name: String,
+    fixed_name: String,
+    id: BlockId,
+    #[serde(skip)]
+    marker: PhantomData<fn() -> T>
+}
+
+impl<T> FilePtr<T> {
+    pub fn new(name: &str, fixed_name: &str, id: BlockId) -> Self {
+        Self {
+            name: name.to_string(),
+            fixed_name: fixed_name.to_string(),
+            id,
+            marker: PhantomData
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn fixed_name(&self) -> &str {
+        &self.fixed_name
+    }
+
+    pub fn id(&self) -> BlockId {
+        self.id
+    }
+}
+
+impl<T> Clone for FilePtr<T> {
+    fn clone(&self) -> Self {
+        Self::new(&self.name, &self.fixed_name, self.id)
+    }
+}
+
+impl<T> PartialEq for FilePtr<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.fixed_name == other.fixed_name && self.id == other.id
+    }
+}
+
+impl<T> Eq for FilePtr<T> {}
+
+impl<T> fmt::Debug for FilePtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FilePtr")
+            .field("name", &self.name)
+            .field("fixed_name", &self.fixed_name)
+            .field("id", &self.id)
+            .finish()
+    }
+}
+
+/*
+** 解引用 FilePtr 得到的块, header 的类型为 T
+*/
+pub struct TypedBlock<T> {
+    block: Block,
+    marker: PhantomData<fn() -> T>
+}
+
+impl<T: serde::Serialize + serde::de::DeserializeOwned> TypedBlock<T> {
+    pub fn header(&mut self) -> Result<T> {
+        self.block.header()
+    }
+
+    pub fn update_header(&mut self, header: &T) -> Result<()> {
+        self.block.update_header(header)
+    }
+}
+
+impl<T> TypedBlock<T> {
+    pub fn new(block: Block) -> Self {
+        Self {
+            block,
+            marker: PhantomData
+        }
+    }
+
+    pub fn id(&self) -> BlockId {
+        self.block.id()
+    }
+
+    pub fn block(&mut self) -> &mut Block {
+        &mut self.block
+    }
+
+    pub fn into_block(self) -> Block {
+        self.block
+    }
+}
@@ -0,0 +1,373 @@
+/*
+** 持久化的双端队列 / 先进先出队列
+**  记录格式: Tail (内容长度) + bincode 内容 + Tail (内容长度), 两端都可以读取
+**  文件头记录 head / tail 位置以及记录数量, head 之前的空间用于 push_front
+**  pop_front 之后 head 之前的空间超过有效数据以及 RECLAIM_THRESHOLD 时, 将有效数据移到文件开始处并截断文件
+*/
+use crate::{Result, Error, Code};
+use super::delete::stack::{Tail, TAIL_LENGTH};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_derive::{Serialize, Deserialize};
+
+use std::fs;
+use std::io::SeekFrom;
+use std::io::prelude::*;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+/*
+** 触发空间回收的最小空闲字节数
+*/
+const RECLAIM_THRESHOLD: usize = 4096;
+
+#[derive(Default, Serialize, Deserialize)]
+struct FileHeader {
+    head: usize,
+    tail: usize,
+    count: u64
+}
+
+impl FileHeader {
+    fn new(head: usize, tail: usize, count: u64) -> FileHeader {
+        FileHeader{
+            head,
+            tail,
+            count
+        }
+    }
+}
+
+lazy_static!{
+    static ref FILE_HEADER_LENGTH: usize = to_vec(&FileHeader::default()).unwrap().len();
+}
+
+fn to_vec<T: Serialize>(t: &T) -> Result<Vec<u8>> {
+    bincode::serialize(t).map_err(|err| Error::bincode(Code::SerdeError, err))
+}
+
+/*
+** 记录: Tail + 内容 + Tail
+*/
+fn frame<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+    let content = to_vec(value)?;
+    let tail = Tail::new(content.len()).to_vec()?;
+    let mut frame = tail.clone();
+    frame.extend_from_slice(&content);
+    frame.extend_from_slice(&tail);
+    Ok(frame)
+}
+
+pub struct PersistentDeque<T> {
+    file: fs::File,
+    path: PathBuf,
+    marker: PhantomData<fn() -> T>
+}
+
+impl<T: Serialize + DeserializeOwned> PersistentDeque<T> {
+    pub fn push_back(&mut self, value: &T) -> Result<()> {
+        let frame = frame(value)?;
+        let header = self.get_file_header()?;
+        self.write_at(header.tail as u64, &frame)?;
+        self.update_file_header(&FileHeader::new(header.head, header.tail + frame.len(), header.count + 1))
+    }
+
+    /*
+    ** head 之前的空间不足时, 将有效数据移到当前文件尾之后, 新旧位置不重叠, 移动过程中崩溃不会丢失数据
+    */
+    pub fn push_front(&mut self, value: &T) -> Result<()> {
+        let frame = frame(value)?;
+        let mut header = self.get_file_header()?;
+        if header.head - *FILE_HEADER_LENGTH < frame.len() {
+            let live = header.tail - header.head;
+            let content = self.read_at(header.head as u64, live)?;
+            let head = header.tail + frame.len() + live;
+            self.write_at(head as u64, &content)?;
+            header = FileHeader::new(head, head + live, header.count);
+            self.update_file_header(&header)?;
+        }
+        let head = header.head - frame.len();
+        self.write_at(head as u64, &frame)?;
+        self.update_file_header(&FileHeader::new(head, header.tail, header.count + 1))
+    }
+
+    pub fn pop_back(&mut self) -> Result<Option<T>> {
+        let header = self.get_file_header()?;
+        if header.count == 0 {
+            return Ok(None);
+        }
+        let tail: Tail = self.deserde_at((header.tail - *TAIL_LENGTH) as u64, *TAIL_LENGTH)?;
+        let start = header.tail - *TAIL_LENGTH - tail.length;
+        let value: T = self.deserde_at(start as u64, tail.length)?;
+        let header = FileHeader::new(header.head, start - *TAIL_LENGTH, header.count - 1);
+        self.update_file_header(&header)?;
+        self.reclaim(header)?;
+        Ok(Some(value))
+    }
+
+    pub fn pop_front(&mut self) -> Result<Option<T>> {
+        let header = self.get_file_header()?;
+        if header.count == 0 {
+            return Ok(None);
+        }
+        let tail: Tail = self.deserde_at(header.head as u64, *TAIL_LENGTH)?;
+        let start = header.head + *TAIL_LENGTH;
+        let value: T = self.deserde_at(start as u64, tail.length)?;
+        let header = FileHeader::new(start + tail.length + *TAIL_LENGTH, header.tail, header.count - 1);
+        self.update_file_header(&header)?;
+        self.reclaim(header)?;
+        Ok(Some(value))
+    }
+
+    pub fn front(&mut self) -> Result<Option<T>> {
+        let header = self.get_file_header()?;
+        if header.count == 0 {
+            return Ok(None);
+        }
+        let tail: Tail = self.deserde_at(header.head as u64, *TAIL_LENGTH)?;
+        Ok(Some(self.deserde_at((header.head + *TAIL_LENGTH) as u64, tail.length)?))
+    }
+
+    pub fn back(&mut self) -> Result<Option<T>> {
+        let header = self.get_file_header()?;
+        if header.count == 0 {
+            return Ok(None);
+        }
+        let tail: Tail = self.deserde_at((header.tail - *TAIL_LENGTH) as u64, *TAIL_LENGTH)?;
+        Ok(Some(self.deserde_at((header.tail - *TAIL_LENGTH - tail.length) as u64, tail.length)?))
+    }
+
+    pub fn len(&mut self) -> Result<u64> {
+        Ok(self.get_file_header()?.count)
+    }
+
+    pub fn is_empty(&mut self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /*
+    ** 将文件内容刷到磁盘
+    */
+    pub fn sync(&self) -> Result<()> {
+        self.file.sync_all().map_err(|err| {
+            Error::io(Code::FileWriteError, err).with_path(&self.path)
+        })
+    }
+}
+
+impl<T> PersistentDeque<T> {
+    /*
+    ** 空间回收
+    **  队列为空 => 截断到文件头
+    **  head 之前的空闲空间足够大 => 有效数据移到文件头之后 (新旧位置不重叠), 更新文件头, 再截断
+    */
+    fn reclaim(&mut self, header: FileHeader) -> Result<()> {
+        let live = header.tail - header.head;
+        let free = header.head - *FILE_HEADER_LENGTH;
+        let file_size = self.get_file_size()?;
+        if header.count == 0 {
+            if file_size > *FILE_HEADER_LENGTH {
+                self.update_file_header(&FileHeader::new(*FILE_HEADER_LENGTH, *FILE_HEADER_LENGTH, 0))?;
+                self.set_len(*FILE_HEADER_LENGTH)?;
+            }
+            return Ok(());
+        }
+        if free <= live || free < RECLAIM_THRESHOLD {
+            return Ok(());
+        }
+        let content = self.read_at(header.head as u64, live)?;
+        self.write_at(*FILE_HEADER_LENGTH as u64, &content)?;
+        self.update_file_header(&FileHeader::new(*FILE_HEADER_LENGTH, *FILE_HEADER_LENGTH + live, header.count))?;
+        self.set_len(*FILE_HEADER_LENGTH + live)
+    }
+
+    fn seek(&mut self, offset: u64) -> Result<()> {
+        if let Err(err) = self.file.seek(SeekFrom::Start(offset)) {
+            return Err(Error::io(Code::FileSeekError, err)
+                .with_path(&self.path)
+                .with_offset(offset));
+        };
+        Ok(())
+    }
+
+    fn read_at(&mut self, offset: u64, length: usize) -> Result<Vec<u8>> {
+        self.seek(offset)?;
+        let mut content: Vec<u8> = Vec::new();
+        if let Err(err) = (&mut self.file).take(length as u64).read_to_end(&mut content) {
+            return Err(Error::io(Code::FileReadError, err)
+                .with_path(&self.path)
+                .with_offset(offset));
+        };
+        Ok(content)
+    }
+
+    fn write_at(&mut self, offset: u64, content: &[u8]) -> Result<()> {
+        self.seek(offset)?;
+        if let Err(err) = self.file.write_all(content) {
+            return Err(Error::io(Code::FileWriteError, err)
+                .with_path(&self.path)
+                .with_offset(offset));
+        };
+        Ok(())
+    }
+
+    fn deserde_at<D: DeserializeOwned>(&mut self, offset: u64, length: usize) -> Result<D> {
+        let content = self.read_at(offset, length)?;
+        bincode::deserialize(&content).map_err(|err| {
+            Error::bincode(Code::DeserdeError, err)
+                .with_path(&self.path)
+                .with_offset(offset)
+        })
+    }
+
+    fn get_file_header(&mut self) -> Result<FileHeader> {
+        self.deserde_at(0, *FILE_HEADER_LENGTH)
+    }
+
+    fn update_file_header(&mut self, file_header: &FileHeader) -> Result<()> {
+        let file_header_vec = to_vec(file_header)?;
+        self.write_at(0, &file_header_vec)
+    }
+
+    fn get_file_size(&self) -> Result<usize> {
+        match self.file.metadata() {
+            Ok(metadata) => Ok(metadata.len() as usize),
+            Err(err) => {
+                Err(Error::io(Code::FileMetadataError, err).with_path(&self.path))
+            }
+        }
+    }
+
+    fn set_len(&self, length: usize) -> Result<()> {
+        self.file.set_len(length as u64).map_err(|err| {
+            Error::io(Code::FileWriteError, err)
+                .with_path(&self.path)
+                .with_offset(length as u64)
+        })
+    }
+}
+
+impl<T> PersistentDeque<T> {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<PersistentDeque<T>> {
+        let f = match fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(path.as_ref()) {
+            Ok(f) => f,
+            Err(err) => {
+                return Err(Error::io(Code::OpenFileError, err).with_path(path));
+            }
+        };
+        let mut deque = PersistentDeque{
+            file: f,
+            path: path.as_ref().to_path_buf(),
+            marker: PhantomData
+        };
+        if deque.get_file_size()? == 0 {
+            /*
+            ** 文件内容为空, 需要添加文件头
+            */
+            deque.update_file_header(&FileHeader::new(*FILE_HEADER_LENGTH, *FILE_HEADER_LENGTH, 0))?;
+        }
+        Ok(deque)
+    }
+}
+
+/*
+** 先进先出队列: 从尾部写入, 从头部读取
+*/
+pub struct PersistentQueue<T> {
+    deque: PersistentDeque<T>
+}
+
+impl<T: Serialize + DeserializeOwned> PersistentQueue<T> {
+    pub fn push(&mut self, value: &T) -> Result<()> {
+        self.deque.push_back(value)
+    }
+
+    pub fn pop(&mut self) -> Result<Option<T>> {
+        self.deque.pop_front()
+    }
+
+    pub fn peek(&mut self) -> Result<Option<T>> {
+        self.deque.front()
+    }
+
+    pub fn len(&mut self) -> Result<u64> {
+        self.deque.len()
+    }
+
+    pub fn is_empty(&mut self) -> Result<bool> {
+        self.deque.is_empty()
+    }
+
+    pub fn sync(&self) -> Result<()> {
+        self.deque.sync()
+    }
+
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<PersistentQueue<T>> {
+        Ok(PersistentQueue{
+            deque: PersistentDeque::new(path)?
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::multifile::run_test::TestDir;
+    use crate::multifile::delete::stack::PersistentStack;
+
+    #[test]
+    fn persistent_collections_test() {
+        let dir = TestDir::new("persistent_collections_test");
+        /*
+        ** 栈
+        */
+        let mut stack: PersistentStack<String> = PersistentStack::new(dir.join("stack")).unwrap();
+        stack.push(&String::from("a")).unwrap();
+        stack.push(&String::from("bc")).unwrap();
+        assert_eq!(stack.peek().unwrap(), Some(String::from("bc")));
+        assert_eq!(stack.pop().unwrap(), Some(String::from("bc")));
+        assert_eq!(stack.pop().unwrap(), Some(String::from("a")));
+        assert!(stack.is_empty().unwrap());
+        /*
+        ** 双端队列, 重新打开之后内容不变
+        */
+        let mut deque: PersistentDeque<u64> = PersistentDeque::new(dir.join("deque")).unwrap();
+        for i in 0..10 {
+            deque.push_back(&i).unwrap();
+            deque.push_front(&(100 + i)).unwrap();
+        }
+        drop(deque);
+        let mut deque: PersistentDeque<u64> = PersistentDeque::new(dir.join("deque")).unwrap();
+        assert_eq!(deque.len().unwrap(), 20);
+        assert_eq!(deque.front().unwrap(), Some(109));
+        assert_eq!(deque.back().unwrap(), Some(9));
+        assert_eq!(deque.pop_back().unwrap(), Some(9));
+        for i in (0..10).rev() {
+            assert_eq!(deque.pop_front().unwrap(), Some(100 + i));
+        }
+        for i in 0..9 {
+            assert_eq!(deque.pop_front().unwrap(), Some(i));
+        }
+        assert_eq!(deque.pop_front().unwrap(), None);
+        assert_eq!(fs::metadata(dir.join("deque")).unwrap().len() as usize, *FILE_HEADER_LENGTH);
+        /*
+        ** 队列, 出队之后空间被回收
+        */
+        let mut queue: PersistentQueue<Vec<u8>> = PersistentQueue::new(dir.join("queue")).unwrap();
+        for i in 0..100u8 {
+            queue.push(&vec![i; 100]).unwrap();
+        }
+        for i in 0..90u8 {
+            assert_eq!(queue.pop().unwrap(), Some(vec![i; 100]));
+        }
+        assert!(fs::metadata(dir.join("queue")).unwrap().len() < 100 * 50);
+        assert_eq!(queue.len().unwrap(), 10);
+        assert_eq!(queue.peek().unwrap(), Some(vec![90; 100]));
+    }
+}
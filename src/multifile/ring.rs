@@ -0,0 +1,240 @@
+/*
+** 固定容量的环形 Fixed
+**  第 0 个块为超级块, 业务header中保存容量以及最旧 / 下一个序号
+**  序号 seq 的记录保存在第 1 + seq % capacity 个块中, 写满之后 new_block 覆盖最旧的块
+**  读取方按序号追踪, 已经被覆盖的序号返回 None
+*/
+use crate::{Result, Error, Code};
+use super::fixed::{Fixed, Block, BlockId};
+
+use serde_derive::{Serialize, Deserialize};
+
+use std::path::Path;
+
+const SUPER_ID: BlockId = 0;
+
+#[derive(Serialize, Deserialize)]
+struct SuperBlock {
+    capacity: u64,
+    /*
+    ** 最旧的仍然保留的序号
+    */
+    head: u64,
+    /*
+    ** 下一个写入的序号
+    */
+    tail: u64
+}
+
+pub struct Ring {
+    fixed: Fixed,
+    capacity: u64
+}
+
+impl Ring {
+    /*
+    ** 分配下一个序号的块, 写满时覆盖最旧的块, 返回 (序号, 块)
+    **  覆盖之前先持久化前移的 head, 中途崩溃时超级块不会指向已经被覆盖的记录
+    */
+    pub fn new_block(&mut self) -> Result<(u64, Block)> {
+        let mut meta = self.super_block()?;
+        let seq = meta.tail;
+        let id = self.slot(seq);
+        let block = if id < self.fixed.block_count()? {
+            if seq + 1 > self.capacity && meta.head < seq + 1 - self.capacity {
+                meta.head = seq + 1 - self.capacity;
+                self.set_super_block(&meta)?;
+            }
+            self.fixed.reset_block(id)?
+        } else {
+            self.fixed.new_block()?
+        };
+        meta.tail += 1;
+        if meta.tail - meta.head > self.capacity {
+            meta.head = meta.tail - self.capacity;
+        }
+        self.set_super_block(&meta)?;
+        Ok((seq, block))
+    }
+
+    /*
+    ** 获取序号对应的块, 序号还没有写入或者已经被覆盖时返回 None
+    */
+    pub fn block(&self, seq: u64) -> Result<Option<Block>> {
+        let meta = self.super_block()?;
+        if seq < meta.head || seq >= meta.tail {
+            return Ok(None);
+        }
+        Ok(Some(self.fixed.block(self.slot(seq))?))
+    }
+
+    /*
+    ** 从 seq 开始遍历到当前最新的块, seq 已经被覆盖时从最旧的块开始
+    */
+    pub fn iter_from(&self, seq: u64) -> Result<Iter<'_>> {
+        let meta = self.super_block()?;
+        Ok(Iter{
+            ring: self,
+            seq: seq.max(meta.head),
+            end: meta.tail
+        })
+    }
+
+    /*
+    ** 最旧的仍然保留的序号
+    */
+    pub fn first_seq(&self) -> Result<u64> {
+        Ok(self.super_block()?.head)
+    }
+
+    /*
+    ** 下一个写入的序号
+    */
+    pub fn next_seq(&self) -> Result<u64> {
+        Ok(self.super_block()?.tail)
+    }
+
+    pub fn len(&self) -> Result<u64> {
+        let meta = self.super_block()?;
+        Ok(meta.tail - meta.head)
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    pub fn fixed(&self) -> &Fixed {
+        &self.fixed
+    }
+
+    pub fn sync(&self) -> Result<()> {
+        self.fixed.sync()
+    }
+}
+
+impl Ring {
+    pub fn new<P: AsRef<Path>>(name: &str, fixed_size: usize, capacity: u64, path: P) -> Result<Ring> {
+        check_capacity(capacity)?;
+        Ring::with_fixed(name, Fixed::new(name, fixed_size, path)?, capacity)
+    }
+
+    /*
+    ** 使用已经打开的 Fixed 创建 / 打开环, 用于 MultiFile::open_ring
+    */
+    pub(crate) fn with_fixed(name: &str, mut fixed: Fixed, capacity: u64) -> Result<Ring> {
+        check_capacity(capacity)?;
+        if fixed.block_count()? == 0 {
+            fixed.new_block()?.update_header(SuperBlock{
+                capacity,
+                head: 0,
+                tail: 0
+            })?;
+        }
+        let ring = Ring::from_fixed(fixed)?;
+        if ring.capacity != capacity {
+            return Err(Error::new(Code::MismatchError)
+                .with_message(format!("ring capacity is {}, but {} was created with {}", capacity, name, ring.capacity)));
+        }
+        Ok(ring)
+    }
+
+    /*
+    ** 打开已经存在的环, 容量从超级块中读取
+    */
+    pub fn open<P: AsRef<Path>>(name: &str, path: P) -> Result<Ring> {
+        Ring::from_fixed(Fixed::open(name, path)?)
+    }
+
+    fn from_fixed(fixed: Fixed) -> Result<Ring> {
+        let meta: SuperBlock = fixed.block(SUPER_ID)?.header()?;
+        Ok(Ring{
+            fixed,
+            capacity: meta.capacity
+        })
+    }
+
+    fn slot(&self, seq: u64) -> BlockId {
+        1 + seq % self.capacity
+    }
+
+    fn super_block(&self) -> Result<SuperBlock> {
+        self.fixed.block(SUPER_ID)?.header()
+    }
+
+    fn set_super_block(&self, meta: &SuperBlock) -> Result<()> {
+        self.fixed.block(SUPER_ID)?.update_header(meta)
+    }
+}
+
+pub(crate) fn check_capacity(capacity: u64) -> Result<()> {
+    if capacity == 0 {
+        return Err(Error::new(Code::LimitError).with_message("ring capacity must be greater than 0"));
+    }
+    Ok(())
+}
+
+pub struct Iter<'a> {
+    ring: &'a Ring,
+    seq: u64,
+    end: u64
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = Result<(u64, Block)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.seq >= self.end {
+            return None;
+        }
+        let seq = self.seq;
+        self.seq += 1;
+        Some(self.ring.fixed.block(self.ring.slot(seq)).map(|block| (seq, block)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::multifile::run_test::TestDir;
+
+    #[test]
+    fn ring_test() {
+        let dir = TestDir::new("ring_test");
+        let mut ring = Ring::new("metric", 32, 4, &dir).unwrap();
+        for i in 0..10u64 {
+            let (seq, mut block) = ring.new_block().unwrap();
+            assert_eq!(seq, i);
+            block.update_header(i * 10).unwrap();
+        }
+        /*
+        ** 只保留最新的 4 个块, 文件不再增长
+        */
+        assert_eq!(ring.fixed().block_count().unwrap(), 5);
+        assert_eq!(ring.first_seq().unwrap(), 6);
+        assert!(ring.block(5).unwrap().is_none());
+        assert!(ring.block(10).unwrap().is_none());
+        drop(ring);
+        let ring = Ring::open("metric", &dir).unwrap();
+        assert_eq!(ring.capacity(), 4);
+        let values: Vec<(u64, u64)> = ring.iter_from(0).unwrap()
+            .map(|r| r.unwrap())
+            .map(|(seq, mut block)| (seq, block.header().unwrap()))
+            .collect();
+        assert_eq!(values, vec![(6, 60), (7, 70), (8, 80), (9, 90)]);
+        assert!(matches!(Ring::new("metric", 32, 8, &dir), Err(err) if err.code() == Code::MismatchError));
+        /*
+        ** 覆盖失败时, 前移的 head 已经持久化, 不会读到被破坏的记录
+        */
+        let mut ring = Ring::open("metric", &dir).unwrap();
+        ring.fixed.free_block(ring.slot(10)).unwrap();
+        assert!(ring.new_block().is_err());
+        drop(ring);
+        let ring = Ring::open("metric", &dir).unwrap();
+        assert_eq!(ring.first_seq().unwrap(), 7);
+        assert_eq!(ring.next_seq().unwrap(), 10);
+    }
+}
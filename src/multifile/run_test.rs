@@ -0,0 +1,48 @@
+/*
+** 测试使用的目录
+**  每次创建新的 run_test/{name}_{进程号}_{序号}, 同时执行的测试 (包括多个 cargo test 进程) 之间不会冲突
+**  离开作用域时删除, 测试 panic 时同样删除
+*/
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) struct TestDir {
+    path: PathBuf
+}
+
+impl TestDir {
+    pub(crate) fn new(name: &str) -> TestDir {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("run_test")
+            .join(format!("{}_{}_{}", name, std::process::id(), NEXT.fetch_add(1, Ordering::SeqCst)));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TestDir{
+            path
+        }
+    }
+}
+
+impl Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for TestDir {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,373 @@
+/*
+** 条带化的 Fixed
+**  一个逻辑上的 Fixed 由多个数据文件 (条带) 组成, 每个条带最多容纳 stripe_slots 个块
+**  条带文件名为 {name}.0000, {name}.0001 ..., 最后一个条带写满之后创建新的条带
+**  所有条带共享一个删除记录 {name}_delete.rd, 位置的 path 为条带文件名, 释放的块可以被任意条带重新使用; 条带自身没有删除记录
+**  通过 MultiFile::open_striped 打开时, 条带的扩展计入目录配额
+**  块序号 = 条带序号 * stripe_slots + 条带中的块序号
+**  返回的 StripedBlock::id 为全局块序号, 内部的 Block::id 是条带中的块序号, 不能持久化
+*/
+use crate::{Result, Error, Code};
+use super::delete::stack;
+use super::fixed::{self, Fixed, Block, BlockId};
+use super::limit::{DirQuota, Limits};
+
+use serde_derive::{Serialize, Deserialize};
+
+use std::fs;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+
+/*
+** 条带信息, 保存在 {name}_stripe.rd 中
+*/
+#[derive(Serialize, Deserialize)]
+struct StripeHeader {
+    fixed_size: usize,
+    stripe_slots: u64
+}
+
+pub struct Striped {
+    name: String,
+    dir: PathBuf,
+    fixed_size: usize,
+    stripe_slots: u64,
+    stripes: Vec<Fixed>,
+    /*
+    ** 条带文件名 => 条带序号
+    */
+    stripe_index: HashMap<String, usize>,
+    delete: stack::Delete,
+    /*
+    ** 目录配额以及目录大小上限, 应用到所有条带
+    */
+    quota: Option<(DirQuota, Option<u64>)>
+}
+
+/*
+** 条带中的块, id() 返回全局块序号, 其它操作通过 Deref 使用条带中的 Block
+*/
+pub struct StripedBlock {
+    id: BlockId,
+    block: Block
+}
+
+impl StripedBlock {
+    /*
+    ** 全局块序号, 用于 Striped::block / Striped::free_block
+    */
+    pub fn id(&self) -> BlockId {
+        self.id
+    }
+
+    /*
+    ** 条带中的块序号
+    */
+    pub fn local_id(&self) -> BlockId {
+        self.block.id()
+    }
+
+    pub fn into_inner(self) -> Block {
+        self.block
+    }
+}
+
+impl Deref for StripedBlock {
+    type Target = Block;
+
+    fn deref(&self) -> &Block {
+        &self.block
+    }
+}
+
+impl DerefMut for StripedBlock {
+    fn deref_mut(&mut self) -> &mut Block {
+        &mut self.block
+    }
+}
+
+/*
+** 使用 name 拼接条带文件名
+*/
+pub fn stripe_file_name(name: &str, index: usize) -> String {
+    format!("{}.{:04}", name, index)
+}
+
+/*
+** 使用 name 拼接 stripe record name
+*/
+pub(crate) fn stripe_record_name(name: &str) -> String {
+    let mut stripe_record_name = String::new();
+    stripe_record_name.push_str(name);
+    stripe_record_name.push_str("_stripe.rd");
+    stripe_record_name
+}
+
+impl Striped {
+    /*
+    ** 创建一个块
+    **  优先使用共享删除记录中的位置, 不存在时在最后一个条带中分配, 写满时创建新的条带
+    **  重新使用位置失败时, 位置放回共享删除记录
+    */
+    pub fn new_block(&mut self) -> Result<StripedBlock> {
+        if let Some(pos) = self.delete.pop()? {
+            let reclaimed = match self.stripe_index.get(&pos.path).cloned() {
+                Some(index) => self.stripes[index].reclaim_block(pos.start_pos).map(|block| (index, block)),
+                None => {
+                    Err(Error::new(Code::ForeignPathError)
+                        .with_message(format!("{} is not a stripe of {}", pos.path, self.name))
+                        .with_path(self.dir.join(fixed::delete_record_name(&self.name)))
+                        .with_offset(pos.start_pos as u64))
+                }
+            };
+            return match reclaimed {
+                Ok((index, block)) => Ok(self.wrap(index, block)),
+                Err(err) => {
+                    self.delete.push(pos)?;
+                    Err(err)
+                }
+            };
+        }
+        let last_full = match self.stripes.last() {
+            Some(stripe) => stripe.block_count()? >= self.stripe_slots,
+            None => true
+        };
+        if last_full {
+            self.add_stripe()?;
+        }
+        let index = self.stripes.len() - 1;
+        let block = self.stripes[index].new_block()?;
+        Ok(self.wrap(index, block))
+    }
+
+    /*
+    ** 获取已经存在的块, 块的 path 为所在条带的数据文件
+    */
+    pub fn block(&self, id: BlockId) -> Result<StripedBlock> {
+        let (index, local) = self.locate(id)?;
+        Ok(self.wrap(index, self.stripes[index].block(local)?))
+    }
+
+    /*
+    ** 释放块, 位置放入共享删除记录
+    */
+    pub fn free_block(&mut self, id: BlockId) -> Result<()> {
+        let (index, local) = self.locate(id)?;
+        let mut pos = self.stripes[index].retire_block(local)?;
+        pos.path = stripe_file_name(&self.name, index);
+        self.delete.push(pos)
+    }
+
+    /*
+    ** 块所在的条带数据文件以及条带中的块序号
+    */
+    pub fn locate_block(&self, id: BlockId) -> Result<(&str, BlockId)> {
+        let (index, local) = self.locate(id)?;
+        Ok((self.stripes[index].path(), local))
+    }
+
+    /*
+    ** 所有条带中块的数量 (包括已删除的块)
+    */
+    pub fn block_count(&self) -> Result<u64> {
+        let mut count = 0;
+        for stripe in self.stripes.iter() {
+            count += stripe.block_count()?;
+        }
+        Ok(count)
+    }
+
+    pub fn stripe_count(&self) -> usize {
+        self.stripes.len()
+    }
+
+    pub fn stripe_slots(&self) -> u64 {
+        self.stripe_slots
+    }
+
+    pub fn fixed_size(&self) -> usize {
+        self.fixed_size
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /*
+    ** 将所有条带以及共享删除记录刷到磁盘
+    */
+    pub fn sync(&self) -> Result<()> {
+        for stripe in self.stripes.iter() {
+            stripe.sync()?;
+        }
+        self.delete.sync()
+    }
+}
+
+impl Striped {
+    /*
+    ** max_stripe_bytes: 单个条带数据文件的最大字节数, 创建之后记录在条带信息中, 打开时必须一致
+    */
+    pub fn new<P: AsRef<Path>>(name: &str, fixed_size: usize, max_stripe_bytes: u64, path: P) -> Result<Striped> {
+        let stripe_slots = fixed::slots_within(fixed_size, 0, max_stripe_bytes);
+        if stripe_slots == 0 {
+            return Err(Error::new(Code::LimitError)
+                .with_message(format!("stripe size {} can not hold a block of {} bytes", max_stripe_bytes, fixed_size))
+                .with_path(path.as_ref().join(name)));
+        }
+        let record_path = path.as_ref().join(stripe_record_name(name));
+        if !record_path.exists() {
+            let header_vec = bincode::serialize(&StripeHeader{
+                fixed_size,
+                stripe_slots
+            }).map_err(|err| Error::bincode(Code::SerdeError, err))?;
+            if let Err(err) = fs::write(&record_path, header_vec) {
+                return Err(Error::io(Code::FileWriteError, err).with_path(&record_path));
+            }
+        }
+        let striped = Striped::open(name, path)?;
+        if striped.fixed_size != fixed_size || striped.stripe_slots != stripe_slots {
+            return Err(Error::new(Code::MismatchError)
+                .with_message(format!("stripe is {} slots of {} bytes, but {} was created with {} slots of {} bytes",
+                    stripe_slots, fixed_size, name, striped.stripe_slots, striped.fixed_size))
+                .with_path(record_path));
+        }
+        Ok(striped)
+    }
+
+    /*
+    ** 打开已经存在的条带化 Fixed, 条带信息从 {name}_stripe.rd 中读取
+    */
+    pub fn open<P: AsRef<Path>>(name: &str, path: P) -> Result<Striped> {
+        let record_path = path.as_ref().join(stripe_record_name(name));
+        let content = match fs::read(&record_path) {
+            Ok(content) => content,
+            Err(err) => {
+                return Err(Error::io(Code::OpenFileError, err).with_path(&record_path));
+            }
+        };
+        let header: StripeHeader = bincode::deserialize(&content).map_err(|err| {
+            Error::bincode(Code::DeserdeError, err).with_path(&record_path).with_offset(0)
+        })?;
+        let mut striped = Striped{
+            name: name.to_string(),
+            dir: path.as_ref().to_path_buf(),
+            fixed_size: header.fixed_size,
+            stripe_slots: header.stripe_slots,
+            stripes: Vec::new(),
+            stripe_index: HashMap::new(),
+            delete: stack::Delete::new(path.as_ref().join(fixed::delete_record_name(name)))?,
+            quota: None
+        };
+        while striped.dir.join(stripe_file_name(name, striped.stripes.len())).exists() {
+            striped.add_stripe()?;
+        }
+        Ok(striped)
+    }
+
+    /*
+    ** 条带的扩展计入目录配额, 包括已经打开的条带
+    */
+    pub(crate) fn set_dir_quota(&mut self, quota: DirQuota, max_dir_bytes: Option<u64>) -> Result<()> {
+        for stripe in self.stripes.iter_mut() {
+            apply_quota(stripe, &quota, max_dir_bytes)?;
+        }
+        self.quota = Some((quota, max_dir_bytes));
+        Ok(())
+    }
+
+    fn add_stripe(&mut self) -> Result<()> {
+        let index = self.stripes.len();
+        let file_name = stripe_file_name(&self.name, index);
+        let mut stripe = Fixed::with_external_free(&file_name, self.fixed_size, &self.dir)?;
+        if let Some((quota, max_dir_bytes)) = &self.quota {
+            apply_quota(&mut stripe, quota, *max_dir_bytes)?;
+        }
+        self.stripes.push(stripe);
+        self.stripe_index.insert(file_name, index);
+        Ok(())
+    }
+
+    fn wrap(&self, index: usize, block: Block) -> StripedBlock {
+        StripedBlock{
+            id: index as BlockId * self.stripe_slots + block.id(),
+            block
+        }
+    }
+
+    fn locate(&self, id: BlockId) -> Result<(usize, BlockId)> {
+        let index = (id / self.stripe_slots) as usize;
+        if index >= self.stripes.len() {
+            return Err(Error::new(Code::NotFoundError)
+                .with_message(format!("block {} is out of range", id))
+                .with_path(self.dir.join(&self.name)));
+        }
+        Ok((index, id % self.stripe_slots))
+    }
+}
+
+fn apply_quota(stripe: &mut Fixed, quota: &DirQuota, max_dir_bytes: Option<u64>) -> Result<()> {
+    stripe.set_dir_quota(quota.clone());
+    let limits = match max_dir_bytes {
+        Some(max_dir_bytes) => Limits::new().with_max_dir_bytes(max_dir_bytes),
+        None => Limits::new()
+    };
+    stripe.set_limits(limits)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::multifile::run_test::TestDir;
+
+    #[test]
+    fn striped_test() {
+        let root = TestDir::new("striped_test");
+        let max_stripe_bytes = 1024;
+        let multi_file = crate::multifile::MultiFile::new(root.to_str().unwrap().to_string());
+        let mut striped = multi_file.open_striped("test.db", "user", 64, max_stripe_bytes).unwrap();
+        let dir = root.join("test.db");
+        let stripe_slots = striped.stripe_slots();
+        let mut ids: Vec<BlockId> = Vec::new();
+        for i in 0..stripe_slots * 2 + 1 {
+            let mut block = striped.new_block().unwrap();
+            block.update_header(i).unwrap();
+            ids.push(block.id());
+        }
+        assert_eq!(striped.stripe_count(), 3);
+        for stripe in 0..3 {
+            assert!(fs::metadata(dir.join(stripe_file_name("user", stripe))).unwrap().len() <= max_stripe_bytes);
+        }
+        /*
+        ** 块指向所在条带的数据文件
+        */
+        let mut block = striped.block(ids[stripe_slots as usize]).unwrap();
+        assert!(block.path().ends_with("user.0001"));
+        assert_eq!((block.id(), block.local_id()), (stripe_slots, 0));
+        assert_eq!(block.header::<u64>().unwrap(), stripe_slots);
+        /*
+        ** 共享删除记录: 第一个条带中释放的块在重新打开之后被使用
+        */
+        striped.free_block(ids[1]).unwrap();
+        assert!(striped.free_block(ids[1]).err().unwrap().is_invalid_free());
+        drop(striped);
+        let mut striped = Striped::open("user", &dir).unwrap();
+        let block = striped.new_block().unwrap();
+        assert_eq!(block.id(), ids[1]);
+        assert!(block.path().ends_with("user.0000"));
+        assert_eq!(striped.locate_block(ids[ids.len() - 1]).unwrap().1, 0);
+        assert_eq!(striped.block_count().unwrap(), stripe_slots * 2 + 1);
+        assert_eq!(Striped::new("user", 64, max_stripe_bytes * 2, &dir).err().unwrap().code(), Code::MismatchError);
+        assert!(striped.block(stripe_slots * 3).err().unwrap().is_not_found());
+        /*
+        ** 重新使用位置失败时位置仍然在共享删除记录中
+        */
+        striped.free_block(ids[2]).unwrap();
+        let mut foreign = stack::Delete::new(dir.join(fixed::delete_record_name("user"))).unwrap();
+        foreign.push(stack::Pos::new("other".to_string(), 0, 64)).unwrap();
+        assert_eq!(striped.new_block().err().unwrap().code(), Code::ForeignPathError);
+        assert_eq!(foreign.pop().unwrap().unwrap().path, "other");
+        assert_eq!(striped.new_block().unwrap().id(), ids[2]);
+    }
+}
@@ -0,0 +1,243 @@
+/*
+** io_uring 读写 (feature = "io_uring", 只支持 Linux)
+**  一次操作的全部写入 (数据文件, 删除记录, 文件头) 按阶段放入提交队列, 后一阶段的第一个请求带 IO_DRAIN,
+**   在之前的请求全部完成之后才开始; 同一阶段内的顺序不保证
+**  提交队列满时先提交 (不等待) 再继续放入, 未收割的请求达到队列长度时才等待完成事件, 最后一次等待全部完成
+**  部分写入的剩余内容使用 pwrite 补齐
+**  提交的内容复制到 Uring 持有的缓冲区, 出错返回时内核仍可能在读取, 缓冲区保留到这些请求完成
+**  user_data 的高 32 位为批次号, 上一批未收割的完成事件不会被当作本批的结果
+*/
+use crate::{Result, Error, Code};
+use super::pager::WriteOp;
+
+use io_uring::{opcode, squeue, types, IoUring};
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+
+const QUEUE_ENTRIES: u32 = 64;
+
+pub(crate) struct Uring {
+    ring: IoUring,
+    /*
+    ** 已经放入提交队列但还没有收割完成事件的请求数
+    */
+    pending: usize,
+    /*
+    ** 当前批次的读写缓冲区, 在 pending 归零之前不能释放
+    */
+    buffers: Vec<Vec<u8>>,
+    batch: u32
+}
+
+impl Uring {
+    pub(crate) fn new() -> Result<Uring> {
+        match IoUring::new(QUEUE_ENTRIES) {
+            Ok(ring) => Ok(Uring{
+                ring,
+                pending: 0,
+                buffers: Vec::new(),
+                batch: 0
+            }),
+            Err(err) => Err(Error::io(Code::NotImplement, err).with_message("io_uring is not available"))
+        }
+    }
+
+    /*
+    ** 按阶段写入, file 为数据文件 (WriteOp::file 为 None 时写入该文件)
+    */
+    pub(crate) fn submit(&mut self, file: &fs::File, path: &str, stages: &[Vec<WriteOp>]) -> Result<()> {
+        /*
+        ** 上一批出错时留下的请求先全部完成
+        */
+        self.drain(path, &mut [])?;
+        self.batch = self.batch.wrapping_add(1);
+        let writes: Vec<(&WriteOp, bool)> = stages.iter()
+            .filter(|stage| !stage.is_empty())
+            .enumerate()
+            .flat_map(|(i, stage)| stage.iter().enumerate().map(move |(j, write)| (write, i > 0 && j == 0)))
+            .collect();
+        self.buffers = writes.iter().map(|(write, _)| write.content.to_vec()).collect();
+        let mut results: Vec<Option<i32>> = vec![None; writes.len()];
+        for (i, (write, barrier)) in writes.iter().enumerate() {
+            let fd = match write.file {
+                Some((other, _)) => types::Fd(other.as_raw_fd()),
+                None => types::Fd(file.as_raw_fd())
+            };
+            let buffer = &self.buffers[i];
+            let mut entry = opcode::Write::new(fd, buffer.as_ptr(), buffer.len() as u32)
+                .offset(write.offset)
+                .build()
+                .user_data((self.batch as u64) << 32 | i as u64);
+            if *barrier {
+                entry = entry.flags(squeue::Flags::IO_DRAIN);
+            }
+            self.push(&entry, path, &mut results)?;
+        }
+        self.drain(path, &mut results)?;
+        for (i, result) in results.into_iter().enumerate() {
+            let write = writes[i].0;
+            let (target, target_path) = match write.file {
+                Some((other, other_path)) => (other, other_path.to_string_lossy().to_string()),
+                None => (file, path.to_string())
+            };
+            let written = check(result, Code::FileWriteError, &target_path, write.offset)?;
+            if written < write.content.len() {
+                target.write_all_at(&write.content[written..], write.offset + written as u64).map_err(|err| {
+                    Error::io(Code::FileWriteError, err)
+                        .with_path(&target_path)
+                        .with_offset(write.offset)
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /*
+    ** 读取 [offset, offset + length), 到达文件尾部时返回的内容变短
+    */
+    pub(crate) fn read_at(&mut self, file: &fs::File, path: &str, offset: u64, length: usize) -> Result<Vec<u8>> {
+        self.drain(path, &mut [])?;
+        self.batch = self.batch.wrapping_add(1);
+        self.buffers = vec![vec![0; length]];
+        let entry = opcode::Read::new(types::Fd(file.as_raw_fd()), self.buffers[0].as_mut_ptr(), length as u32)
+            .offset(offset)
+            .build()
+            .user_data((self.batch as u64) << 32);
+        let mut results: Vec<Option<i32>> = vec![None];
+        self.push(&entry, path, &mut results)?;
+        self.wait(path, &mut results, 0)?;
+        let mut content = std::mem::take(&mut self.buffers).pop().unwrap_or_default();
+        let mut read = check(results[0], Code::FileReadError, path, offset)?;
+        while read > 0 && read < length {
+            match file.read_at(&mut content[read..], offset + read as u64) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    return Err(Error::io(Code::FileReadError, err)
+                        .with_path(path)
+                        .with_offset(offset));
+                }
+            }
+        }
+        content.truncate(read);
+        Ok(content)
+    }
+
+    /*
+    ** 放入提交队列, 队列满时先提交; 未收割的请求达到队列长度时等待一部分完成
+    */
+    fn push(&mut self, entry: &squeue::Entry, path: &str, results: &mut [Option<i32>]) -> Result<()> {
+        if self.pending >= QUEUE_ENTRIES as usize {
+            self.wait(path, results, QUEUE_ENTRIES as usize - 1)?;
+        }
+        while unsafe { self.ring.submission().push(entry) }.is_err() {
+            if let Err(err) = self.ring.submit() {
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(Error::io(Code::FileWriteError, err).with_path(path));
+                }
+            }
+        }
+        self.pending += 1;
+        Ok(())
+    }
+
+    /*
+    ** 提交并等待, 直到未收割的请求不超过 until; 当前批次的结果放入 results, 其它批次的结果丢弃
+    **  出错时 pending 以及缓冲区保持不变, 下一次调用继续等待
+    */
+    fn wait(&mut self, path: &str, results: &mut [Option<i32>], until: usize) -> Result<()> {
+        while self.pending > until {
+            if let Err(err) = self.ring.submit_and_wait(1) {
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(Error::io(Code::FileWriteError, err).with_path(path));
+            }
+            for cqe in self.ring.completion() {
+                self.pending -= 1;
+                let user_data = cqe.user_data();
+                let i = (user_data & u32::MAX as u64) as usize;
+                if (user_data >> 32) as u32 == self.batch && i < results.len() {
+                    results[i] = Some(cqe.result());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn drain(&mut self, path: &str, results: &mut [Option<i32>]) -> Result<()> {
+        self.wait(path, results, 0)?;
+        self.buffers.clear();
+        Ok(())
+    }
+}
+
+/*
+** 完成事件的结果 => 读写的字节数
+*/
+fn check(result: Option<i32>, code: Code, path: &str, offset: u64) -> Result<usize> {
+    match result {
+        Some(result) if result < 0 => {
+            Err(Error::io(code, io::Error::from_raw_os_error(-result))
+                .with_path(path)
+                .with_offset(offset))
+        },
+        Some(result) => Ok(result as usize),
+        None => {
+            Err(Error::new(code)
+                .with_message("io_uring lost a completion")
+                .with_path(path)
+                .with_offset(offset))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::multifile::run_test::TestDir;
+
+    #[test]
+    fn uring_test() {
+        let mut uring = match Uring::new() {
+            Ok(uring) => uring,
+            /*
+            ** 内核不支持 / 被禁止使用 io_uring
+            */
+            Err(_) => return
+        };
+        let dir = TestDir::new("uring_test");
+        let path = dir.join("data");
+        let file = fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path).unwrap();
+        let other_path = dir.join("other");
+        let other = fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&other_path).unwrap();
+        /*
+        ** 超过队列长度的批量不等待地连续提交; 第二阶段在第一阶段完成之后写入另一个文件
+        */
+        let contents: Vec<Vec<u8>> = (0..QUEUE_ENTRIES as usize * 3 + 3).map(|i| vec![i as u8; 8]).collect();
+        let data: Vec<WriteOp> = contents.iter().enumerate()
+            .map(|(i, content)| WriteOp::data((i * 8) as u64, content))
+            .collect();
+        let header = WriteOp::to(&other, &other_path, 0, &[7u8; 4]);
+        uring.submit(&file, "data", &[data, vec![header]]).unwrap();
+        let content = fs::read(&path).unwrap();
+        assert_eq!(content.len(), contents.len() * 8);
+        assert_eq!(content.chunks(8).map(|c| c.to_vec()).collect::<Vec<_>>(), contents);
+        assert_eq!(fs::read(&other_path).unwrap(), vec![7u8; 4]);
+        assert_eq!(uring.pending, 0);
+        assert_eq!(uring.read_at(&file, "data", 8, 8).unwrap(), contents[1]);
+        assert_eq!(uring.read_at(&file, "data", content.len() as u64 - 4, 8).unwrap().len(), 4);
+        /*
+        ** 写入失败之后, 下一批的结果不受影响
+        */
+        let readonly = fs::File::open(&path).unwrap();
+        let write = WriteOp::data(0, &[1u8; 4]);
+        assert_eq!(uring.submit(&readonly, "data", &[vec![write]]).err().unwrap().code(), Code::FileWriteError);
+        uring.submit(&file, "data", &[vec![WriteOp::data(0, &[9u8; 4])]]).unwrap();
+        assert_eq!(&fs::read(&path).unwrap()[..4], &[9u8; 4]);
+    }
+}